@@ -0,0 +1,494 @@
+use crate::cli::read_image;
+use mage::codec;
+use mage::{DecodeOptions, EncodeOptions, MageError, MageResult};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+// This module converts many files at once, spread over a pool of threads.
+// The inputs can be files, directories, or glob patterns, and each one is
+// written to an output directory, or to a path made from a template.
+
+/// Everything needed to convert a batch of files
+pub struct Batch {
+    pub inputs: Vec<String>,
+    /// Either a directory, or a template containing `{stem}` and friends
+    pub output: String,
+    pub format: Option<String>,
+    pub input_format: Option<String>,
+    pub exposure: Option<f32>,
+    /// Whether or not to look inside the subdirectories of input directories
+    pub recursive: bool,
+    /// Whether or not to skip inputs whose output is newer than them
+    pub skip_up_to_date: bool,
+    /// How many files to convert at the same time
+    pub jobs: Option<usize>,
+    /// How to decode the inputs
+    pub options: DecodeOptions,
+}
+
+/// Check if some arguments to convert need to be handled as a batch
+pub fn is_batch(inputs: &[String], output: &str) -> bool {
+    inputs.len() > 1
+        || inputs
+            .iter()
+            .any(|input| has_wildcards(input) || Path::new(input).is_dir())
+        || is_template(output)
+        || output.ends_with('/')
+        || Path::new(output).is_dir()
+}
+
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn is_template(output: &str) -> bool {
+    output.contains('{')
+}
+
+/// Check if a name matches a pattern, with `*`, `?` and `[...]` wildcards
+///
+/// Like in a shell, wildcards don't match a leading `.`.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to go back to when what follows the last `*` stops matching
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(&pattern[p..], name[n]).map(|len| p + len),
+            Some(&c) if c == name[n] => Some(p + 1),
+            _ => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star_p, star_n))) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match a character against a class like `[a-z]` or `[!abc]`
+///
+/// This returns the length of the class if it matches.
+fn match_class(class: &[char], c: char) -> Option<usize> {
+    let negated = class.get(1) == Some(&'!');
+    let start = if negated { 2 } else { 1 };
+    // A `]` right at the start is part of the class
+    let end = start + 1 + class.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    let members = &class[start..end];
+    let mut found = false;
+    let mut i = 0;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == '-' {
+            found |= members[i] <= c && c <= members[i + 2];
+            i += 3;
+        } else {
+            found |= members[i] == c;
+            i += 1;
+        }
+    }
+    if found != negated {
+        Some(end + 1)
+    } else {
+        None
+    }
+}
+
+/// The entries of a directory, sorted by name
+fn sorted_entries(dir: &Path) -> io::Result<Vec<fs::DirEntry>> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+/// A file to convert, along with its path relative to where we found it
+struct Input {
+    path: PathBuf,
+    relative: PathBuf,
+}
+
+/// Find the files in a directory we know how to read
+fn walk_dir(dir: &Path, relative: &Path, recursive: bool, out: &mut Vec<Input>) -> io::Result<()> {
+    for entry in sorted_entries(dir)? {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let relative = relative.join(entry.file_name());
+        if path.is_dir() {
+            if recursive {
+                walk_dir(&path, &relative, recursive, out)?;
+            }
+            continue;
+        }
+        let readable = path
+            .to_str()
+            .and_then(codec::by_path)
+            .is_some_and(|codec| codec.decoder.is_some());
+        if readable {
+            out.push(Input { path, relative });
+        }
+    }
+    Ok(())
+}
+
+/// Find the files matching the components of a glob pattern, inside a directory
+fn walk_glob(dir: &Path, relative: &Path, parts: &[&str], out: &mut Vec<Input>) -> io::Result<()> {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    // `**` matches any number of directories, including none
+    if *part == "**" {
+        walk_glob(dir, relative, rest, out)?;
+    }
+    for entry in sorted_entries(dir)? {
+        let name = entry.file_name();
+        let path = entry.path();
+        let relative = relative.join(&name);
+        let is_dir = path.is_dir();
+        if *part == "**" {
+            if is_dir && !name.to_string_lossy().starts_with('.') {
+                walk_glob(&path, &relative, parts, out)?;
+            }
+        } else if glob_match(part, &name.to_string_lossy()) {
+            if rest.is_empty() && !is_dir {
+                out.push(Input { path, relative });
+            } else if is_dir {
+                walk_glob(&path, &relative, rest, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find every file to convert, from the inputs given on the command line
+fn find_inputs(inputs: &[String], recursive: bool) -> MageResult<Vec<Input>> {
+    let mut out = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if has_wildcards(input) {
+            // The directories before the first wildcard are where we start
+            let parts: Vec<&str> = input.split('/').collect();
+            let literal = parts.iter().take_while(|p| !has_wildcards(p)).count();
+            let mut base = parts[..literal].join("/");
+            if base.is_empty() {
+                base = if input.starts_with('/') { "/" } else { "." }.into();
+            }
+            let before = out.len();
+            walk_glob(Path::new(&base), Path::new(""), &parts[literal..], &mut out)
+                .map_err(|e| MageError::with_path(&base, e))?;
+            if out.len() == before {
+                return Err(MageError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: no files match this pattern", input),
+                )));
+            }
+        } else if path.is_dir() {
+            walk_dir(path, Path::new(""), recursive, &mut out)
+                .map_err(|e| MageError::with_path(input, e))?;
+        } else {
+            let relative = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+            out.push(Input {
+                path: path.to_path_buf(),
+                relative,
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Fill in a template for an output path, using the path of an input
+///
+/// The template can contain `{stem}`, `{name}`, `{ext}`, and `{dir}`, which
+/// is the directory of the input, relative to where we found it.
+fn fill_template(template: &str, relative: &Path) -> String {
+    let text = |s: Option<&OsStr>| {
+        s.map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let dir = relative
+        .parent()
+        .map(|p| p.to_string_lossy())
+        .unwrap_or_default();
+    template
+        .replace("{stem}", &text(relative.file_stem()))
+        .replace("{name}", &text(relative.file_name()))
+        .replace("{ext}", &text(relative.extension()))
+        .replace("{dir}", &dir)
+}
+
+/// A file to convert, and where it goes
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+/// How many of the files in a batch were converted, skipped, or failed
+pub struct Summary {
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    pub fn total(&self) -> usize {
+        self.converted + self.skipped + self.failed
+    }
+}
+
+/// What happened when converting a single file
+enum Outcome {
+    Converted,
+    /// The output was newer than the input
+    Skipped,
+    Failed(MageError),
+}
+
+/// Work out where each input is written to
+///
+/// Two inputs can't be written to the same place, since one of them would
+/// silently overwrite the other, depending on which thread finishes last.
+fn plan(batch: &Batch, inputs: Vec<Input>) -> MageResult<Vec<Job>> {
+    let outputs: Vec<PathBuf> = if is_template(&batch.output) {
+        inputs
+            .iter()
+            .map(|input| {
+                // An empty {dir} shouldn't leave an empty component behind
+                let filled = fill_template(&batch.output, &input.relative);
+                Path::new(&filled).components().collect()
+            })
+            .collect()
+    } else {
+        // Without a template, the outputs keep their name in the output directory
+        let extension = match batch.format.as_deref().and_then(codec::by_name) {
+            Some(codec) => codec.extensions[0],
+            None => {
+                return Err(MageError::UnsupportedFormat {
+                    format: None,
+                    message: "use --format to pick the format when writing to a directory".into(),
+                })
+            }
+        };
+        let dir = Path::new(&batch.output);
+        inputs
+            .iter()
+            .map(|input| dir.join(&input.relative).with_extension(extension))
+            .collect()
+    };
+    let mut seen: HashMap<&Path, &Path> = HashMap::new();
+    for (input, output) in inputs.iter().zip(&outputs) {
+        if let Some(other) = seen.insert(output, &input.path) {
+            return Err(MageError::UnsupportedFormat {
+                format: None,
+                message: format!(
+                    "{} and {} would both be written to {}",
+                    other.display(),
+                    input.path.display(),
+                    output.display()
+                ),
+            });
+        }
+    }
+    Ok(inputs
+        .into_iter()
+        .zip(outputs)
+        .map(|(input, output)| Job {
+            input: input.path,
+            output,
+        })
+        .collect())
+}
+
+/// Check if the output of a job is newer than its input
+fn is_up_to_date(job: &Job) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(&job.input), modified(&job.output)) {
+        (Some(input), Some(output)) => output >= input,
+        (_, _) => false,
+    }
+}
+
+fn convert_job(batch: &Batch, job: &Job) -> MageResult<()> {
+    let input = job.input.to_string_lossy();
+    let format = batch.input_format.as_deref();
+    let image = read_image(&input, format, &batch.options)?;
+    if let Some(dir) = job.output.parent() {
+        fs::create_dir_all(dir).map_err(|e| MageError::with_path(dir, e))?;
+    }
+    let options = EncodeOptions {
+        name: String::new(),
+        exposure: batch.exposure,
+    };
+    mage::save_with(&job.output, image, batch.format.as_deref(), &options)
+}
+
+fn convert_one(batch: &Batch, job: &Job) -> Outcome {
+    if batch.skip_up_to_date && is_up_to_date(job) {
+        return Outcome::Skipped;
+    }
+    match convert_job(batch, job) {
+        Ok(()) => Outcome::Converted,
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
+/// Run a function over each job, with a pool of threads
+///
+/// The outcomes are in the same order as the jobs.
+fn run_pool<F>(jobs: &[Job], workers: usize, work: F) -> Vec<Outcome>
+where
+    F: Fn(&Job) -> Outcome + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(Vec::with_capacity(jobs.len()));
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let job = match jobs.get(i) {
+                    Some(job) => job,
+                    None => break,
+                };
+                let outcome = work(job);
+                outcomes.lock().unwrap().push((i, outcome));
+            });
+        }
+    });
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|&(i, _)| i);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Convert every file in a batch, printing a summary of what happened
+///
+/// Files that fail to convert don't stop the others, so this only returns an
+/// error if we couldn't get started. The caller decides what to make of failures.
+pub fn convert_all(batch: Batch) -> MageResult<Summary> {
+    if batch.inputs.iter().any(|input| input == "-") || batch.output == "-" {
+        return Err(MageError::UnsupportedFormat {
+            format: None,
+            message: "stdin and stdout can only be used to convert a single file".into(),
+        });
+    }
+    let inputs = find_inputs(&batch.inputs, batch.recursive)?;
+    let jobs = plan(&batch, inputs)?;
+    let workers = batch.jobs.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let start = Instant::now();
+    let outcomes = run_pool(&jobs, workers, |job| convert_one(&batch, job));
+    let mut summary = Summary {
+        converted: 0,
+        skipped: 0,
+        failed: 0,
+    };
+    for (job, outcome) in jobs.iter().zip(&outcomes) {
+        let (input, output) = (job.input.display(), job.output.display());
+        match outcome {
+            Outcome::Converted => {
+                summary.converted += 1;
+                println!("converted {} -> {}", input, output);
+            }
+            Outcome::Skipped => {
+                summary.skipped += 1;
+                println!("skipped {}, {} is up to date", input, output);
+            }
+            Outcome::Failed(e) => {
+                summary.failed += 1;
+                eprintln!("failed {}: {}", input, e);
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{} {} in {:.2}s: {} converted, {} skipped, {} failed",
+        jobs.len(),
+        if jobs.len() == 1 { "file" } else { "files" },
+        elapsed.as_secs_f32(),
+        summary.converted,
+        summary.skipped,
+        summary.failed
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.bmp", "a.bmp"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("*.bmp", "a.png"));
+        assert!(!glob_match("*", ".hidden"));
+        assert!(glob_match("img?.[bp][!a]*", "img1.png"));
+        assert!(!glob_match("img?.[bp][!n]*", "img1.png"));
+        assert!(glob_match("[a-c]", "b"));
+        assert!(glob_match("[]x]", "]"));
+    }
+
+    #[test]
+    fn test_fill_template() {
+        let relative = Path::new("sub/photo.old.bmp");
+        assert_eq!(
+            fill_template("out/{dir}/{stem}.png", relative),
+            "out/sub/photo.old.png"
+        );
+        assert_eq!(fill_template("{name}.{ext}", relative), "photo.old.bmp.bmp");
+    }
+
+    #[test]
+    fn test_plan_collisions() {
+        let input = |path: &str| Input {
+            path: PathBuf::from(path),
+            relative: PathBuf::from(path),
+        };
+        let mut batch = Batch {
+            inputs: Vec::new(),
+            output: "out/{stem}.png".into(),
+            format: None,
+            input_format: None,
+            exposure: None,
+            recursive: false,
+            skip_up_to_date: false,
+            jobs: None,
+            options: DecodeOptions::default(),
+        };
+        let jobs = plan(&batch, vec![input("a.bmp"), input("b.bmp")]).unwrap();
+        assert_eq!(jobs[1].output, Path::new("out/b.png"));
+        // Only the extension differs between these inputs
+        assert!(plan(&batch, vec![input("a.bmp"), input("a.tga")]).is_err());
+        batch.output = "out".into();
+        batch.format = Some("png".into());
+        assert!(plan(&batch, vec![input("x/a.bmp"), input("x/a.tga")]).is_err());
+        assert!(plan(&batch, vec![input("x/a.bmp"), input("y/a.bmp")]).is_ok());
+    }
+}
@@ -1,4 +1,10 @@
-use crate::image::{Image, RGBA_BYTES, RGBA};
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder, Inspector};
+use crate::dissect::Region;
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, ImageView, RGBA_BYTES, RGBA};
+use crate::info::{dpi_from_meters, Info};
+use crate::limits::LimitError;
+use crate::validate::Issue;
 use std::convert::TryFrom;
 use std::io;
 // The structures and parsing in this module are mainly based off of the
@@ -46,24 +52,57 @@ fn write_u16_le<W: io::Write>(writer: &mut W, num: u16) -> io::Result<()> {
 #[derive(Debug)]
 pub enum BMPError {
     /// The format of the file doesn't match the specification
-    InvalidFormat(String),
+    ///
+    /// This also holds the offset of the offending bytes in the file.
+    InvalidFormat(String, usize),
     /// The format of the file is valid, but we don't support it
     ///
     /// This is necessary because we don't support esoteric formats
     /// like 8 bit or 24 bit pixels, even though they aren't invalid.
     UnsupportedFormat(String),
+    /// Reading the file failed
+    Io(io::Error),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
 }
 
 pub type BMPResult<T> = Result<T, BMPError>;
 
-fn invalid_format<T, S: Into<String>>(s: S) -> BMPResult<T> {
-    Err(BMPError::InvalidFormat(s.into()))
+impl From<BMPError> for MageError {
+    fn from(error: BMPError) -> Self {
+        match error {
+            BMPError::InvalidFormat(message, offset) => MageError::InvalidFormat {
+                format: "bmp",
+                message,
+                offset: Some(offset),
+            },
+            BMPError::UnsupportedFormat(message) => MageError::unsupported("bmp", message),
+            BMPError::Io(e) => MageError::Io(e),
+            BMPError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for BMPError {
+    fn from(error: LimitError) -> Self {
+        BMPError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(offset: usize, s: S) -> BMPResult<T> {
+    Err(BMPError::InvalidFormat(s.into(), offset))
 }
 
 fn unsupported_format<T, S: Into<String>>(s: S) -> BMPResult<T> {
     Err(BMPError::UnsupportedFormat(s.into()))
 }
 
+/// The size of the file header, which the image header follows
+const FILE_HEADER_SIZE: usize = 14;
+
+/// Where the color masks start, right after a 40 byte image header
+const COLOR_MASKS_OFFSET: usize = 54;
+
 /// This contains the data in the file header for BMP
 #[derive(Debug)]
 struct FileHeader {
@@ -153,14 +192,14 @@ struct ColorMasks {
 /// This contains the color formats that we can handle.
 #[derive(Clone, Copy, Debug)]
 enum ColorFormat {
-    RGBA,
+    Rgba,
 }
 
 impl TryFrom<ColorMasks> for ColorFormat {
     type Error = BMPError;
 
     fn try_from(mask: ColorMasks) -> Result<Self, Self::Error> {
-        let formats = [ColorFormat::RGBA];
+        let formats = [ColorFormat::Rgba];
         for &f in &formats {
             if mask == f.into() {
                 return Ok(f);
@@ -173,7 +212,7 @@ impl TryFrom<ColorMasks> for ColorFormat {
 impl From<ColorFormat> for ColorMasks {
     fn from(format: ColorFormat) -> Self {
         match format {
-            ColorFormat::RGBA => ColorMasks {
+            ColorFormat::Rgba => ColorMasks {
                 r: 0xFF_00_00_00,
                 g: 0x00_FF_00_00,
                 b: 0x00_00_FF_00,
@@ -188,35 +227,63 @@ impl From<ColorFormat> for ColorMasks {
 struct Header {
     file_header: FileHeader,
     image_header: ImageHeader,
-    format: ColorFormat,
+}
+
+/// Keeps track of the problems we've recovered from, when decoding leniently
+///
+/// When we're not lenient, these problems are errors instead.
+struct Recovery {
+    lenient: bool,
+    warnings: Vec<String>,
+}
+
+impl Recovery {
+    fn new(lenient: bool) -> Self {
+        Recovery {
+            lenient,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn tolerate(&mut self, offset: usize, message: &str) -> BMPResult<()> {
+        if !self.lenient {
+            return invalid_format(offset, message);
+        }
+        let warning = format!("{} (at byte {})", message, offset);
+        self.warnings.push(warning);
+        Ok(())
+    }
 }
 
 // This assumes we're parsing the header from the start of the slice
-fn parse_file_header(data: &[u8]) -> BMPResult<FileHeader> {
+fn parse_file_header(data: &[u8], recovery: &mut Recovery) -> BMPResult<FileHeader> {
     if data.len() < 14 {
-        return invalid_format("insufficient file header length");
+        return invalid_format(data.len(), "insufficient file header length");
     }
     if data[0] != 66 || data[1] != 77 {
-        return invalid_format("header didn't start with 'BM'");
+        return invalid_format(0, "header didn't start with 'BM'");
     }
     let size = u32_le(&data[2..]);
     if data[6] != 0 || data[7] != 0 || data[8] != 0 || data[9] != 0 {
-        return invalid_format("reserved bytes not 0");
+        recovery.tolerate(6, "reserved bytes not 0")?;
     }
     let offset = u32_le(&data[10..]);
     Ok(FileHeader { size, offset })
 }
 
 // This assumes we're parsing the header from the start of the slice
-fn parse_image_header(data: &[u8]) -> BMPResult<ImageHeader> {
+fn parse_image_header(data: &[u8], recovery: &mut Recovery) -> BMPResult<ImageHeader> {
     if data.len() < 40 {
-        return invalid_format("insufficient image header length");
+        return invalid_format(
+            FILE_HEADER_SIZE + data.len(),
+            "insufficient image header length",
+        );
     }
     let size = u32_le(data);
     let width = u32_le(&data[4..]);
     let height = i32_le(&data[8..]);
     if data[12] != 1 || data[13] != 0 {
-        return invalid_format("plane count not 1");
+        recovery.tolerate(FILE_HEADER_SIZE + 12, "plane count not 1")?;
     }
     let bit_count = u16_le(&data[14..]);
     let compression = CompressionType::from(u32_le(&data[16..]));
@@ -242,7 +309,10 @@ fn parse_image_header(data: &[u8]) -> BMPResult<ImageHeader> {
 // This assumes we're reading from the start of the slice
 fn parse_color_format(data: &[u8]) -> BMPResult<ColorFormat> {
     if data.len() < 16 {
-        return invalid_format("insufficient color mask length");
+        return invalid_format(
+            COLOR_MASKS_OFFSET + data.len(),
+            "insufficient color mask length",
+        );
     }
     let r = u32_le(data);
     let g = u32_le(&data[4..]);
@@ -251,55 +321,485 @@ fn parse_color_format(data: &[u8]) -> BMPResult<ColorFormat> {
     ColorFormat::try_from(ColorMasks { r, g, b, a })
 }
 
-fn parse_header(data: &[u8]) -> BMPResult<Header> {
-    let file_header = parse_file_header(data)?;
-    if data.len() < file_header.offset as usize {
-        return invalid_format("insufficient header length");
-    }
-    let image_header = parse_image_header(&data[14..])?;
+/// Fill a buffer with the bytes at some offset in a file
+///
+/// Running out of bytes means that the file is too short, in which case
+/// we report what we were trying to read.
+fn read_at<R: io::Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    offset: usize,
+    what: &str,
+) -> BMPResult<()> {
+    reader.read_exact(buf).or_else(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_format(offset, format!("insufficient {}", what)),
+        _ => Err(BMPError::Io(e)),
+    })
+}
+
+/// Read the headers at the start of a file, leaving the reader at the pixels
+fn read_header<R: io::Read>(reader: &mut R, recovery: &mut Recovery) -> BMPResult<Header> {
+    let mut buf = [0; COLOR_MASKS_OFFSET + 16];
+    read_at(
+        reader,
+        &mut buf[..FILE_HEADER_SIZE],
+        0,
+        "file header length",
+    )?;
+    let file_header = parse_file_header(&buf, recovery)?;
+    let image_header_data = &mut buf[FILE_HEADER_SIZE..COLOR_MASKS_OFFSET];
+    read_at(
+        reader,
+        image_header_data,
+        FILE_HEADER_SIZE,
+        "image header length",
+    )?;
+    let image_header = parse_image_header(image_header_data, recovery)?;
     if image_header.compression != CompressionType::Bitfields {
         return unsupported_format("compression type not supported");
     }
     if image_header.bit_count != 32 {
         return unsupported_format("unspported pixel format");
     }
-    let format = parse_color_format(&data[54..])?;
+    let masks_data = &mut buf[COLOR_MASKS_OFFSET..];
+    read_at(reader, masks_data, COLOR_MASKS_OFFSET, "color mask length")?;
+    // We only check that the masks are a format we can handle
+    parse_color_format(masks_data)?;
+    // Skip over the rest of the header, and anything else before the pixels
+    let header_end = buf.len() as u64;
+    let skip = match u64::from(file_header.offset).checked_sub(header_end) {
+        Some(skip) => skip,
+        None => return invalid_format(10, "pixel data starts inside the header"),
+    };
+    let mut rest = io::Read::take(&mut *reader, skip);
+    let skipped = io::copy(&mut rest, &mut io::sink()).map_err(BMPError::Io)?;
+    if skipped < skip {
+        return invalid_format(
+            (header_end + skipped) as usize,
+            "insufficient header length",
+        );
+    }
     Ok(Header {
         file_header,
         image_header,
-        format,
     })
 }
 
-pub fn parse_image(data: &[u8]) -> BMPResult<Image> {
-    let header = parse_header(data)?;
-    if data.len() < header.file_header.size as usize {
-        return invalid_format("insufficient image data");
+/// Read as many bytes as we can into a buffer, stopping early at the end
+fn read_full<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> BMPResult<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(BMPError::Io(e)),
+        }
     }
-    let height = header.image_header.height.abs() as u32;
-    let image_data = &data[header.file_header.offset as usize..];
+    Ok(read)
+}
+
+/// Decode an image from a reader, reading one row of pixels at a time
+pub fn decode<R: io::Read>(reader: R) -> BMPResult<Image> {
+    decode_with(reader, &DecodeOptions::default()).map(|(image, _)| image)
+}
+
+/// Decode an image from a reader, optionally recovering from broken files
+///
+/// When lenient, we accept some broken headers, and the rows missing from a
+/// truncated file are left transparent. This returns warnings describing
+/// what we recovered from.
+pub fn decode_with<R: io::Read>(
+    mut reader: R,
+    options: &DecodeOptions,
+) -> BMPResult<(Image, Vec<String>)> {
+    let mut recovery = Recovery::new(options.lenient);
+    let header = read_header(&mut reader, &mut recovery)?;
+    let height = header.image_header.height.unsigned_abs();
+    let width = u64::from(header.image_header.width);
+    options
+        .limits
+        .check_image(width, u64::from(height), RGBA_BYTES as u64)?;
     let mut image = Image::new(header.image_header.width, height);
-    let mut i = 0;
-    let mut x = 0;
-    let mut y = 0;
-    while i < header.image_header.image_bytes as usize {
-        let r = image_data[i] as u8;
-        i += 1;
-        let g = image_data[i] as u8;
-        i += 1;
-        let b = image_data[i] as u8;
-        i += 1;
-        let a = image_data[i] as u8;
-        i += 1;
-        let color = RGBA::new(r, g, b, a);
-        image.write(x, y, color);
-        x += 1;
-        if x >= image.width {
-            x = 0;
-            y += 1;
+    let mut row = vec![0; RGBA_BYTES * image.width as usize];
+    let mut offset = header.file_header.offset as usize;
+    for i in 0..height {
+        let read = read_full(&mut reader, &mut row)?;
+        if read < row.len() {
+            let message = format!(
+                "pixel data ends in row {}, the rest of the image is transparent",
+                i
+            );
+            recovery.tolerate(offset, &message)?;
+        }
+        offset += row.len();
+        // A positive height means that the rows go up the image, starting from
+        // the bottom, and only a negative height means they go down.
+        let y = if header.image_header.height < 0 {
+            i
+        } else {
+            height - 1 - i
+        };
+        for (x, bytes) in row[..read].chunks_exact(RGBA_BYTES).enumerate() {
+            // Pixels are little endian, and the masks put red in the most
+            // significant byte, so the bytes are in ABGR order
+            let color = RGBA::new(bytes[3], bytes[2], bytes[1], bytes[0]);
+            image.write(x as u32, y, color);
+        }
+        if read < row.len() {
+            break;
         }
     }
-    Ok(image)
+    Ok((image, recovery.warnings))
+}
+
+pub fn parse_image(data: &[u8]) -> BMPResult<Image> {
+    decode(data)
+}
+
+/// Describe a file, with every field in its headers
+///
+/// Unlike decoding, this works for the kinds of files we can't decode.
+pub fn info(data: &[u8]) -> BMPResult<Info> {
+    // We want to describe broken headers, not complain about them
+    let mut recovery = Recovery::new(true);
+    let file_header = parse_file_header(data, &mut recovery)?;
+    if data.len() < COLOR_MASKS_OFFSET {
+        return invalid_format(data.len(), "insufficient image header length");
+    }
+    let image_header = parse_image_header(&data[FILE_HEADER_SIZE..], &mut recovery)?;
+    let compression = u32_le(&data[FILE_HEADER_SIZE + 16..]);
+    let mut info = Info::new(image_header.width, image_header.height.unsigned_abs());
+    info.bit_depth = Some(u32::from(image_header.bit_count));
+    info.compression = Some(match image_header.compression {
+        CompressionType::Uncompressed => "none".into(),
+        CompressionType::RLE8 => "rle8".into(),
+        CompressionType::RLE4 => "rle4".into(),
+        CompressionType::Bitfields => "bitfields".into(),
+        CompressionType::Unknown => format!("unknown ({})", compression),
+    });
+    info.dpi = Some((
+        dpi_from_meters(image_header.x_pixels_per_meter),
+        dpi_from_meters(image_header.y_pixels_per_meter),
+    ));
+    let indexed = image_header.bit_count <= 8;
+    info.palette_size = Some(match image_header.color_used {
+        0 if indexed => 1 << image_header.bit_count,
+        used => used,
+    });
+    info.field("FileHeader.size", file_header.size);
+    info.field("FileHeader.offset", file_header.offset);
+    info.field("ImageHeader.size", image_header.size);
+    info.field("ImageHeader.width", image_header.width);
+    info.field("ImageHeader.height", image_header.height);
+    info.field("ImageHeader.bit_count", image_header.bit_count);
+    info.field("ImageHeader.compression", compression);
+    info.field("ImageHeader.image_bytes", image_header.image_bytes);
+    info.field(
+        "ImageHeader.x_pixels_per_meter",
+        image_header.x_pixels_per_meter,
+    );
+    info.field(
+        "ImageHeader.y_pixels_per_meter",
+        image_header.y_pixels_per_meter,
+    );
+    info.field("ImageHeader.color_used", image_header.color_used);
+    info.field("ImageHeader.color_important", image_header.color_important);
+    let mut has_alpha = false;
+    let has_masks = image_header.compression == CompressionType::Bitfields;
+    if has_masks && data.len() >= COLOR_MASKS_OFFSET + 16 {
+        let masks = &data[COLOR_MASKS_OFFSET..];
+        for (i, name) in ["r", "g", "b", "a"].iter().enumerate() {
+            let mask = u32_le(&masks[4 * i..]);
+            info.field(&format!("ColorMasks.{}", name), format!("0x{:08X}", mask));
+            has_alpha |= *name == "a" && mask != 0;
+        }
+    }
+    info.color_type = Some(
+        match (indexed, has_alpha) {
+            (true, _) => "indexed",
+            (false, true) => "rgba",
+            (false, false) => "rgb",
+        }
+        .into(),
+    );
+    // Only the fifth version of the header can embed a color profile
+    let profile_size = FILE_HEADER_SIZE + 116;
+    info.metadata = Some(
+        image_header.size >= 124
+            && data.len() >= profile_size + 4
+            && u32_le(&data[profile_size..]) != 0,
+    );
+    Ok(info)
+}
+
+/// Add the region of a field in a header, if the field starts in the data
+fn field(data: &[u8], regions: &mut Vec<Region>, offset: usize, len: usize, name: &str) -> bool {
+    if offset >= data.len() {
+        return false;
+    }
+    let label = match len {
+        2 if offset + 2 <= data.len() => format!("{} = {}", name, u16_le(&data[offset..])),
+        4 if offset + 4 <= data.len() => format!("{} = {}", name, u32_le(&data[offset..])),
+        _ => name.to_string(),
+    };
+    regions.push(Region::new(offset, len, label));
+    true
+}
+
+/// Split a file into the regions of its headers and rows of pixels
+///
+/// This follows the specification, rather than what we can decode.
+pub fn dissect(data: &[u8]) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let signature = if data.starts_with(b"BM") {
+        "FileHeader.signature = \"BM\""
+    } else {
+        "FileHeader.signature, which should be \"BM\""
+    };
+    regions.push(Region::new(0, 2, signature));
+    field(data, &mut regions, 2, 4, "FileHeader.size");
+    field(data, &mut regions, 6, 4, "FileHeader.reserved");
+    field(data, &mut regions, 10, 4, "FileHeader.offset");
+    let h = FILE_HEADER_SIZE;
+    if !field(data, &mut regions, h, 4, "ImageHeader.size") || data.len() < h + 4 {
+        return regions;
+    }
+    let header_size = u32_le(&data[h..]) as usize;
+    let image_fields = [
+        (4, "ImageHeader.width"),
+        (4, "ImageHeader.height"),
+        (2, "ImageHeader.planes"),
+        (2, "ImageHeader.bit_count"),
+        (4, "ImageHeader.compression"),
+        (4, "ImageHeader.image_bytes"),
+        (4, "ImageHeader.x_pixels_per_meter"),
+        (4, "ImageHeader.y_pixels_per_meter"),
+        (4, "ImageHeader.color_used"),
+        (4, "ImageHeader.color_important"),
+    ];
+    let mut offset = h + 4;
+    for &(len, name) in &image_fields {
+        field(data, &mut regions, offset, len, name);
+        offset += len;
+    }
+    // The height is the only signed field
+    if let Some(height) = regions.iter_mut().find(|r| r.offset == h + 8) {
+        if data.len() >= h + 12 {
+            height.label = format!("ImageHeader.height = {}", i32_le(&data[h + 8..]));
+        }
+    }
+    if data.len() < COLOR_MASKS_OFFSET {
+        return regions;
+    }
+    let image_header = &data[h..];
+    let width = u32_le(&image_header[4..]) as usize;
+    let height = i32_le(&image_header[8..]).unsigned_abs() as usize;
+    let bit_count = u16_le(&image_header[14..]) as usize;
+    let compression = CompressionType::from(u32_le(&image_header[16..]));
+    // Masks come after the smallest header, or are part of bigger ones
+    let mut masks_end = h + header_size;
+    if compression == CompressionType::Bitfields || header_size >= 52 {
+        let names = ["red mask", "green mask", "blue mask", "alpha mask"];
+        let count = if header_size >= 56 || compression == CompressionType::Bitfields {
+            4
+        } else {
+            3
+        };
+        for (i, name) in names.iter().take(count).enumerate() {
+            let offset = COLOR_MASKS_OFFSET + 4 * i;
+            if offset + 4 <= data.len() {
+                let mask = u32_le(&data[offset..]);
+                regions.push(Region::new(offset, 4, format!("{} = 0x{:08X}", name, mask)));
+            }
+        }
+        masks_end = masks_end.max(COLOR_MASKS_OFFSET + 4 * count);
+    }
+    if header_size >= 108 {
+        if data.len() >= h + 60 {
+            let space = u32_le(&data[h + 56..]);
+            let label = format!("ImageHeader.color_space = 0x{:08X}", space);
+            regions.push(Region::new(h + 56, 4, label));
+        }
+        field(data, &mut regions, h + 60, 36, "ImageHeader.endpoints");
+        field(data, &mut regions, h + 96, 4, "ImageHeader.gamma_red");
+        field(data, &mut regions, h + 100, 4, "ImageHeader.gamma_green");
+        field(data, &mut regions, h + 104, 4, "ImageHeader.gamma_blue");
+    }
+    if header_size >= 124 {
+        field(data, &mut regions, h + 108, 4, "ImageHeader.intent");
+        field(data, &mut regions, h + 112, 4, "ImageHeader.profile_data");
+        field(data, &mut regions, h + 116, 4, "ImageHeader.profile_size");
+        field(data, &mut regions, h + 120, 4, "ImageHeader.reserved");
+    }
+    if bit_count <= 8 {
+        let colors = match u32_le(&image_header[32..]) as usize {
+            0 => 1 << bit_count,
+            used => used,
+        };
+        regions.push(Region::new(
+            masks_end,
+            4 * colors,
+            format!("color table ({} entries)", colors),
+        ));
+    }
+    let pixels = u32_le(&data[10..]) as usize;
+    match compression {
+        CompressionType::Uncompressed | CompressionType::Bitfields => {
+            let row_bytes = padded_row_bytes(width as u64, bit_count as u64);
+            let row_bytes = usize::try_from(row_bytes).unwrap_or(usize::MAX);
+            // Empty rows would never get us past the end of the file
+            if row_bytes == 0 {
+                let label = format!("pixel data ({} empty rows)", height);
+                regions.push(Region::new(pixels, 0, label));
+                return regions;
+            }
+            for row in 0..height {
+                let offset = pixels.saturating_add(row.saturating_mul(row_bytes));
+                if offset >= data.len() {
+                    regions.push(Region::new(
+                        offset,
+                        (height - row).saturating_mul(row_bytes),
+                        format!("pixel data rows {} to {}", row, height - 1),
+                    ));
+                    break;
+                }
+                regions.push(Region::new(
+                    offset,
+                    row_bytes,
+                    format!("pixel data row {}", row),
+                ));
+            }
+        }
+        _ => {
+            let len = u32_le(&image_header[20..]) as usize;
+            regions.push(Region::new(pixels, len, "compressed pixel data"));
+        }
+    }
+    regions
+}
+
+/// How many bytes a row of pixels takes up, after padding it to 4 bytes
+///
+/// This can't overflow, even for the largest widths and bit counts.
+fn padded_row_bytes(width: u64, bit_count: u64) -> u64 {
+    (width * bit_count).div_ceil(32) * 4
+}
+
+/// Check a file against the specification, reporting every deviation
+pub fn validate(data: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if !data.starts_with(b"BM") {
+        issues.push(Issue::error(0, "header doesn't start with 'BM'"));
+    }
+    if data.len() < FILE_HEADER_SIZE + 4 {
+        issues.push(Issue::error(data.len(), "file ends inside the file header"));
+        return issues;
+    }
+    let size = u32_le(&data[2..]) as usize;
+    if size != data.len() {
+        let message = format!(
+            "file size is {}, but the file is {} bytes",
+            size,
+            data.len()
+        );
+        issues.push(Issue::warning(2, message));
+    }
+    if data[6..10].iter().any(|&b| b != 0) {
+        issues.push(Issue::warning(6, "reserved bytes not 0"));
+    }
+    let offset = u32_le(&data[10..]) as usize;
+    let header_size = u32_le(&data[FILE_HEADER_SIZE..]) as usize;
+    if ![12, 40, 52, 56, 64, 108, 124].contains(&header_size) {
+        let message = format!("unknown image header size {}", header_size);
+        issues.push(Issue::error(FILE_HEADER_SIZE, message));
+    }
+    // The oldest header is too different to check any further
+    if header_size == 12 {
+        return issues;
+    }
+    if data.len() < COLOR_MASKS_OFFSET {
+        issues.push(Issue::error(
+            data.len(),
+            "file ends inside the image header",
+        ));
+        return issues;
+    }
+    let h = &data[FILE_HEADER_SIZE..];
+    let width = u32_le(&h[4..]) as usize;
+    let height = i32_le(&h[8..]).unsigned_abs() as usize;
+    let bit_count = u16_le(&h[14..]) as usize;
+    let compression = u32_le(&h[16..]);
+    let image_bytes = u32_le(&h[20..]) as usize;
+    if width == 0 || height == 0 {
+        issues.push(Issue::warning(FILE_HEADER_SIZE + 4, "image has no pixels"));
+    }
+    if h[12] != 1 || h[13] != 0 {
+        issues.push(Issue::error(FILE_HEADER_SIZE + 12, "plane count not 1"));
+    }
+    if ![1, 4, 8, 16, 24, 32].contains(&bit_count) {
+        let message = format!("invalid bit count {}", bit_count);
+        issues.push(Issue::error(FILE_HEADER_SIZE + 14, message));
+    }
+    let compression_ok = match CompressionType::from(compression) {
+        CompressionType::Uncompressed => true,
+        CompressionType::RLE8 => bit_count == 8,
+        CompressionType::RLE4 => bit_count == 4,
+        CompressionType::Bitfields => bit_count == 16 || bit_count == 32,
+        CompressionType::Unknown => false,
+    };
+    if !compression_ok {
+        let message = format!(
+            "compression type {} can't be used with {} bit pixels",
+            compression, bit_count
+        );
+        issues.push(Issue::error(FILE_HEADER_SIZE + 16, message));
+    }
+    let header_end = FILE_HEADER_SIZE + header_size;
+    let masks_end = if compression == 3 && header_size == 40 {
+        header_end + 12
+    } else {
+        header_end
+    };
+    if offset < masks_end {
+        issues.push(Issue::error(10, "pixel data starts inside the headers"));
+        return issues;
+    }
+    let compressed = compression == 1 || compression == 2;
+    let row_bytes = padded_row_bytes(width as u64, bit_count as u64);
+    let expected = if compressed {
+        image_bytes as u64
+    } else {
+        match row_bytes.checked_mul(height as u64) {
+            Some(expected) => expected,
+            None => {
+                let message = format!(
+                    "{}x{} pixels with {} bits each take too many bytes to count",
+                    width, height, bit_count
+                );
+                issues.push(Issue::error(FILE_HEADER_SIZE + 4, message));
+                return issues;
+            }
+        }
+    };
+    if !compressed && image_bytes != 0 && image_bytes as u64 != expected {
+        let message = format!(
+            "image size is {}, but {}x{} pixels take {} bytes",
+            image_bytes, width, height, expected
+        );
+        issues.push(Issue::warning(FILE_HEADER_SIZE + 20, message));
+    }
+    let len = data.len() as u64;
+    let pixels_end = (offset as u64).saturating_add(expected);
+    if pixels_end > len {
+        let missing = pixels_end - len.max(offset as u64);
+        let message = format!("pixel data is truncated, {} bytes are missing", missing);
+        issues.push(Issue::error(data.len(), message));
+    } else if pixels_end < len {
+        let message = format!("{} bytes after the pixel data", len - pixels_end);
+        issues.push(Issue::warning(pixels_end as usize, message));
+    }
+    issues
 }
 
 fn write_file_header<W: io::Write>(writer: &mut W, header: &FileHeader) -> io::Result<()> {
@@ -333,7 +833,11 @@ fn write_format<W: io::Write>(writer: &mut W, format: ColorFormat) -> io::Result
     writer.write_all(&[0; 48])
 }
 
-pub fn write_image<W: io::Write>(writer: &mut W, image: &Image) -> io::Result<()> {
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a>>>(
+    writer: &mut W,
+    image: I,
+) -> io::Result<()> {
+    let image = image.into();
     let pixel_count = image.width * image.height;
     let file_header = FileHeader {
         size: 122 + (RGBA_BYTES as u32 * pixel_count),
@@ -341,11 +845,11 @@ pub fn write_image<W: io::Write>(writer: &mut W, image: &Image) -> io::Result<()
     };
     let image_header = ImageHeader {
         size: 108,
-        width: image.width as u32,
+        width: image.width,
         height: -(image.height as i32),
         bit_count: 32,
         compression: CompressionType::Bitfields,
-        image_bytes: (image.width * image.height * 4) as u32,
+        image_bytes: image.width * image.height * 4,
         x_pixels_per_meter: 2835,
         y_pixels_per_meter: 2835,
         color_used: 0,
@@ -353,9 +857,187 @@ pub fn write_image<W: io::Write>(writer: &mut W, image: &Image) -> io::Result<()
     };
     write_file_header(writer, &file_header)?;
     write_image_header(writer, &image_header)?;
-    write_format(writer, ColorFormat::RGBA)?;
+    write_format(writer, ColorFormat::Rgba)?;
     for pixel in image {
         writer.write_all(&[pixel.a, pixel.b, pixel.g, pixel.r])?;
     }
     Ok(())
 }
+
+/// Reads and writes Windows bitmaps, and dissects and validates them
+pub struct BMPCodec;
+
+impl ImageDecoder for BMPCodec {
+    fn decode(&self, mut data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        self.decode_reader(&mut data, options)
+    }
+
+    fn decode_reader(
+        &self,
+        reader: &mut dyn io::BufRead,
+        options: &DecodeOptions,
+    ) -> MageResult<Decoded> {
+        let (image, _) = self.decode_with_warnings(reader, options)?;
+        Ok(image)
+    }
+
+    fn decode_with_warnings(
+        &self,
+        reader: &mut dyn io::BufRead,
+        options: &DecodeOptions,
+    ) -> MageResult<(Decoded, Vec<String>)> {
+        let (image, warnings) = decode_with(reader, options)?;
+        Ok((Decoded::Low(image), warnings))
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+impl Inspector for BMPCodec {
+    fn dissect(&self, data: &[u8]) -> Vec<Region> {
+        dissect(data)
+    }
+
+    fn validate(&self, data: &[u8]) -> Vec<Issue> {
+        validate(data)
+    }
+
+    fn info(&self, data: &[u8]) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+impl ImageEncoder for BMPCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        options: &EncodeOptions,
+    ) -> MageResult<()> {
+        write_image(&mut writer, &image.into_low(options.exposure))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::validate::Severity;
+
+    /// A reader giving out a single byte at a time, like a slow pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(out)) => {
+                    *out = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = Image::new(3, 2);
+        image.write(0, 0, RGBA::new(255, 0, 0, 255));
+        image.write(1, 0, RGBA::new(0, 255, 0, 128));
+        image.write(2, 1, RGBA::new(1, 2, 3, 4));
+        let mut data = Vec::new();
+        write_image(&mut data, &image).unwrap();
+        let decoded = decode(Trickle(&data)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert!((&decoded).into_iter().eq(&image));
+        let mut cropped = Vec::new();
+        write_image(&mut cropped, image.view(1, 0, 2, 2)).unwrap();
+        let decoded_crop = decode(&cropped[..]).unwrap();
+        assert!((&decoded_crop).into_iter().eq(image.view(1, 0, 2, 2)));
+        let truncated = &data[..data.len() - 1];
+        match decode(truncated) {
+            Err(BMPError::InvalidFormat(_, offset)) => assert_eq!(offset, 122 + 12),
+            _ => panic!("truncated file decoded"),
+        }
+        let mut options = DecodeOptions {
+            lenient: true,
+            ..DecodeOptions::default()
+        };
+        let (recovered, warnings) = decode_with(truncated, &options).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(recovered.read(0, 0), image.read(0, 0));
+        assert_eq!(recovered.read(1, 1), image.read(1, 1));
+        assert_eq!(recovered.read(2, 1), RGBA::new(0, 0, 0, 0));
+        options.limits.max_pixels = 5;
+        match decode_with(&data[..], &options) {
+            Err(BMPError::LimitsExceeded(_)) => {}
+            _ => panic!("image over the limits decoded"),
+        }
+    }
+
+    #[test]
+    fn test_known_file() {
+        // A 2x2 image, with red and green on top of blue and white
+        let mut data = std::fs::read("test_images/1.bmp").unwrap();
+        let image = parse_image(&data).unwrap();
+        let colors = [
+            RGBA::new(0xFF, 0, 0, 0xFF),
+            RGBA::new(0, 0xFF, 0, 0xFF),
+            RGBA::new(0, 0, 0xFF, 0xFF),
+            RGBA::new(0xFF, 0xFF, 0xFF, 0xFF),
+        ];
+        assert!((&image).into_iter().eq(colors.iter().cloned()));
+        // The file stores its rows top down, and flipping the sign of the
+        // height makes them go bottom up
+        data[22..26].copy_from_slice(&2i32.to_le_bytes());
+        let flipped = parse_image(&data).unwrap();
+        assert_eq!(flipped.read(0, 0), colors[2]);
+        assert_eq!(flipped.read(1, 1), colors[1]);
+    }
+
+    #[test]
+    fn test_dissect() {
+        let mut data = Vec::new();
+        write_image(&mut data, &Image::new(2, 3)).unwrap();
+        let regions = dissect(&data);
+        assert!(crate::dissect::unaccounted(data.len(), &regions).is_empty());
+        let last = regions.last().unwrap();
+        assert_eq!(
+            (last.offset, last.label.as_str()),
+            (138, "pixel data row 2")
+        );
+        data.push(0);
+        assert_eq!(
+            crate::dissect::unaccounted(data.len(), &regions),
+            vec![(146, 1)]
+        );
+        // Rows without any bytes can't go on forever
+        data[22..26].copy_from_slice(&i32::MAX.to_le_bytes());
+        data[28..30].copy_from_slice(&0u16.to_le_bytes());
+        let last = dissect(&data).pop().unwrap();
+        assert_eq!(last.label, format!("pixel data ({} empty rows)", i32::MAX));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut data = Vec::new();
+        write_image(&mut data, &Image::new(2, 2)).unwrap();
+        assert!(validate(&data).is_empty());
+        data[6] = 1;
+        data.push(0);
+        let offsets: Vec<usize> = validate(&data).iter().map(|i| i.offset).collect();
+        assert_eq!(offsets, vec![2, 6, 138]);
+        data.truncate(130);
+        let issues = validate(&data);
+        assert_eq!(issues.last().unwrap().severity, Severity::Error);
+        // Sizes too big to count are errors, rather than overflows
+        data[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[22..26].copy_from_slice(&i32::MAX.to_le_bytes());
+        data[28..30].copy_from_slice(&u16::MAX.to_le_bytes());
+        let issues = validate(&data);
+        assert_eq!(issues.last().unwrap().offset, 18);
+    }
+}
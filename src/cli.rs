@@ -1,68 +1,597 @@
-use crate::bmp;
+use crate::batch::{self, Batch};
+#[cfg(feature = "viewer")]
 use crate::display::display;
-use crate::image::{Image, RGBA};
-use crate::structopt::StructOpt;
+use mage::codec::{self, CODECS};
+use mage::composite::{self, Operator};
+use mage::format;
+use mage::validate::{Issue, Severity};
+use mage::{DecodeOptions, Decoded, EncodeOptions, Limits, MageError, MageResult, RGBA};
 use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::io::{BufReader, Read, Write};
+use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "mage")]
 pub enum Opt {
+    #[cfg(feature = "viewer")]
     #[structopt(name = "show")]
     /// Show the image in a file
     Show {
-        /// The input file to show
+        /// The input file to show, or - to read it from stdin
         input: String,
+        #[structopt(long = "format")]
+        /// The format of the input, instead of detecting it
+        format: Option<String>,
+        #[structopt(long = "exposure")]
+        /// Tone map high dynamic range images with this exposure, in stops,
+        /// instead of using Reinhard's operator
+        exposure: Option<f32>,
+        #[structopt(long = "mip", default_value = "0")]
+        /// Which mip level of a texture to show, with 0 being the largest
+        mip: u32,
+        #[structopt(long = "slice", default_value = "0")]
+        /// Which element of a texture array, or face of a cube map, to show
+        slice: u32,
+        #[structopt(long = "lenient")]
+        /// Recover what we can from broken BMP files, with warnings, instead of failing
+        lenient: bool,
+        #[structopt(flatten)]
+        limits: LimitFlags,
     },
     #[structopt(name = "convert")]
     /// Convert an image from one format to another
     Convert {
-        /// The image file to convert
-        input: String,
+        #[structopt(raw(required = "true"))]
+        /// The image files to convert, or - to read one from stdin
+        ///
+        /// These can also be directories, or glob patterns like 'photos/**/*.bmp'.
+        inputs: Vec<String>,
         #[structopt(short = "o")]
-        /// The output file for the image
+        /// The output file for the image, or - to write it to stdout
+        ///
+        /// With many inputs, this is either a directory, or a template
+        /// like 'out/{dir}/{stem}.png', where {name} and {ext} can also be used.
         output: String,
+        #[structopt(long = "format")]
+        /// The format to write, instead of guessing it from the output file
+        format: Option<String>,
+        #[structopt(long = "input-format")]
+        /// The format of the input, instead of detecting it
+        input_format: Option<String>,
+        #[structopt(long = "exposure")]
+        /// Tone map high dynamic range images with this exposure, in stops,
+        /// when writing them to an ordinary format
+        exposure: Option<f32>,
+        #[structopt(short = "r", long = "recursive")]
+        /// Convert the files in subdirectories of input directories as well
+        recursive: bool,
+        #[structopt(long = "skip-up-to-date")]
+        /// Don't convert inputs whose output is newer than them
+        skip_up_to_date: bool,
+        #[structopt(short = "j", long = "jobs")]
+        /// How many files to convert at the same time, by default one per core
+        jobs: Option<usize>,
+        #[structopt(long = "lenient")]
+        /// Recover what we can from broken BMP files, with warnings, instead of failing
+        lenient: bool,
+        #[structopt(flatten)]
+        limits: LimitFlags,
+    },
+    #[structopt(name = "composite")]
+    /// Place one image on top of another
+    Composite {
+        /// The image to place the other one onto, which decides the size of the result
+        destination: String,
+        /// The image to place on top
+        source: String,
+        #[structopt(short = "o")]
+        /// The output file for the image, or - to write it to stdout
+        output: String,
+        #[structopt(
+            long = "at",
+            default_value = "0,0",
+            parse(try_from_str = "parse_offset"),
+            raw(allow_hyphen_values = "true")
+        )]
+        /// Where the top left corner of the source goes, as x,y
+        at: (i64, i64),
+        #[structopt(
+            long = "op",
+            default_value = "over",
+            raw(possible_values = "Operator::NAMES")
+        )]
+        /// How to combine the two images
+        op: String,
+        #[structopt(long = "background", parse(try_from_str = "parse_color"))]
+        /// Flatten the result onto a color, like ffffff for white
+        background: Option<RGBA>,
+        #[structopt(long = "format")]
+        /// The format to write, instead of guessing it from the output file
+        format: Option<String>,
+    },
+    #[structopt(name = "identify")]
+    /// Detect the format of an image file
+    Identify {
+        /// The file to identify
+        input: String,
+    },
+    #[structopt(name = "info")]
+    /// Print what's inside an image file, including the fields of its headers
+    Info {
+        /// The file to describe
+        input: String,
+        #[structopt(long = "json")]
+        /// Print a JSON object, for other programs to read
+        json: bool,
+    },
+    #[structopt(name = "dissect")]
+    /// Print a hex dump of a file, annotated with what each part means
+    Dissect {
+        /// The file to dissect
+        input: String,
+    },
+    #[structopt(name = "validate")]
+    /// Check a file against the specification of its format, listing every problem
+    Validate {
+        /// The file to check
+        input: String,
+    },
+    #[structopt(name = "formats")]
+    /// List the formats we know about, and whether we can read or write them
+    Formats,
+}
+
+/// The flags overriding the limits on what we decode
+#[derive(Debug, StructOpt)]
+pub struct LimitFlags {
+    #[structopt(long = "max-width")]
+    /// The widest image to accept, in pixels
+    max_width: Option<u32>,
+    #[structopt(long = "max-height")]
+    /// The tallest image to accept, in pixels
+    max_height: Option<u32>,
+    #[structopt(long = "max-pixels")]
+    /// The most pixels an image can have
+    max_pixels: Option<u64>,
+    #[structopt(long = "max-alloc")]
+    /// The most bytes to allocate for a single buffer while decoding
+    max_alloc: Option<u64>,
+    #[structopt(long = "max-metadata")]
+    /// The most bytes of metadata a file can have
+    max_metadata: Option<u64>,
+    #[structopt(long = "max-frames")]
+    /// The most frames, layers, or texture elements a file can have
+    max_frames: Option<u32>,
+    #[structopt(long = "no-limits")]
+    /// Accept images of any size, for files you trust
+    no_limits: bool,
+}
+
+impl LimitFlags {
+    /// Apply these flags on top of the default limits
+    fn limits(&self) -> Limits {
+        let base = if self.no_limits {
+            Limits::unlimited()
+        } else {
+            Limits::default()
+        };
+        Limits {
+            max_width: self.max_width.unwrap_or(base.max_width),
+            max_height: self.max_height.unwrap_or(base.max_height),
+            max_pixels: self.max_pixels.unwrap_or(base.max_pixels),
+            max_alloc: self.max_alloc.unwrap_or(base.max_alloc),
+            max_metadata: self.max_metadata.unwrap_or(base.max_metadata),
+            max_frames: self.max_frames.unwrap_or(base.max_frames),
+        }
+    }
+}
+
+/// How a command finished, when it didn't fail outright
+pub enum Status {
+    Success,
+    /// Some of the files in a batch couldn't be converted
+    BatchFailures {
+        failed: usize,
+        total: usize,
     },
 }
 
 impl Opt {
     /// Handle all cases of the command line options, running
     /// the right sub-programs
-    pub fn dispatch(self) -> io::Result<()> {
+    pub fn dispatch(self) -> MageResult<Status> {
         match self {
-            Opt::Show { input } => show(input),
-            Opt::Convert { .. } => {
-                let image = make_image();
-                let file = File::create("foo.bmp")?;
-                let mut writer = io::BufWriter::new(file);
-                bmp::write_image(&mut writer, &image)
+            #[cfg(feature = "viewer")]
+            Opt::Show {
+                input,
+                format,
+                exposure,
+                mip,
+                slice,
+                lenient,
+                limits,
+            } => {
+                let options = DecodeOptions {
+                    mip_level: mip,
+                    array_slice: slice,
+                    lenient,
+                    limits: limits.limits(),
+                };
+                show(input, format, exposure, options)
             }
-        }
+            Opt::Convert {
+                inputs,
+                output,
+                format,
+                input_format,
+                exposure,
+                recursive,
+                skip_up_to_date,
+                jobs,
+                lenient,
+                limits,
+            } => {
+                let options = DecodeOptions {
+                    lenient,
+                    limits: limits.limits(),
+                    ..DecodeOptions::default()
+                };
+                if batch::is_batch(&inputs, &output) {
+                    let summary = batch::convert_all(Batch {
+                        inputs,
+                        output,
+                        format,
+                        input_format,
+                        exposure,
+                        recursive,
+                        skip_up_to_date,
+                        jobs,
+                        options,
+                    })?;
+                    if summary.failed > 0 {
+                        return Ok(Status::BatchFailures {
+                            failed: summary.failed,
+                            total: summary.total(),
+                        });
+                    }
+                    return Ok(Status::Success);
+                }
+                // There's only one input if this isn't a batch
+                let input = inputs.into_iter().next().unwrap();
+                convert(input, output, format, input_format, exposure, options)
+            }
+            Opt::Composite {
+                destination,
+                source,
+                output,
+                at,
+                op,
+                background,
+                format,
+            } => {
+                // The possible values are checked while parsing
+                let operator = Operator::from_name(&op).unwrap();
+                composite(
+                    destination,
+                    source,
+                    output,
+                    at,
+                    operator,
+                    background,
+                    format,
+                )
+            }
+            Opt::Identify { input } => identify(input),
+            Opt::Info { input, json } => info(input, json),
+            Opt::Dissect { input } => dissect(input),
+            Opt::Validate { input } => validate(input),
+            Opt::Formats => {
+                formats();
+                Ok(())
+            }
+        }?;
+        Ok(Status::Success)
     }
 }
 
-fn show(input: String) -> io::Result<()> {
-    let mut f = File::open(input)?;
+/// Parse an offset written as x,y
+fn parse_offset(s: &str) -> Result<(i64, i64), String> {
+    let invalid = || format!("'{}' isn't an offset like 10,20", s);
+    let mut parts = s.splitn(2, ',');
+    let mut next = || parts.next().and_then(|p| p.trim().parse().ok());
+    let x = next().ok_or_else(invalid)?;
+    let y = next().ok_or_else(invalid)?;
+    Ok((x, y))
+}
+
+/// Parse a color written in hex, as rrggbb or rrggbbaa, with an optional #
+fn parse_color(s: &str) -> Result<RGBA, String> {
+    let invalid = || format!("'{}' isn't a color like ffffff", s);
+    let hex = s.trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut c = [255u8; 4];
+    for (i, byte) in c.iter_mut().take(hex.len() / 2).enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(RGBA::new(c[0], c[1], c[2], c[3]))
+}
+
+/// Read the whole contents of a file
+fn read_file(path: &str) -> MageResult<Vec<u8>> {
+    let mut f = File::open(path).map_err(|e| MageError::with_path(path, e))?;
     let mut buffer = Vec::new();
-    f.read_to_end(&mut buffer)?;
-    let image = match bmp::parse_image(&buffer) {
-        Ok(img) => img,
-        Err(e) => {
-            println!("Failed to parse image: {:?}", e);
-            return Ok(())
-        }
+    f.read_to_end(&mut buffer)
+        .map_err(|e| MageError::with_path(path, e))?;
+    Ok(buffer)
+}
+
+/// The path standing in for stdin or stdout
+const STDIO: &str = "-";
+
+/// Read an image from a file, or from stdin, optionally in a given format
+pub fn read_image(
+    input: &str,
+    format: Option<&str>,
+    options: &DecodeOptions,
+) -> MageResult<Decoded> {
+    let (image, warnings) = if input == STDIO {
+        let stdin = io::stdin();
+        mage::decode_with_warnings(stdin.lock(), format, options)?
+    } else if format.is_some() {
+        let file = File::open(input).map_err(|e| MageError::with_path(input, e))?;
+        mage::decode_with_warnings(BufReader::new(file), format, options)?
+    } else {
+        mage::load_with_warnings(input, options)?
+    };
+    for warning in warnings {
+        eprintln!("mage: warning: {}: {}", input, warning);
+    }
+    Ok(image)
+}
+
+fn convert(
+    input: String,
+    output: String,
+    format: Option<String>,
+    input_format: Option<String>,
+    exposure: Option<f32>,
+    options: DecodeOptions,
+) -> MageResult<()> {
+    check_output(&output, format.as_deref())?;
+    let image = read_image(&input, input_format.as_deref(), &options)?;
+    write_image(&input, &output, image, format.as_deref(), exposure)
+}
+
+/// Make sure we know what format to write an output in, before doing any work
+fn check_output(output: &str, format: Option<&str>) -> MageResult<()> {
+    if format.is_none() && (output == STDIO || codec::by_path(output).is_none()) {
+        let what = if output == STDIO {
+            "stdout".into()
+        } else {
+            format!("'{}'", output)
+        };
+        return Err(MageError::UnsupportedFormat {
+            format: None,
+            message: format!(
+                "couldn't detect the format of {}, use --format to pick one",
+                what
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Write an image to a file, or to stdout, naming it after its input if needed
+fn write_image(
+    input: &str,
+    output: &str,
+    image: Decoded,
+    format: Option<&str>,
+    exposure: Option<f32>,
+) -> MageResult<()> {
+    if output != STDIO {
+        let options = EncodeOptions {
+            name: String::new(),
+            exposure,
+        };
+        return mage::save_with(output, image, format, &options);
+    }
+    let name = if input == STDIO {
+        "image".into()
+    } else {
+        mage::identifier(input)
+    };
+    let options = EncodeOptions { name, exposure };
+    // Only the image goes to stdout, any messages go to stderr
+    let stdout = io::stdout();
+    let mut writer = io::BufWriter::new(stdout.lock());
+    // We checked that there's a format above
+    mage::encode(&mut writer, image, format.unwrap(), &options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn composite(
+    destination: String,
+    source: String,
+    output: String,
+    at: (i64, i64),
+    operator: Operator,
+    background: Option<RGBA>,
+    format: Option<String>,
+) -> MageResult<()> {
+    check_output(&output, format.as_deref())?;
+    let options = DecodeOptions::default();
+    let bottom = read_image(&destination, None, &options)?.into_low(None);
+    let top = read_image(&source, None, &options)?.into_low(None);
+    let mut image = composite::composite(&bottom, &top, at, operator);
+    if let Some(color) = background {
+        image = composite::flatten(&image, color);
+    }
+    write_image(
+        &destination,
+        &output,
+        Decoded::Low(image),
+        format.as_deref(),
+        None,
+    )
+}
+
+fn identify(input: String) -> MageResult<()> {
+    let buffer = read_file(&input)?;
+    match format::detect(&buffer, Some(&input)) {
+        Some(detected) => println!(
+            "{}: {} ({})",
+            input,
+            detected.codec.name,
+            detected.confidence.describe()
+        ),
+        None => return Err(MageError::unknown_format(&format!("'{}'", input))),
+    }
+    Ok(())
+}
+
+fn info(input: String, json: bool) -> MageResult<()> {
+    let info = mage::info(&input)?;
+    if json {
+        println!("{}", info.to_json());
+        return Ok(());
+    }
+    let unknown = || "unknown".to_string();
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+    println!("format: {}", info.format);
+    println!("dimensions: {}x{}", info.width, info.height);
+    let bit_depth = info.bit_depth.map(|b| b.to_string());
+    println!("bit depth: {}", bit_depth.unwrap_or_else(unknown));
+    println!("color type: {}", info.color_type.unwrap_or_else(unknown));
+    println!("compression: {}", info.compression.unwrap_or_else(unknown));
+    let dpi = info.dpi.map(|(x, y)| format!("{} x {}", x, y));
+    println!("dpi: {}", dpi.unwrap_or_else(unknown));
+    let palette_size = info.palette_size.map(|p| p.to_string());
+    println!("palette size: {}", palette_size.unwrap_or_else(unknown));
+    let metadata = info.metadata.map(yes_no);
+    println!("metadata: {}", metadata.unwrap_or_else(unknown));
+    for (name, value) in &info.fields {
+        println!("{}: {}", name, value);
+    }
+    Ok(())
+}
+
+fn dissect(input: String) -> MageResult<()> {
+    let buffer = read_file(&input)?;
+    let codec = match format::detect(&buffer, Some(&input)) {
+        Some(detected) => detected.codec,
+        None => return Err(MageError::unknown_format(&format!("'{}'", input))),
     };
-    display(image);
+    let inspector = codec.inspector.ok_or_else(|| {
+        MageError::unsupported(codec.name, "dissecting this format is not supported")
+    })?;
+    let regions = inspector.dissect(&buffer);
+    print!("{}", mage::dissect::render(&buffer, &regions));
+    let gaps = mage::dissect::unaccounted(buffer.len(), &regions);
+    if !gaps.is_empty() {
+        let bytes: usize = gaps.iter().map(|&(_, len)| len).sum();
+        eprintln!("{} bytes weren't accounted for", bytes);
+    }
     Ok(())
 }
 
-fn make_image() -> Image {
-    let mut image = Image::new(255, 200);
-    for x in 0..255 {
-        for y in 0..200 {
-            image.write(x, y, RGBA::new(0xFF, x as u8, y as u8, 0xFF));
+fn validate(input: String) -> MageResult<()> {
+    let buffer = read_file(&input)?;
+    let codec = match format::detect(&buffer, Some(&input)) {
+        Some(detected) => detected.codec,
+        None => return Err(MageError::unknown_format(&format!("'{}'", input))),
+    };
+    let inspector = codec.inspector.ok_or_else(|| {
+        MageError::unsupported(codec.name, "validating this format is not supported")
+    })?;
+    let issues = inspector.validate(&buffer);
+    for issue in &issues {
+        println!("{}: {}", input, issue);
+    }
+    let errors: Vec<&Issue> = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .collect();
+    match errors.first() {
+        None => {
+            if issues.is_empty() {
+                println!("{}: valid {} file", input, codec.name);
+            }
+            Ok(())
         }
+        Some(first) => Err(MageError::InvalidFormat {
+            format: codec.name,
+            message: match errors.len() {
+                1 => "found 1 error".into(),
+                n => format!("found {} errors", n),
+            },
+            offset: Some(first.offset),
+        }),
+    }
+}
+
+fn formats() {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    println!(
+        "{:<6} {:<5} {:<6} {:<24} MIME TYPES",
+        "NAME", "READ", "WRITE", "EXTENSIONS"
+    );
+    for codec in CODECS.iter() {
+        println!(
+            "{:<6} {:<5} {:<6} {:<24} {}",
+            codec.name,
+            yes_no(codec.decoder.is_some()),
+            yes_no(codec.encoder.is_some()),
+            codec.extensions.join(", "),
+            codec.mime_types.join(", ")
+        );
+    }
+}
+
+#[cfg(feature = "viewer")]
+fn show(
+    input: String,
+    format: Option<String>,
+    exposure: Option<f32>,
+    options: DecodeOptions,
+) -> MageResult<()> {
+    let image = read_image(&input, format.as_deref(), &options)?.into_low(exposure);
+    display(image)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mage::{png, Image};
+
+    #[test]
+    fn test_composite_png() {
+        let dir = std::env::temp_dir().join(format!("mage-composite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let write = |name: &str, image: &Image| {
+            png::write_image(&mut File::create(path(name)).unwrap(), image).unwrap()
+        };
+        write(
+            "a.png",
+            &Image::from_fn(40, 30, |_, _| RGBA::new(0, 0, 0xFF, 0xFF)),
+        );
+        write(
+            "b.png",
+            &Image::from_fn(4, 4, |_, _| RGBA::new(0xFF, 0, 0, 0xFF)),
+        );
+        let args = ["mage", "composite", &path("a.png"), &path("b.png")];
+        let rest = ["--at", "10,20", "-o", &path("out.png")];
+        let opt = Opt::from_iter(args.iter().chain(rest.iter()));
+        assert!(matches!(opt.dispatch(), Ok(Status::Success)));
+        let out = mage::load(path("out.png")).unwrap();
+        assert_eq!((out.width, out.height), (40, 30));
+        assert_eq!(out.read(10, 20), RGBA::new(0xFF, 0, 0, 0xFF));
+        assert_eq!(out.read(9, 20), RGBA::new(0, 0, 0xFF, 0xFF));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-    image
 }
@@ -0,0 +1,512 @@
+use crate::bmp::BMPCodec;
+use crate::dds::DDSCodec;
+use crate::dissect::Region;
+use crate::error::MageResult;
+use crate::exr::EXRCodec;
+use crate::hdr::HDRCodec;
+use crate::ilbm::ILBMCodec;
+use crate::image::{FloatImage, Image, Luma8, LumaA8, Rgb8, Rgba16, RGBA_BYTES};
+use crate::info::Info;
+use crate::limits::{LimitResult, Limits};
+use crate::pcx::PCXCodec;
+use crate::png::PNGCodec;
+use crate::psd::PSDCodec;
+use crate::sgi::SGICodec;
+use crate::sunras::SunRasterCodec;
+use crate::tonemap;
+use crate::validate::Issue;
+use crate::webp::WebPCodec;
+use crate::xbm::XBMCodec;
+use crate::xpm::XPMCodec;
+use std::io;
+// The registry in this module is the one place that knows about every format.
+// Each format module provides a codec implementing `ImageDecoder` and/or
+// `ImageEncoder`, and adding a format only requires adding an entry here.
+
+/// An image read from a file
+///
+/// High dynamic range images are kept as is, so that converting between
+/// those formats doesn't lose any information. Other formats can also keep
+/// the pixels they store, instead of inflating them into 8 bit RGBA.
+pub enum Decoded {
+    Low(Image),
+    High(FloatImage),
+    Gray(Image<Luma8>),
+    GrayAlpha(Image<LumaA8>),
+    Rgb(Image<Rgb8>),
+    Rgba16(Image<Rgba16>),
+}
+
+impl Decoded {
+    /// Get an ordinary image, tone mapping if necessary
+    ///
+    /// With no exposure, high dynamic range images use Reinhard's operator.
+    pub fn into_low(self, exposure: Option<f32>) -> Image {
+        match self {
+            Decoded::Low(image) => image,
+            Decoded::High(image) => match exposure {
+                Some(stops) => tonemap::exposure(&image, stops, tonemap::DEFAULT_GAMMA),
+                None => tonemap::reinhard(&image),
+            },
+            Decoded::Gray(image) => image.convert(),
+            Decoded::GrayAlpha(image) => image.convert(),
+            Decoded::Rgb(image) => image.convert(),
+            Decoded::Rgba16(image) => image.convert(),
+        }
+    }
+
+    /// Get a floating point image, linearizing ordinary images
+    pub fn into_high(self) -> FloatImage {
+        match self {
+            Decoded::Low(image) => tonemap::linearize(&image, tonemap::DEFAULT_GAMMA),
+            Decoded::High(image) => image,
+            Decoded::Gray(image) => tonemap::linearize(&image, tonemap::DEFAULT_GAMMA),
+            Decoded::GrayAlpha(image) => tonemap::linearize(&image, tonemap::DEFAULT_GAMMA),
+            Decoded::Rgb(image) => tonemap::linearize(&image, tonemap::DEFAULT_GAMMA),
+            Decoded::Rgba16(image) => tonemap::linearize(&image, tonemap::DEFAULT_GAMMA),
+        }
+    }
+
+    /// The width and height of the image, whatever its pixels are
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Decoded::Low(image) => (image.width, image.height),
+            Decoded::High(image) => (image.width, image.height),
+            Decoded::Gray(image) => (image.width, image.height),
+            Decoded::GrayAlpha(image) => (image.width, image.height),
+            Decoded::Rgb(image) => (image.width, image.height),
+            Decoded::Rgba16(image) => (image.width, image.height),
+        }
+    }
+
+    /// Check that `into_low` stays within the limits
+    ///
+    /// Decoders only check the pixels they keep, which can take up less
+    /// room than the 4 bytes of each pixel in an ordinary image.
+    pub fn check_low(&self, limits: &Limits) -> LimitResult {
+        let (width, height) = self.dimensions();
+        limits.check_image(u64::from(width), u64::from(height), RGBA_BYTES as u64)
+    }
+}
+
+/// The options that can change how a file is decoded
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecodeOptions {
+    /// Which mip level to pick, for textures
+    pub mip_level: u32,
+    /// Which element of an array, or face of a cube map, to pick, for textures
+    pub array_slice: u32,
+    /// Whether or not to recover what we can from broken files, instead of failing
+    ///
+    /// Only BMP files can be recovered for now. Other formats are decoded
+    /// strictly, with a warning saying so.
+    pub lenient: bool,
+    /// The largest images we accept, checked before allocating anything
+    pub limits: Limits,
+}
+
+/// The options that can change how an image is encoded
+#[derive(Clone, Debug, Default)]
+pub struct EncodeOptions {
+    /// The name to embed in formats that are source code, like XBM
+    pub name: String,
+    /// The exposure used when tone mapping into an ordinary format
+    pub exposure: Option<f32>,
+}
+
+/// Something that can read the contents of a file into an image
+pub trait ImageDecoder: Sync {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded>;
+
+    /// Decode an image straight from a reader
+    ///
+    /// By default, this reads everything into memory before calling `decode`,
+    /// but formats that can decode as they read should override it.
+    fn decode_reader(
+        &self,
+        reader: &mut dyn io::BufRead,
+        options: &DecodeOptions,
+    ) -> MageResult<Decoded> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        self.decode(&data, options)
+    }
+
+    /// Decode an image from a reader, along with warnings about broken files
+    ///
+    /// When decoding leniently, formats that can recover from problems
+    /// describe them with these warnings. Formats that can't recover should
+    /// keep this default, which decodes strictly, and says so when asked
+    /// to be lenient.
+    fn decode_with_warnings(
+        &self,
+        reader: &mut dyn io::BufRead,
+        options: &DecodeOptions,
+    ) -> MageResult<(Decoded, Vec<String>)> {
+        let image = self.decode_reader(reader, options)?;
+        let mut warnings = Vec::new();
+        if options.lenient {
+            warnings.push("this format can't be recovered, so it was decoded strictly".into());
+        }
+        Ok((image, warnings))
+    }
+
+    /// Describe an image, along with the headers of its file
+    ///
+    /// By default, this decodes the image, which only tells us its size.
+    fn info(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Info> {
+        let (width, height) = self.decode(data, options)?.dimensions();
+        Ok(Info::new(width, height))
+    }
+}
+
+/// Something that can write an image out in a given format
+pub trait ImageEncoder: Sync {
+    fn encode(
+        &self,
+        writer: &mut dyn io::Write,
+        image: Decoded,
+        options: &EncodeOptions,
+    ) -> MageResult<()>;
+}
+
+/// Something that can explain the structure of files in a format, and check them
+pub trait Inspector: Sync {
+    /// Split a file into labelled regions, following its structure
+    ///
+    /// This should go as far as it can in broken files, leaving the
+    /// bytes it can't make sense of out of the regions.
+    fn dissect(&self, data: &[u8]) -> Vec<Region>;
+
+    /// Check a file against the specification of its format
+    ///
+    /// Unlike decoding, this keeps going after a problem, to report every one.
+    fn validate(&self, data: &[u8]) -> Vec<Issue>;
+
+    /// Describe an image, along with the headers of its file
+    ///
+    /// This is what we use for formats we can inspect, but not decode.
+    fn info(&self, data: &[u8]) -> MageResult<Info>;
+}
+
+/// A sequence of bytes, each at some offset, identifying a format
+pub struct Magic {
+    /// Each of these must match for the magic to match
+    pub parts: &'static [(usize, &'static [u8])],
+    /// The byte at each of these offsets must be one of the given bytes
+    pub one_of: &'static [(usize, &'static [u8])],
+    /// Whether or not other files could start with these bytes by chance
+    pub weak: bool,
+}
+
+impl Magic {
+    /// Check if the start of a file matches
+    pub fn matches(&self, data: &[u8]) -> bool {
+        let parts = self
+            .parts
+            .iter()
+            .all(|&(offset, bytes)| data.get(offset..offset + bytes.len()) == Some(bytes));
+        parts
+            && self
+                .one_of
+                .iter()
+                .all(|&(offset, bytes)| data.get(offset).is_some_and(|b| bytes.contains(b)))
+    }
+}
+
+const fn strong(parts: &'static [(usize, &'static [u8])]) -> Magic {
+    Magic {
+        parts,
+        one_of: &[],
+        weak: false,
+    }
+}
+
+const fn weak(parts: &'static [(usize, &'static [u8])]) -> Magic {
+    Magic {
+        parts,
+        one_of: &[],
+        weak: true,
+    }
+}
+
+/// Weak magic, where some bytes can take a few different values
+const fn weak_one_of(
+    parts: &'static [(usize, &'static [u8])],
+    one_of: &'static [(usize, &'static [u8])],
+) -> Magic {
+    Magic {
+        parts,
+        one_of,
+        weak: true,
+    }
+}
+
+/// Everything we know about a format
+pub struct Codec {
+    /// The short name used to refer to the format
+    pub name: &'static str,
+    /// The extensions used for files in this format, the first being the usual one
+    pub extensions: &'static [&'static str],
+    pub mime_types: &'static [&'static str],
+    /// The ways files in this format can start
+    pub magic: &'static [Magic],
+    /// Whether or not the format is text, which may start with whitespace
+    pub is_text: bool,
+    pub decoder: Option<&'static dyn ImageDecoder>,
+    pub encoder: Option<&'static dyn ImageEncoder>,
+    pub inspector: Option<&'static dyn Inspector>,
+}
+
+/// Every format we know about, including some that we can only recognize
+#[rustfmt::skip]
+pub static CODECS: [Codec; 18] = [
+    Codec {
+        name: "bmp",
+        extensions: &["bmp", "dib"],
+        mime_types: &["image/bmp"],
+        magic: &[weak(&[(0, b"BM")])],
+        is_text: false,
+        decoder: Some(&BMPCodec),
+        encoder: Some(&BMPCodec),
+        inspector: Some(&BMPCodec),
+    },
+    Codec {
+        name: "dds",
+        extensions: &["dds"],
+        mime_types: &["image/vnd-ms.dds"],
+        magic: &[strong(&[(0, b"DDS ")])],
+        is_text: false,
+        decoder: Some(&DDSCodec),
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "exr",
+        extensions: &["exr"],
+        mime_types: &["image/x-exr"],
+        magic: &[strong(&[(0, &[0x76, 0x2F, 0x31, 0x01])])],
+        is_text: false,
+        decoder: Some(&EXRCodec),
+        encoder: Some(&EXRCodec),
+        inspector: None,
+    },
+    Codec {
+        name: "gif",
+        extensions: &["gif"],
+        mime_types: &["image/gif"],
+        magic: &[strong(&[(0, b"GIF87a")]), strong(&[(0, b"GIF89a")])],
+        is_text: false,
+        decoder: None,
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "hdr",
+        extensions: &["hdr"],
+        mime_types: &["image/vnd.radiance"],
+        magic: &[strong(&[(0, b"#?RADIANCE")]), strong(&[(0, b"#?RGBE")])],
+        is_text: false,
+        decoder: Some(&HDRCodec),
+        encoder: Some(&HDRCodec),
+        inspector: None,
+    },
+    Codec {
+        name: "ilbm",
+        extensions: &["iff", "ilbm", "lbm"],
+        mime_types: &["image/x-ilbm"],
+        magic: &[strong(&[(0, b"FORM"), (8, b"ILBM")]), strong(&[(0, b"FORM"), (8, b"PBM ")])],
+        is_text: false,
+        decoder: Some(&ILBMCodec),
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "jpeg",
+        extensions: &["jpg", "jpeg", "jpe"],
+        mime_types: &["image/jpeg"],
+        magic: &[strong(&[(0, &[0xFF, 0xD8, 0xFF])])],
+        is_text: false,
+        decoder: None,
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "pcx",
+        extensions: &["pcx"],
+        mime_types: &["image/vnd.zbrush.pcx", "image/x-pcx"],
+        // The manufacturer byte, followed by the version and the encoding
+        magic: &[weak_one_of(
+            &[(0, &[0x0A])],
+            &[(1, &[0, 2, 3, 4, 5]), (2, &[0, 1])],
+        )],
+        is_text: false,
+        decoder: Some(&PCXCodec),
+        encoder: Some(&PCXCodec),
+        inspector: None,
+    },
+    Codec {
+        name: "png",
+        extensions: &["png"],
+        mime_types: &["image/png"],
+        magic: &[strong(&[(0, b"\x89PNG\r\n\x1A\n")])],
+        is_text: false,
+        decoder: Some(&PNGCodec),
+        encoder: Some(&PNGCodec),
+        inspector: Some(&PNGCodec),
+    },
+    Codec {
+        name: "pnm",
+        extensions: &["pnm", "pbm", "pgm", "ppm", "pam"],
+        mime_types: &["image/x-portable-anymap"],
+        // The kind of map has to be followed by whitespace
+        magic: &[weak_one_of(
+            &[(0, b"P")],
+            &[(1, b"1234567"), (2, b" \t\n\x0C\r")],
+        )],
+        is_text: false,
+        decoder: None,
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "psd",
+        extensions: &["psd"],
+        mime_types: &["image/vnd.adobe.photoshop"],
+        magic: &[strong(&[(0, b"8BPS")])],
+        is_text: false,
+        decoder: Some(&PSDCodec),
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "qoi",
+        extensions: &["qoi"],
+        mime_types: &["image/qoi"],
+        magic: &[strong(&[(0, b"qoif")])],
+        is_text: false,
+        decoder: None,
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "sgi",
+        extensions: &["sgi", "rgb", "rgba", "bw"],
+        mime_types: &["image/sgi", "image/x-rgb"],
+        // The magic number, followed by the storage and bytes per channel
+        magic: &[weak_one_of(
+            &[(0, &[0x01, 0xDA])],
+            &[(2, &[0, 1]), (3, &[1, 2])],
+        )],
+        is_text: false,
+        decoder: Some(&SGICodec),
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "ras",
+        extensions: &["ras", "sun"],
+        mime_types: &["image/x-sun-raster"],
+        magic: &[strong(&[(0, &[0x59, 0xA6, 0x6A, 0x95])])],
+        is_text: false,
+        decoder: Some(&SunRasterCodec),
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "tiff",
+        extensions: &["tif", "tiff"],
+        mime_types: &["image/tiff"],
+        magic: &[strong(&[(0, b"II*\0")]), strong(&[(0, b"MM\0*")])],
+        is_text: false,
+        decoder: None,
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "webp",
+        extensions: &["webp"],
+        mime_types: &["image/webp"],
+        magic: &[strong(&[(0, b"RIFF"), (8, b"WEBP")])],
+        is_text: false,
+        decoder: Some(&WebPCodec),
+        encoder: None,
+        inspector: None,
+    },
+    Codec {
+        name: "xbm",
+        extensions: &["xbm"],
+        mime_types: &["image/x-xbitmap"],
+        magic: &[weak(&[(0, b"#define")])],
+        is_text: true,
+        decoder: Some(&XBMCodec),
+        encoder: Some(&XBMCodec),
+        inspector: None,
+    },
+    Codec {
+        name: "xpm",
+        extensions: &["xpm"],
+        mime_types: &["image/x-xpixmap"],
+        magic: &[strong(&[(0, b"/* XPM */")])],
+        is_text: true,
+        decoder: Some(&XPMCodec),
+        encoder: Some(&XPMCodec),
+        inspector: None,
+    },
+];
+
+/// Find a codec by its name, or any of its extensions, ignoring case
+pub fn by_name(name: &str) -> Option<&'static Codec> {
+    let lower = name.to_lowercase();
+    CODECS
+        .iter()
+        .find(|codec| codec.name == lower || codec.extensions.contains(&lower.as_str()))
+}
+
+/// Find a codec by one of its MIME types
+pub fn by_mime_type(mime_type: &str) -> Option<&'static Codec> {
+    let lower = mime_type.to_lowercase();
+    CODECS
+        .iter()
+        .find(|codec| codec.mime_types.contains(&lower.as_str()))
+}
+
+/// Find a codec by the extension in a path
+pub fn by_path(path: &str) -> Option<&'static Codec> {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let (_, extension) = name.rsplit_once('.')?;
+    let lower = extension.to_lowercase();
+    CODECS
+        .iter()
+        .find(|codec| codec.extensions.contains(&lower.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(by_name("JPG").map(|c| c.name), Some("jpeg"));
+        assert_eq!(by_mime_type("image/webp").map(|c| c.name), Some("webp"));
+        assert_eq!(by_path("a.b/c.Rgb").map(|c| c.name), Some("sgi"));
+        assert!(by_path("c").is_none());
+    }
+
+    #[test]
+    fn test_strict_warning() {
+        let mut data = Vec::new();
+        crate::pcx::write_image(&mut data, &Image::new(2, 2)).unwrap();
+        let decoder = by_name("pcx").and_then(|c| c.decoder).unwrap();
+        let mut options = DecodeOptions::default();
+        let (_, warnings) = decoder
+            .decode_with_warnings(&mut &data[..], &options)
+            .unwrap();
+        assert!(warnings.is_empty());
+        options.lenient = true;
+        let (_, warnings) = decoder
+            .decode_with_warnings(&mut &data[..], &options)
+            .unwrap();
+        assert_eq!(warnings.len(), 1);
+    }
+}
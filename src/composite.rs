@@ -0,0 +1,184 @@
+use crate::image::{FloatRGBA, Image, ImageView, Pixel, RGBA};
+use std::convert::TryFrom;
+// Compositing places one image on top of another, using the alpha channel of
+// each to decide how much of them shows through. The operators come from
+// Porter and Duff's "Compositing Digital Images", and work on premultiplied
+// colors, where each component has already been scaled by the alpha.
+
+/// How to combine a source image with the destination underneath it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// The source on top of the destination
+    Over,
+    /// The source, only where the destination is
+    In,
+    /// The source, only where the destination isn't
+    Out,
+    /// The source on top of the destination, only where the destination is
+    Atop,
+    /// The source and the destination, only where the other isn't
+    Xor,
+}
+
+impl Operator {
+    /// The names of each operator, as the command line accepts them
+    pub const NAMES: &'static [&'static str] = &["over", "in", "out", "atop", "xor"];
+
+    /// Find the operator with a given name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "over" => Some(Operator::Over),
+            "in" => Some(Operator::In),
+            "out" => Some(Operator::Out),
+            "atop" => Some(Operator::Atop),
+            "xor" => Some(Operator::Xor),
+            _ => None,
+        }
+    }
+
+    /// How much of the source and of the destination end up in the result
+    ///
+    /// These fractions depend on the alpha of the source and of the destination.
+    fn fractions(self, source: f32, destination: f32) -> (f32, f32) {
+        match self {
+            Operator::Over => (1.0, 1.0 - source),
+            Operator::In => (destination, 0.0),
+            Operator::Out => (1.0 - destination, 0.0),
+            Operator::Atop => (destination, 1.0 - source),
+            Operator::Xor => (1.0 - destination, 1.0 - source),
+        }
+    }
+
+    /// Combine two premultiplied colors
+    fn apply(self, source: FloatRGBA, destination: FloatRGBA) -> FloatRGBA {
+        let (fs, fd) = self.fractions(source.a, destination.a);
+        FloatRGBA::new(
+            fs * source.r + fd * destination.r,
+            fs * source.g + fd * destination.g,
+            fs * source.b + fd * destination.b,
+            fs * source.a + fd * destination.a,
+        )
+    }
+}
+
+fn premultiply_float(color: FloatRGBA) -> FloatRGBA {
+    let a = color.a;
+    FloatRGBA::new(color.r * a, color.g * a, color.b * a, a)
+}
+
+fn unpremultiply_float(color: FloatRGBA) -> FloatRGBA {
+    // A transparent pixel has no color left to recover
+    if color.a <= 0.0 {
+        return FloatRGBA::new(0.0, 0.0, 0.0, 0.0);
+    }
+    let a = color.a;
+    FloatRGBA::new(color.r / a, color.g / a, color.b / a, a)
+}
+
+fn map_pixels<F: Fn(RGBA) -> RGBA>(image: &Image, f: F) -> Image {
+    Image::from_fn(image.width, image.height, |x, y| f(image.read(x, y)))
+}
+
+/// Scale the color of each pixel by its alpha
+///
+/// This loses some precision in translucent pixels, and all of the color in
+/// fully transparent ones.
+pub fn premultiply(image: &Image) -> Image {
+    map_pixels(image, |p| RGBA::from_float(premultiply_float(p.to_float())))
+}
+
+/// Undo `premultiply`, dividing the color of each pixel by its alpha
+pub fn unpremultiply(image: &Image) -> Image {
+    map_pixels(image, |p| {
+        RGBA::from_float(unpremultiply_float(p.to_float()))
+    })
+}
+
+/// Composite a source image onto a destination, with its top left corner at an offset
+///
+/// The result is the size of the destination. The offset can be negative, and
+/// any part of the source outside of the destination is cut off. Where the source
+/// doesn't cover the destination, it counts as transparent, so operators like
+/// `In` clear the rest of the destination.
+pub fn composite<'a, I: Into<ImageView<'a>>>(
+    destination: &Image,
+    source: I,
+    at: (i64, i64),
+    operator: Operator,
+) -> Image {
+    let source = source.into();
+    let transparent = FloatRGBA::new(0.0, 0.0, 0.0, 0.0);
+    Image::from_fn(destination.width, destination.height, |x, y| {
+        // An offset too far away to subtract puts the pixel outside of the source
+        let sx = i64::from(x).checked_sub(at.0).map(u32::try_from);
+        let sy = i64::from(y).checked_sub(at.1).map(u32::try_from);
+        let s = match (sx, sy) {
+            (Some(Ok(sx)), Some(Ok(sy))) => source.get(sx, sy),
+            _ => None,
+        };
+        let s = s.map_or(transparent, |p| premultiply_float(p.to_float()));
+        let d = premultiply_float(destination.read(x, y).to_float());
+        RGBA::from_float(unpremultiply_float(operator.apply(s, d)))
+    })
+}
+
+/// Composite an image over a solid background color, getting rid of transparency
+///
+/// With an opaque background, every pixel of the result is opaque.
+pub fn flatten(image: &Image, background: RGBA) -> Image {
+    let d = premultiply_float(background.to_float());
+    map_pixels(image, |p| {
+        let s = premultiply_float(p.to_float());
+        RGBA::from_float(unpremultiply_float(Operator::Over.apply(s, d)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_operators() {
+        let red = RGBA::new(255, 0, 0, 255);
+        let blue = RGBA::new(0, 0, 255, 255);
+        let clear = RGBA::new(0, 0, 0, 0);
+        let mut destination = Image::from_fn(3, 1, |_, _| blue);
+        destination.write(2, 0, clear);
+        let source = Image::from_fn(2, 1, |_, _| red);
+        let over = composite(&destination, &source, (1, 0), Operator::Over);
+        assert_eq!(over.into_iter().collect::<Vec<_>>(), vec![blue, red, red]);
+        let atop = composite(&destination, &source, (1, 0), Operator::Atop);
+        assert_eq!(atop.into_iter().collect::<Vec<_>>(), vec![blue, red, clear]);
+        let xor = composite(&destination, &source, (1, 0), Operator::Xor);
+        assert_eq!(xor.into_iter().collect::<Vec<_>>(), vec![blue, clear, red]);
+        // Whatever falls outside of the destination is cut off
+        let left = composite(&destination, &source, (-1, 0), Operator::Over);
+        assert_eq!(left.into_iter().collect::<Vec<_>>(), vec![red, blue, clear]);
+        let far = composite(&destination, &source, (i64::MIN, 0), Operator::Over);
+        assert_eq!(far.as_raw(), destination.as_raw());
+        let inside = composite(&destination, &source, (1, 0), Operator::In);
+        assert_eq!(
+            inside.into_iter().collect::<Vec<_>>(),
+            vec![clear, red, clear]
+        );
+        let out = composite(&destination, &source, (1, 0), Operator::Out);
+        assert_eq!(out.into_iter().collect::<Vec<_>>(), vec![clear, clear, red]);
+    }
+
+    #[test]
+    fn test_premultiply() {
+        let image = Image::from_fn(2, 1, |x, _| RGBA::new(200, 100, 0, 255 * x as u8));
+        let premultiplied = premultiply(&image);
+        assert_eq!(premultiplied.read(0, 0), RGBA::new(0, 0, 0, 0));
+        assert_eq!(premultiplied.read(1, 0), image.read(1, 0));
+        let half = Image::from_fn(1, 1, |_, _| RGBA::new(200, 100, 0, 128));
+        let premultiplied = premultiply(&half);
+        assert_eq!(premultiplied.read(0, 0), RGBA::new(100, 50, 0, 128));
+        assert_eq!(
+            unpremultiply(&premultiplied).read(0, 0),
+            RGBA::new(199, 100, 0, 128)
+        );
+        let flat = flatten(&half, RGBA::new(0, 0, 0, 255));
+        assert_eq!(flat.read(0, 0), RGBA::new(100, 50, 0, 255));
+    }
+}
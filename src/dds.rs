@@ -0,0 +1,570 @@
+use crate::codec::{DecodeOptions, Decoded, ImageDecoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, RGBA_BYTES, RGBA};
+use crate::limits::{LimitError, Limits};
+// The structures and parsing in this module follow Microsoft's documentation
+// of the DDS format, and of the block compression formats:
+// https://learn.microsoft.com/en-us/windows/win32/direct3ddds/dx-graphics-dds-pguide
+// https://learn.microsoft.com/en-us/windows/win32/direct3d10/d3d10-graphics-programming-guide-resources-block-compression
+
+fn u16_le(data: &[u8]) -> u16 {
+    (data[0] as u16) | ((data[1] as u16) << 8)
+}
+
+fn u32_le(data: &[u8]) -> u32 {
+    (data[0] as u32) | ((data[1] as u32) << 8) | ((data[2] as u32) << 16) | ((data[3] as u32) << 24)
+}
+
+/// Represents the errors we can encounter when reading a DDS file
+#[derive(Debug)]
+pub enum DDSError {
+    /// The format of the file doesn't match the specification
+    InvalidFormat(String),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// DDS can hold just about any format a GPU understands, and we only
+    /// handle the more common ones.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type DDSResult<T> = Result<T, DDSError>;
+
+impl From<DDSError> for MageError {
+    fn from(error: DDSError) -> Self {
+        match error {
+            DDSError::InvalidFormat(message) => MageError::invalid("dds", message),
+            DDSError::UnsupportedFormat(message) => MageError::unsupported("dds", message),
+            DDSError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for DDSError {
+    fn from(error: LimitError) -> Self {
+        DDSError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(s: S) -> DDSResult<T> {
+    Err(DDSError::InvalidFormat(s.into()))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> DDSResult<T> {
+    Err(DDSError::UnsupportedFormat(s.into()))
+}
+
+/// The pixel format flag for a four character code
+const DDPF_FOURCC: u32 = 0x4;
+/// The pixel format flag for an alpha mask being present
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+/// The caps2 flag for cube maps
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+/// The caps2 flag for volume textures
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+/// The DX10 misc flag for cube maps
+const DX10_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Which subimage of a file to decode
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// The mip level, with 0 being the full size image
+    pub mip_level: u32,
+    /// The element of a texture array, or face of a cube map
+    pub array_slice: u32,
+    /// The largest textures we accept
+    pub limits: Limits,
+}
+
+/// The bit masks used by uncompressed pixel formats
+#[derive(Clone, Copy, Debug)]
+struct Masks {
+    r: u32,
+    g: u32,
+    b: u32,
+    a: u32,
+}
+
+/// The layouts of pixel data that we know how to decode
+#[derive(Clone, Copy, Debug)]
+enum PixelFormat {
+    /// Pixels made up of components picked out by masks
+    Uncompressed { bits: u32, masks: Masks },
+    /// 4x4 blocks with two colors and interpolation between them
+    BC1,
+    /// BC1 colors, with 4 bits of explicit alpha per pixel
+    BC2,
+    /// BC1 colors, with interpolated alpha
+    BC3,
+    /// A single channel, interpolated like BC3's alpha
+    BC4 { signed: bool },
+    /// Two channels, each interpolated like BC3's alpha
+    BC5 { signed: bool },
+}
+
+impl PixelFormat {
+    /// How many bytes the image data for one surface takes up
+    ///
+    /// This is `None` if the size is too big to count.
+    fn surface_size(self, width: usize, height: usize) -> Option<usize> {
+        let blocks = width
+            .div_ceil(4)
+            .max(1)
+            .checked_mul(height.div_ceil(4).max(1))?;
+        match self {
+            PixelFormat::Uncompressed { bits, .. } => {
+                let bits = width.checked_mul(height)?.checked_mul(bits as usize)?;
+                Some(bits / 8)
+            }
+            PixelFormat::BC1 | PixelFormat::BC4 { .. } => blocks.checked_mul(8),
+            _ => blocks.checked_mul(16),
+        }
+    }
+}
+
+/// This holds the information in the headers that we make use of
+#[derive(Debug)]
+struct Header {
+    width: usize,
+    height: usize,
+    mip_count: usize,
+    /// How many separate images there are, counting each face of a cube map
+    array_size: usize,
+    format: PixelFormat,
+    /// At what index does the pixel data start
+    offset: usize,
+}
+
+fn masks_format(bits: u32, masks: Masks) -> DDSResult<PixelFormat> {
+    match bits {
+        8 | 16 | 24 | 32 => Ok(PixelFormat::Uncompressed { bits, masks }),
+        _ => unsupported_format("unsupported bit count"),
+    }
+}
+
+fn fourcc_format(fourcc: &[u8]) -> DDSResult<PixelFormat> {
+    match fourcc {
+        b"DXT1" => Ok(PixelFormat::BC1),
+        b"DXT2" | b"DXT3" => Ok(PixelFormat::BC2),
+        b"DXT4" | b"DXT5" => Ok(PixelFormat::BC3),
+        b"ATI1" | b"BC4U" => Ok(PixelFormat::BC4 { signed: false }),
+        b"BC4S" => Ok(PixelFormat::BC4 { signed: true }),
+        b"ATI2" | b"BC5U" => Ok(PixelFormat::BC5 { signed: false }),
+        b"BC5S" => Ok(PixelFormat::BC5 { signed: true }),
+        _ => unsupported_format(format!(
+            "unsupported four character code '{}'",
+            String::from_utf8_lossy(fourcc)
+        )),
+    }
+}
+
+fn dxgi_format(format: u32) -> DDSResult<PixelFormat> {
+    let rgba = Masks {
+        r: 0x0000_00FF,
+        g: 0x0000_FF00,
+        b: 0x00FF_0000,
+        a: 0xFF00_0000,
+    };
+    let bgra = Masks {
+        r: 0x00FF_0000,
+        g: 0x0000_FF00,
+        b: 0x0000_00FF,
+        a: 0xFF00_0000,
+    };
+    match format {
+        27..=29 => masks_format(32, rgba),
+        87 | 90 | 91 => masks_format(32, bgra),
+        88 | 92 | 93 => masks_format(32, Masks { a: 0, ..bgra }),
+        61 => masks_format(
+            8,
+            Masks {
+                r: 0xFF,
+                g: 0,
+                b: 0,
+                a: 0,
+            },
+        ),
+        70..=72 => Ok(PixelFormat::BC1),
+        73..=75 => Ok(PixelFormat::BC2),
+        76..=78 => Ok(PixelFormat::BC3),
+        79 | 80 => Ok(PixelFormat::BC4 { signed: false }),
+        81 => Ok(PixelFormat::BC4 { signed: true }),
+        82 | 83 => Ok(PixelFormat::BC5 { signed: false }),
+        84 => Ok(PixelFormat::BC5 { signed: true }),
+        _ => unsupported_format(format!("unsupported DXGI format {}", format)),
+    }
+}
+
+fn parse_header(data: &[u8]) -> DDSResult<Header> {
+    if data.len() < 128 {
+        return invalid_format("insufficient header length");
+    }
+    if &data[0..4] != b"DDS " {
+        return invalid_format("header didn't start with 'DDS '");
+    }
+    if u32_le(&data[4..]) != 124 || u32_le(&data[76..]) != 32 {
+        return invalid_format("wrong header size");
+    }
+    let height = u32_le(&data[12..]) as usize;
+    let width = u32_le(&data[16..]) as usize;
+    // Halving the size can't go past 1x1, whatever the file says
+    let most_mips = 32 - (height.max(width) as u32).leading_zeros();
+    let mip_count = (u32_le(&data[28..]) as usize).clamp(1, most_mips.max(1) as usize);
+    let pf_flags = u32_le(&data[80..]);
+    let fourcc = &data[84..88];
+    let caps2 = u32_le(&data[112..]);
+    if caps2 & DDSCAPS2_VOLUME != 0 {
+        return unsupported_format("volume textures are not supported");
+    }
+    // Cube maps store each face present as a separate image
+    let mut array_size = if caps2 & DDSCAPS2_CUBEMAP != 0 {
+        ((caps2 >> 10) & 0x3F).count_ones().max(1) as usize
+    } else {
+        1
+    };
+    let mut offset = 128;
+    let format = if pf_flags & DDPF_FOURCC != 0 && fourcc == b"DX10" {
+        if data.len() < 148 {
+            return invalid_format("insufficient DX10 header length");
+        }
+        let format = dxgi_format(u32_le(&data[128..]))?;
+        let misc = u32_le(&data[136..]);
+        array_size = (u32_le(&data[140..]) as usize).max(1);
+        if misc & DX10_MISC_TEXTURECUBE != 0 {
+            array_size *= 6;
+        }
+        offset = 148;
+        format
+    } else if pf_flags & DDPF_FOURCC != 0 {
+        fourcc_format(fourcc)?
+    } else {
+        let alpha = if pf_flags & DDPF_ALPHAPIXELS != 0 || u32_le(&data[92..]) == 0 {
+            u32_le(&data[104..])
+        } else {
+            0
+        };
+        let masks = Masks {
+            r: u32_le(&data[92..]),
+            g: u32_le(&data[96..]),
+            b: u32_le(&data[100..]),
+            a: alpha,
+        };
+        masks_format(u32_le(&data[88..]), masks)?
+    };
+    Ok(Header {
+        width,
+        height,
+        mip_count,
+        array_size,
+        format,
+        offset,
+    })
+}
+
+/// Extract a component picked out by a mask, scaled to 8 bits
+fn masked(value: u32, mask: u32) -> Option<u8> {
+    if mask == 0 {
+        return None;
+    }
+    let shifted = (value & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+    Some(((u64::from(shifted) * 255 + u64::from(max) / 2) / u64::from(max)) as u8)
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1F) as u8;
+    let g = ((color >> 5) & 0x3F) as u8;
+    let b = (color & 0x1F) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+/// Decode the colors of a BC1 block, which are also used by BC2 and BC3
+///
+/// Only BC1 can use the mode with a transparent color.
+fn decode_color_block(block: &[u8], allow_transparent: bool) -> [RGBA; 16] {
+    let c0 = u16_le(block);
+    let c1 = u16_le(&block[2..]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u16, wb: u16, total: u16| {
+        let mut out = [0; 3];
+        for i in 0..3 {
+            out[i] = ((u16::from(a[i]) * wa + u16::from(b[i]) * wb) / total) as u8;
+        }
+        RGBA::new(out[0], out[1], out[2], 0xFF)
+    };
+    let palette = if c0 > c1 || !allow_transparent {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [
+            mix(1, 0, 1),
+            mix(0, 1, 1),
+            mix(1, 1, 2),
+            RGBA::new(0, 0, 0, 0),
+        ]
+    };
+    let indices = u32_le(&block[4..]);
+    let mut out = [RGBA::new(0, 0, 0, 0); 16];
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = palette[((indices >> (2 * i)) & 0x3) as usize];
+    }
+    out
+}
+
+/// Decode an interpolated channel, as used by BC3's alpha, BC4 and BC5
+///
+/// Signed channels are shifted up to the unsigned range.
+fn decode_channel_block(block: &[u8], signed: bool) -> [u8; 16] {
+    let (e0, e1) = if signed {
+        // -128 and -127 both mean -1.0
+        let e = |b: u8| i32::from((b as i8).max(-127));
+        (e(block[0]), e(block[1]))
+    } else {
+        (i32::from(block[0]), i32::from(block[1]))
+    };
+    let (min, max) = if signed { (-127, 127) } else { (0, 255) };
+    let mut palette = [0i32; 8];
+    palette[0] = e0;
+    palette[1] = e1;
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * e0 + i as i32 * e1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as i32) * e0 + i as i32 * e1) / 5;
+        }
+        palette[6] = min;
+        palette[7] = max;
+    }
+    let mut indices = 0u64;
+    for (i, &byte) in block[2..8].iter().enumerate() {
+        indices |= u64::from(byte) << (8 * i);
+    }
+    let mut out = [0; 16];
+    for (i, value) in out.iter_mut().enumerate() {
+        let v = palette[((indices >> (3 * i)) & 0x7) as usize];
+        *value = if signed {
+            (v + 128).clamp(0, 255) as u8
+        } else {
+            v as u8
+        };
+    }
+    out
+}
+
+/// Decode a 4x4 block of pixels, in row major order
+fn decode_block(format: PixelFormat, block: &[u8]) -> [RGBA; 16] {
+    match format {
+        PixelFormat::BC1 => decode_color_block(block, true),
+        PixelFormat::BC2 => {
+            let mut pixels = decode_color_block(&block[8..], false);
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let alpha = (block[i / 2] >> (4 * (i % 2))) & 0xF;
+                pixel.a = alpha * 0x11;
+            }
+            pixels
+        }
+        PixelFormat::BC3 => {
+            let mut pixels = decode_color_block(&block[8..], false);
+            let alpha = decode_channel_block(block, false);
+            for (pixel, &a) in pixels.iter_mut().zip(&alpha) {
+                pixel.a = a;
+            }
+            pixels
+        }
+        PixelFormat::BC4 { signed } => {
+            let red = decode_channel_block(block, signed);
+            let mut pixels = [RGBA::new(0, 0, 0, 0xFF); 16];
+            for (pixel, &r) in pixels.iter_mut().zip(&red) {
+                *pixel = RGBA::new(r, r, r, 0xFF);
+            }
+            pixels
+        }
+        PixelFormat::BC5 { signed } => {
+            let red = decode_channel_block(block, signed);
+            let green = decode_channel_block(&block[8..], signed);
+            let mut pixels = [RGBA::new(0, 0, 0, 0xFF); 16];
+            for i in 0..16 {
+                pixels[i] = RGBA::new(red[i], green[i], 0, 0xFF);
+            }
+            pixels
+        }
+        PixelFormat::Uncompressed { .. } => unreachable!("uncompressed pixels aren't in blocks"),
+    }
+}
+
+fn decode_surface(format: PixelFormat, width: usize, height: usize, data: &[u8]) -> Image {
+    let mut image = Image::new(width as u32, height as u32);
+    if let PixelFormat::Uncompressed { bits, masks } = format {
+        let bytes = bits as usize / 8;
+        for y in 0..height {
+            for x in 0..width {
+                let i = (y * width + x) * bytes;
+                let mut value = 0;
+                for (j, &byte) in data[i..i + bytes].iter().enumerate() {
+                    value |= u32::from(byte) << (8 * j);
+                }
+                let r = masked(value, masks.r).unwrap_or(0);
+                // Single channel formats are shown as grayscale
+                let g = masked(value, masks.g).unwrap_or(if masks.b == 0 { r } else { 0 });
+                let b = masked(value, masks.b).unwrap_or(if masks.g == 0 { r } else { 0 });
+                let a = masked(value, masks.a).unwrap_or(0xFF);
+                image.write(x as u32, y as u32, RGBA::new(r, g, b, a));
+            }
+        }
+        return image;
+    }
+    // A single block always has a small size
+    let block_size = format.surface_size(4, 4).unwrap();
+    let blocks_wide = width.div_ceil(4).max(1);
+    for (i, block) in data.chunks_exact(block_size).enumerate() {
+        let bx = (i % blocks_wide) * 4;
+        let by = (i / blocks_wide) * 4;
+        for (j, &pixel) in decode_block(format, block).iter().enumerate() {
+            let (x, y) = (bx + j % 4, by + j / 4);
+            // Blocks on the edge can extend past the image
+            if x < width && y < height {
+                image.write(x as u32, y as u32, pixel);
+            }
+        }
+    }
+    image
+}
+
+/// Decode the full size image of the first slice in a file
+pub fn parse_image(data: &[u8]) -> DDSResult<Image> {
+    parse_image_with(data, Options::default())
+}
+
+/// Decode a specific mip level and array slice of a file
+pub fn parse_image_with(data: &[u8], options: Options) -> DDSResult<Image> {
+    let header = parse_header(data)?;
+    let limits = options.limits;
+    limits.check_frames(header.array_size as u64)?;
+    let (width, height) = (header.width as u64, header.height as u64);
+    limits.check_image(width, height, RGBA_BYTES as u64)?;
+    let mip = options.mip_level as usize;
+    let slice = options.array_slice as usize;
+    if mip >= header.mip_count {
+        return invalid_format(format!("file only has {} mip levels", header.mip_count));
+    }
+    if slice >= header.array_size {
+        return invalid_format(format!("file only has {} slices", header.array_size));
+    }
+    let size_of = |level: usize| {
+        let width = (header.width >> level).max(1);
+        let height = (header.height >> level).max(1);
+        header.format.surface_size(width, height)
+    };
+    let sum_sizes =
+        |levels: usize| (0..levels).try_fold(0usize, |sum, l| sum.checked_add(size_of(l)?));
+    // Each slice holds its whole chain of mip levels
+    let range = sum_sizes(header.mip_count).and_then(|chain_size| {
+        let start = slice
+            .checked_mul(chain_size)?
+            .checked_add(sum_sizes(mip)?)?
+            .checked_add(header.offset)?;
+        Some(start..start.checked_add(size_of(mip)?)?)
+    });
+    let range = match range {
+        Some(range) if range.end <= data.len() => range,
+        _ => return invalid_format("insufficient pixel data"),
+    };
+    let width = (header.width >> mip).max(1);
+    let height = (header.height >> mip).max(1);
+    Ok(decode_surface(header.format, width, height, &data[range]))
+}
+
+/// Decodes DirectDraw Surface textures, at any mip level or array slice
+pub struct DDSCodec;
+
+impl ImageDecoder for DDSCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        let texture_options = Options {
+            mip_level: options.mip_level,
+            array_slice: options.array_slice,
+            limits: options.limits,
+        };
+        parse_image_with(data, texture_options)
+            .map(Decoded::Low)
+            .map_err(MageError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bc1_block() {
+        // Pure red and pure blue, with every index used once in the first row
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0x00, 0x00, 0x00];
+        let pixels = decode_block(PixelFormat::BC1, &block);
+        assert_eq!(pixels[0], RGBA::new(0xFF, 0, 0, 0xFF));
+        assert_eq!(pixels[1], RGBA::new(0, 0, 0xFF, 0xFF));
+        assert_eq!(pixels[2], RGBA::new(0xAA, 0, 0x55, 0xFF));
+        assert_eq!(pixels[3], RGBA::new(0x55, 0, 0xAA, 0xFF));
+        assert_eq!(pixels[4], RGBA::new(0xFF, 0, 0, 0xFF));
+    }
+
+    #[test]
+    fn test_channel_block() {
+        let mut block = [0xFF, 0x00, 0, 0, 0, 0, 0, 0];
+        // The second pixel uses index 1, the third index 2
+        block[2] = 0b1000_1000;
+        let values = decode_channel_block(&block, false);
+        assert_eq!(values[0], 0xFF);
+        assert_eq!(values[1], 0x00);
+        assert_eq!(values[2], 0xDA);
+    }
+
+    #[test]
+    fn test_mip_selection() {
+        // An 8x4 DXT1 texture with 2 mips, each a single solid color
+        let mut data = vec![0; 128];
+        data[0..4].copy_from_slice(b"DDS ");
+        data[4] = 124;
+        data[12] = 4;
+        data[16] = 8;
+        data[28] = 2;
+        data[76] = 32;
+        data[80] = DDPF_FOURCC as u8;
+        data[84..88].copy_from_slice(b"DXT1");
+        let red = [0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0];
+        let blue = [0x1F, 0x00, 0x1F, 0x00, 0, 0, 0, 0];
+        data.extend_from_slice(&red);
+        data.extend_from_slice(&red);
+        data.extend_from_slice(&blue);
+        let options = Options {
+            mip_level: 1,
+            ..Options::default()
+        };
+        let image = parse_image_with(&data, options).unwrap();
+        assert_eq!(image.width, 4);
+        assert_eq!(image.height, 2);
+        assert_eq!(image.read(3, 1), RGBA::new(0, 0, 0xFF, 0xFF));
+        let full = parse_image(&data).unwrap();
+        assert_eq!(full.read(7, 3), RGBA::new(0xFF, 0, 0, 0xFF));
+        // An 8x4 texture can't have more than 4 mip levels
+        data[28] = 100;
+        assert_eq!(parse_header(&data).unwrap().mip_count, 4);
+        let options = Options {
+            mip_level: 50,
+            ..Options::default()
+        };
+        assert!(parse_image_with(&data, options).is_err());
+        // The biggest textures need more data than we have, without overflowing
+        data[28] = 1;
+        data[12..20].copy_from_slice(&[0xFF; 8]);
+        let options = Options {
+            limits: Limits::unlimited(),
+            ..Options::default()
+        };
+        assert!(parse_image_with(&data, options).is_err());
+    }
+}
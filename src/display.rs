@@ -1,3 +1,4 @@
+use std::fmt;
 use std::thread;
 use std::time::Duration;
 
@@ -5,8 +6,20 @@ use crate::sdl2::event::{Event, WindowEvent};
 use crate::sdl2::keyboard::Keycode;
 use crate::sdl2::pixels::{Color, PixelFormatEnum};
 use crate::sdl2::rect::Rect;
+use crate::sdl2::render::{Texture, UpdateTextureError};
 
-use crate::image::Image;
+use mage::{Image, ImageView, MageError, MageResult};
+
+/// Wrap up an error coming from SDL
+fn sdl_error<E: fmt::Display>(error: E) -> MageError {
+    MageError::Display(error.to_string())
+}
+
+/// Fill a texture with the pixels in an image, or a view of one
+fn fill(texture: &mut Texture, image: ImageView) -> Result<(), UpdateTextureError> {
+    let pitch = 4 * image.stride();
+    texture.update(None, image.as_raw(), pitch)
+}
 
 /// Holds the information in a display
 ///
@@ -22,8 +35,8 @@ struct Display {
 
 impl Display {
     fn new(image: Image) -> Display {
-        let width = image.width as u32;
-        let height = image.height as u32;
+        let width = image.width;
+        let height = image.height;
         Display {
             image,
             width,
@@ -49,49 +62,49 @@ impl Display {
     }
 
     fn handle_window(&mut self, event: WindowEvent) {
-        match event {
-            WindowEvent::SizeChanged(x, y) => {
-                self.width = x as u32;
-                self.height = y as u32;
-            }
-            _ => {}
+        if let WindowEvent::SizeChanged(x, y) = event {
+            self.width = x as u32;
+            self.height = y as u32;
         }
     }
 
-    fn run(&mut self) {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+    fn run(&mut self) -> MageResult<()> {
+        let sdl_context = sdl2::init().map_err(sdl_error)?;
+        let video_subsystem = sdl_context.video().map_err(sdl_error)?;
         let window = video_subsystem
             .window("mage", self.width, self.height)
             .resizable()
             .position_centered()
             .build()
-            .unwrap();
-        let mut canvas = window.into_canvas().build().unwrap();
+            .map_err(sdl_error)?;
+        let mut canvas = window.into_canvas().build().map_err(sdl_error)?;
         canvas.set_draw_color(Color::RGB(0, 255, 255));
         canvas.clear();
         canvas.present();
 
         let creator = canvas.texture_creator();
+        // Our pixels are stored as R, G, B, and A bytes, which is what RGBA32
+        // means on any platform, unlike RGBA8888, which is a packed integer
         let mut texture = creator
-            .create_texture_static(Some(PixelFormatEnum::RGBA8888), self.width, self.height)
-            .unwrap();
-        self.image.fill(&mut texture).unwrap();
-        canvas.copy(&texture, None, None).unwrap();
+            .create_texture_static(Some(PixelFormatEnum::RGBA32), self.width, self.height)
+            .map_err(sdl_error)?;
+        fill(&mut texture, self.image.as_view()).map_err(sdl_error)?;
+        canvas.copy(&texture, None, None).map_err(sdl_error)?;
 
-        let mut event_pump = sdl_context.event_pump().unwrap();
+        let mut event_pump = sdl_context.event_pump().map_err(sdl_error)?;
         while !self.should_end {
             for event in event_pump.poll_iter() {
                 self.handle(event);
             }
             let dest = Rect::new(0, 0, self.width, self.height);
-            canvas.copy(&texture, None, dest).unwrap();
+            canvas.copy(&texture, None, dest).map_err(sdl_error)?;
             canvas.present();
             thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
         }
+        Ok(())
     }
 }
 
-pub fn display(image: Image) {
-    Display::new(image).run();
+pub fn display(image: Image) -> MageResult<()> {
+    Display::new(image).run()
 }
@@ -0,0 +1,111 @@
+use std::fmt::Write;
+// This module prints the bytes of a file next to what they mean, which is
+// the best way to learn how a format works. Each format splits its files
+// into labelled regions, and any bytes left over are flagged.
+
+/// A run of bytes in a file, and what they're for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub offset: usize,
+    pub len: usize,
+    pub label: String,
+}
+
+impl Region {
+    pub fn new<S: Into<String>>(offset: usize, len: usize, label: S) -> Self {
+        Region {
+            offset,
+            len,
+            label: label.into(),
+        }
+    }
+}
+
+/// The most bytes of a single region we show, before eliding the rest
+const MAX_BYTES_SHOWN: usize = 16;
+
+/// Find the parts of a file that none of the regions cover
+///
+/// This returns the offset and length of each gap.
+pub fn unaccounted(len: usize, regions: &[Region]) -> Vec<(usize, usize)> {
+    let mut sorted: Vec<&Region> = regions.iter().collect();
+    sorted.sort_by_key(|r| r.offset);
+    let mut gaps = Vec::new();
+    let mut covered = 0;
+    for region in sorted {
+        let start = region.offset.min(len);
+        if start > covered {
+            gaps.push((covered, start - covered));
+        }
+        covered = covered.max(region.offset.saturating_add(region.len).min(len));
+    }
+    if covered < len {
+        gaps.push((covered, len - covered));
+    }
+    gaps
+}
+
+fn hex_line(out: &mut String, data: &[u8], offset: usize, len: usize, label: &str) {
+    let end = offset.saturating_add(len).min(data.len());
+    let bytes = &data[offset.min(end)..end];
+    let mut hex = String::new();
+    for b in bytes.iter().take(MAX_BYTES_SHOWN) {
+        let _ = write!(hex, "{:02X} ", b);
+    }
+    if bytes.len() > MAX_BYTES_SHOWN {
+        hex.push_str("..");
+    }
+    let _ = writeln!(out, "0x{:08X}  {:<50} {}", offset, hex, label);
+}
+
+/// Print a hex dump of a file, annotated by its regions
+///
+/// Bytes that aren't in any region are flagged, and regions that run past
+/// the end of the file are marked as truncated.
+pub fn render(data: &[u8], regions: &[Region]) -> String {
+    let mut lines: Vec<(usize, String, usize)> = regions
+        .iter()
+        .map(|r| {
+            let end = r.offset.saturating_add(r.len);
+            let label = if end > data.len() {
+                format!(
+                    "{} (truncated, {} bytes missing)",
+                    r.label,
+                    end - data.len().max(r.offset)
+                )
+            } else {
+                r.label.clone()
+            };
+            (r.offset, label, r.len)
+        })
+        .collect();
+    for (offset, len) in unaccounted(data.len(), regions) {
+        let label = format!("?? unaccounted for ({} bytes)", len);
+        lines.push((offset, label, len));
+    }
+    lines.sort_by_key(|&(offset, _, _)| offset);
+    let mut out = String::new();
+    for (offset, label, len) in lines {
+        hex_line(&mut out, data, offset, len, &label);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unaccounted() {
+        let regions = [
+            Region::new(2, 2, "a"),
+            Region::new(0, 1, "b"),
+            Region::new(6, 10, "c"),
+        ];
+        assert_eq!(unaccounted(8, &regions), vec![(1, 1), (4, 2)]);
+        assert_eq!(unaccounted(20, &regions), vec![(1, 1), (4, 2), (16, 4)]);
+        let dump = render(&[0xAB; 8], &regions);
+        assert!(dump.contains("0x00000004  AB AB"));
+        assert!(dump.contains("(truncated, 8 bytes missing)"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+// Each format module has its own error type, describing what can go wrong
+// when parsing that format. Outside of those modules, we use `MageError`,
+// which every one of those errors can be converted into.
+
+/// Represents all of the errors that can happen in the program
+#[derive(Debug)]
+pub enum MageError {
+    /// Reading or writing some file failed
+    Io(io::Error),
+    /// The contents of a file don't match its format
+    InvalidFormat {
+        /// The name of the format, as in the codec registry
+        format: &'static str,
+        message: String,
+        /// The offset of the offending bytes in the file, if we know it
+        offset: Option<usize>,
+    },
+    /// A file is in a format, or uses features, that we don't support
+    ///
+    /// The format is missing if we couldn't figure out what it was.
+    UnsupportedFormat {
+        format: Option<&'static str>,
+        message: String,
+    },
+    /// An image is larger than the limits we've been given
+    LimitsExceeded(String),
+    /// Something went wrong while showing an image in a window
+    Display(String),
+}
+
+pub type MageResult<T> = Result<T, MageError>;
+
+impl MageError {
+    /// Create an error for an invalid file, without an offset
+    pub fn invalid<S: Into<String>>(format: &'static str, message: S) -> Self {
+        MageError::InvalidFormat {
+            format,
+            message: message.into(),
+            offset: None,
+        }
+    }
+
+    /// Create an error for a file we don't support
+    pub fn unsupported<S: Into<String>>(format: &'static str, message: S) -> Self {
+        MageError::UnsupportedFormat {
+            format: Some(format),
+            message: message.into(),
+        }
+    }
+
+    /// Create an error for data whose format we couldn't detect
+    ///
+    /// `what` describes the data, like a quoted path, or "the data".
+    pub fn unknown_format(what: &str) -> Self {
+        MageError::UnsupportedFormat {
+            format: None,
+            message: format!("couldn't detect the format of {}", what),
+        }
+    }
+
+    /// Add the path of a file to an I/O error involving it
+    pub fn with_path<P: AsRef<Path>>(path: P, error: io::Error) -> Self {
+        let message = format!("{}: {}", path.as_ref().display(), error);
+        MageError::Io(io::Error::new(error.kind(), message))
+    }
+
+    /// The code the program should exit with after this error
+    ///
+    /// Each kind of error has its own code, so that scripts can tell them
+    /// apart. A code of 1 is left for bad command line arguments, and the
+    /// program picks codes after these for its own failures: 7 is for
+    /// batches where some of the files couldn't be converted.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MageError::Io(_) => 2,
+            MageError::InvalidFormat { .. } => 3,
+            MageError::UnsupportedFormat { .. } => 4,
+            MageError::LimitsExceeded(_) => 5,
+            MageError::Display(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for MageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MageError::Io(e) => write!(f, "{}", e),
+            MageError::InvalidFormat {
+                format,
+                message,
+                offset: Some(offset),
+            } => write!(
+                f,
+                "invalid {} file: {} (at byte {})",
+                format, message, offset
+            ),
+            MageError::InvalidFormat {
+                format, message, ..
+            } => write!(f, "invalid {} file: {}", format, message),
+            MageError::UnsupportedFormat {
+                format: Some(format),
+                message,
+            } => write!(f, "unsupported {} file: {}", format, message),
+            MageError::UnsupportedFormat { message, .. } => write!(f, "{}", message),
+            MageError::LimitsExceeded(message) => write!(f, "limits exceeded: {}", message),
+            MageError::Display(message) => write!(f, "couldn't display the image: {}", message),
+        }
+    }
+}
+
+impl error::Error for MageError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MageError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MageError {
+    fn from(error: io::Error) -> Self {
+        MageError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = MageError::InvalidFormat {
+            format: "bmp",
+            message: "plane count not 1".into(),
+            offset: Some(26),
+        };
+        assert_eq!(
+            error.to_string(),
+            "invalid bmp file: plane count not 1 (at byte 26)"
+        );
+        assert_eq!(error.exit_code(), 3);
+    }
+}
@@ -0,0 +1,886 @@
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{FloatImage, FloatRGBA, ImageView};
+use crate::info::{Info, Value};
+use crate::limits::{LimitError, Limits};
+use crate::zlib;
+use std::convert::TryFrom;
+use std::io;
+use std::mem;
+// The structures in this module follow the OpenEXR file layout document:
+// https://openexr.com/en/latest/OpenEXRFileLayout.html
+// Only single part scanline images are handled, not tiled or deep ones.
+
+fn u32_le(data: &[u8]) -> u32 {
+    (data[0] as u32) | ((data[1] as u32) << 8) | ((data[2] as u32) << 16) | ((data[3] as u32) << 24)
+}
+
+fn i32_le(data: &[u8]) -> i32 {
+    u32_le(data) as i32
+}
+
+fn u64_le(data: &[u8]) -> u64 {
+    u64::from(u32_le(data)) | (u64::from(u32_le(&data[4..])) << 32)
+}
+
+/// Represents the errors we can encounter when reading an OpenEXR file
+#[derive(Debug)]
+pub enum EXRError {
+    /// The format of the file doesn't match the specification
+    ///
+    /// This also holds the offset of the offending bytes in the file.
+    InvalidFormat(String, usize),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This is the case for tiled, deep, or multi-part files, as well as
+    /// the more elaborate compression methods like PIZ or DWA.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type EXRResult<T> = Result<T, EXRError>;
+
+impl From<EXRError> for MageError {
+    fn from(error: EXRError) -> Self {
+        match error {
+            EXRError::InvalidFormat(message, offset) => MageError::InvalidFormat {
+                format: "exr",
+                message,
+                offset: Some(offset),
+            },
+            EXRError::UnsupportedFormat(message) => MageError::unsupported("exr", message),
+            EXRError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for EXRError {
+    fn from(error: LimitError) -> Self {
+        EXRError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(offset: usize, s: S) -> EXRResult<T> {
+    Err(EXRError::InvalidFormat(s.into(), offset))
+}
+
+/// Move the offset of an error in part of a file, starting at `start`
+///
+/// The functions parsing attribute values and blocks only know about offsets
+/// inside of the slice they're given, so their callers use this to fix them up.
+fn offset_by<T>(start: usize, result: EXRResult<T>) -> EXRResult<T> {
+    result.map_err(|error| match error {
+        EXRError::InvalidFormat(message, offset) => {
+            EXRError::InvalidFormat(message, start + offset)
+        }
+        error => error,
+    })
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> EXRResult<T> {
+    Err(EXRError::UnsupportedFormat(s.into()))
+}
+
+/// Convert a 16 bit floating point number into a normal float
+fn half_to_f32(half: u16) -> f32 {
+    let sign = u32::from(half >> 15) << 31;
+    let exponent = u32::from((half >> 10) & 0x1F);
+    let mantissa = u32::from(half & 0x3FF);
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        0 => {
+            // Subnormal halves are normal floats
+            let value = mantissa as f32 * 2f32.powi(-24);
+            return if sign != 0 { -value } else { value };
+        }
+        31 => sign | 0x7F80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// Convert a float into a 16 bit float, rounding to the nearest even value
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+    if exponent == 255 {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7C00 | nan;
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 31 {
+        return sign | 0x7C00;
+    }
+    let (mut half, shift, full) = if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        (sign | (full >> shift) as u16, shift, full)
+    } else {
+        let half = sign | ((half_exponent as u16) << 10) | (mantissa >> 13) as u16;
+        (half, 13, mantissa)
+    };
+    let remainder = full & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    // A carry here moves into the exponent, which is exactly what we want
+    if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+        half += 1;
+    }
+    half
+}
+
+/// How the numbers in a channel are stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelType {
+    /// 32 bit unsigned integers
+    Uint,
+    /// 16 bit floating point numbers
+    Half,
+    /// 32 bit floating point numbers
+    Float,
+}
+
+impl PixelType {
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            _ => 4,
+        }
+    }
+}
+
+/// The ways the pixel data in a file can be compressed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Uncompressed,
+    /// Run length encoding, one scanline at a time
+    RunLength,
+    /// zlib compression, one scanline at a time
+    ZipSingle,
+    /// zlib compression, 16 scanlines at a time
+    Zip,
+}
+
+impl Compression {
+    fn lines_per_block(self) -> usize {
+        match self {
+            Compression::Zip => 16,
+            _ => 1,
+        }
+    }
+}
+
+impl From<Compression> for u8 {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Uncompressed => 0,
+            Compression::RunLength => 1,
+            Compression::ZipSingle => 2,
+            Compression::Zip => 3,
+        }
+    }
+}
+
+/// The options used when writing a file
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub compression: Compression,
+    /// This should be either `Half` or `Float`
+    pub pixel_type: PixelType,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            compression: Compression::Zip,
+            pixel_type: PixelType::Half,
+        }
+    }
+}
+
+/// A description of a single channel of the image
+#[derive(Debug)]
+struct Channel {
+    name: String,
+    pixel_type: PixelType,
+}
+
+/// The attributes of the header that we make use of
+#[derive(Debug)]
+struct Header {
+    /// The channels, which are stored in this order in each scanline
+    channels: Vec<Channel>,
+    compression: Compression,
+    /// The bounds of the pixels stored, as (x min, y min, x max, y max)
+    data_window: (i32, i32, i32, i32),
+    /// Where the data window is in the file, to point at when it's invalid
+    data_window_offset: usize,
+    /// At what index does the offset table start
+    offset: usize,
+}
+
+/// Read a null terminated string, advancing the index past it
+fn read_string(data: &[u8], i: &mut usize) -> EXRResult<String> {
+    let end = match data[*i..].iter().position(|&b| b == 0) {
+        Some(len) => *i + len,
+        None => return invalid_format(data.len(), "unterminated string"),
+    };
+    let s = String::from_utf8_lossy(&data[*i..end]).into_owned();
+    *i = end + 1;
+    Ok(s)
+}
+
+fn parse_channels(data: &[u8]) -> EXRResult<Vec<Channel>> {
+    let mut channels = Vec::new();
+    let mut i = 0;
+    loop {
+        let name = read_string(data, &mut i)?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        if data.len() < i + 16 {
+            return invalid_format(data.len(), "insufficient channel length");
+        }
+        let pixel_type = match u32_le(&data[i..]) {
+            0 => PixelType::Uint,
+            1 => PixelType::Half,
+            2 => PixelType::Float,
+            _ => return invalid_format(i, "unknown pixel type"),
+        };
+        let x_sampling = i32_le(&data[i + 8..]);
+        let y_sampling = i32_le(&data[i + 12..]);
+        if x_sampling != 1 || y_sampling != 1 {
+            return unsupported_format("subsampled channels are not supported");
+        }
+        channels.push(Channel { name, pixel_type });
+        i += 16;
+    }
+}
+
+/// Read the name, type, and value of an attribute, advancing past it
+///
+/// This returns `None` at the null byte ending the header.
+fn read_attribute<'a>(
+    data: &'a [u8],
+    i: &mut usize,
+) -> EXRResult<Option<(String, String, &'a [u8])>> {
+    if *i >= data.len() {
+        return invalid_format(data.len(), "unterminated header");
+    }
+    let name = read_string(data, i)?;
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let kind = read_string(data, i)?;
+    if data.len() < *i + 4 {
+        return invalid_format(data.len(), "insufficient attribute length");
+    }
+    let size = u32_le(&data[*i..]) as usize;
+    *i += 4;
+    if data.len() - *i < size {
+        return invalid_format(*i - 4, "attribute extends past the end of the file");
+    }
+    let value = &data[*i..*i + size];
+    *i += size;
+    Ok(Some((name, kind, value)))
+}
+
+fn parse_header(data: &[u8]) -> EXRResult<Header> {
+    if data.len() < 8 {
+        return invalid_format(data.len(), "insufficient header length");
+    }
+    if data[0..4] != [0x76, 0x2F, 0x31, 0x01] {
+        return invalid_format(0, "wrong magic number");
+    }
+    if data[4] != 2 {
+        return unsupported_format("unknown version");
+    }
+    let flags = u32_le(&data[4..]) >> 8;
+    if flags & 0x2 != 0 {
+        return unsupported_format("tiled images are not supported");
+    }
+    if flags & 0x8 != 0 {
+        return unsupported_format("deep images are not supported");
+    }
+    if flags & 0x10 != 0 {
+        return unsupported_format("multi-part files are not supported");
+    }
+    let mut channels = None;
+    let mut compression = None;
+    let mut data_window = None;
+    let mut i = 8;
+    while let Some((name, _, value)) = read_attribute(data, &mut i)? {
+        let start = i - value.len();
+        match name.as_str() {
+            "channels" => channels = Some(offset_by(start, parse_channels(value))?),
+            "compression" if value.len() == 1 => {
+                compression = Some(match value[0] {
+                    0 => Compression::Uncompressed,
+                    1 => Compression::RunLength,
+                    2 => Compression::ZipSingle,
+                    3 => Compression::Zip,
+                    _ => return unsupported_format("unsupported compression"),
+                });
+            }
+            "dataWindow" if value.len() == 16 => {
+                let window = (
+                    i32_le(value),
+                    i32_le(&value[4..]),
+                    i32_le(&value[8..]),
+                    i32_le(&value[12..]),
+                );
+                data_window = Some((window, start));
+            }
+            _ => {}
+        }
+    }
+    match (channels, compression, data_window) {
+        (Some(channels), Some(compression), Some((data_window, data_window_offset))) => {
+            Ok(Header {
+                channels,
+                compression,
+                data_window,
+                data_window_offset,
+                offset: i,
+            })
+        }
+        // Point at the null byte ending the header
+        _ => invalid_format(i - 1, "missing required attribute"),
+    }
+}
+
+fn rle_decompress(data: &[u8], expected: usize) -> EXRResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected);
+    let mut i = 0;
+    while i < data.len() {
+        let start = i;
+        let count = data[i] as i8;
+        i += 1;
+        if count < 0 {
+            let count = -(count as isize) as usize;
+            if i + count > data.len() {
+                return invalid_format(start, "run extends past the end of the block");
+            }
+            out.extend_from_slice(&data[i..i + count]);
+            i += count;
+        } else {
+            if i >= data.len() {
+                return invalid_format(start, "run extends past the end of the block");
+            }
+            let count = count as usize + 1;
+            out.extend(std::iter::repeat_n(data[i], count));
+            i += 1;
+        }
+        if out.len() > expected {
+            return invalid_format(start, "block decompresses to too many bytes");
+        }
+    }
+    Ok(out)
+}
+
+fn rle_compress(data: &[u8]) -> Vec<u8> {
+    let starts_run =
+        |i: usize| i + 2 < data.len() && data[i] == data[i + 1] && data[i] == data[i + 2];
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        if starts_run(i) {
+            let mut run = 3;
+            while i + run < data.len() && run < 128 && data[i + run] == data[i] {
+                run += 1;
+            }
+            out.push((run - 1) as u8);
+            out.push(data[i]);
+            i += run;
+            continue;
+        }
+        let start = i;
+        while i < data.len() && i - start < 127 && !starts_run(i) {
+            i += 1;
+        }
+        out.push((-((i - start) as i8)) as u8);
+        out.extend_from_slice(&data[start..i]);
+    }
+    out
+}
+
+/// Undo the preprocessing applied before RLE or ZIP compression
+///
+/// Each byte is stored as the difference from the last, and the bytes were
+/// split into two halves, with the odd bytes making up the second half.
+fn unpredict(mut data: Vec<u8>) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+    let half = data.len().div_ceil(2);
+    let mut out = Vec::with_capacity(data.len());
+    for i in 0..half {
+        out.push(data[i]);
+        if half + i < data.len() {
+            out.push(data[half + i]);
+        }
+    }
+    out
+}
+
+fn predict(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = data.iter().step_by(2).cloned().collect();
+    out.extend(data.iter().skip(1).step_by(2));
+    for i in (1..out.len()).rev() {
+        out[i] = out[i].wrapping_sub(out[i - 1]).wrapping_add(128);
+    }
+    out
+}
+
+fn decompress(compression: Compression, data: &[u8], expected: usize) -> EXRResult<Vec<u8>> {
+    // Blocks that wouldn't get any smaller are stored uncompressed
+    if data.len() >= expected {
+        return Ok(data[..expected].to_vec());
+    }
+    let out = match compression {
+        Compression::Uncompressed => return invalid_format(data.len(), "insufficient block data"),
+        Compression::RunLength => unpredict(rle_decompress(data, expected)?),
+        Compression::ZipSingle | Compression::Zip => match zlib::decompress(data, expected) {
+            Ok(out) => unpredict(out),
+            Err(e) => return invalid_format(0, format!("bad zip data: {}", e)),
+        },
+    };
+    if out.len() != expected {
+        return invalid_format(0, "block decompresses to the wrong size");
+    }
+    Ok(out)
+}
+
+fn read_sample(pixel_type: PixelType, data: &[u8]) -> f32 {
+    match pixel_type {
+        PixelType::Uint => u32_le(data) as f32,
+        PixelType::Half => half_to_f32(u16::from(data[0]) | u16::from(data[1]) << 8),
+        PixelType::Float => f32::from_bits(u32_le(data)),
+    }
+}
+
+/// The attributes every scanline file needs, leaving the rest as metadata
+const REQUIRED_ATTRIBUTES: [&str; 8] = [
+    "channels",
+    "compression",
+    "dataWindow",
+    "displayWindow",
+    "lineOrder",
+    "pixelAspectRatio",
+    "screenWindowCenter",
+    "screenWindowWidth",
+];
+
+/// Describe the value of an attribute, spelling out the types we know
+fn describe_attribute(kind: &str, value: &[u8]) -> Value {
+    match (kind, value.len()) {
+        ("int", 4) => Value::from(i32_le(value)),
+        ("float", 4) => Value::Text(f32::from_bits(u32_le(value)).to_string()),
+        ("compression" | "lineOrder", 1) => Value::Number(i64::from(value[0])),
+        ("string", _) => Value::Text(String::from_utf8_lossy(value).into_owned()),
+        ("box2i", 16) => Value::Text(format!(
+            "({}, {}) to ({}, {})",
+            i32_le(value),
+            i32_le(&value[4..]),
+            i32_le(&value[8..]),
+            i32_le(&value[12..])
+        )),
+        ("chlist", _) => match parse_channels(value) {
+            Ok(channels) => {
+                let names: Vec<&str> = channels.iter().map(|c| c.name.as_str()).collect();
+                Value::Text(names.join(", "))
+            }
+            Err(_) => Value::Text("invalid channel list".into()),
+        },
+        _ => Value::Text(format!("{} ({} bytes)", kind, value.len())),
+    }
+}
+
+/// Describe a file, with every attribute in its header
+pub fn info(data: &[u8]) -> EXRResult<Info> {
+    let header = parse_header(data)?;
+    let (x_min, y_min, x_max, y_max) = header.data_window;
+    if x_max < x_min || y_max < y_min {
+        return invalid_format(header.data_window_offset, "empty data window");
+    }
+    let size = |min: i32, max: i32| u32::try_from(i64::from(max) - i64::from(min) + 1);
+    let mut info = match (size(x_min, x_max), size(y_min, y_max)) {
+        (Ok(width), Ok(height)) => Info::new(width, height),
+        _ => return invalid_format(header.data_window_offset, "data window too large"),
+    };
+    let pixel_size: usize = header.channels.iter().map(|c| c.pixel_type.size()).sum();
+    info.bit_depth = Some(8 * pixel_size as u32);
+    let has = |name: &str| header.channels.iter().any(|c| c.name == name);
+    let color_type = match (has("R") || has("G") || has("B"), has("A")) {
+        (true, true) => "rgba",
+        (true, false) => "rgb",
+        (false, true) => "grayscale alpha",
+        (false, false) => "grayscale",
+    };
+    info.color_type = Some(color_type.into());
+    info.compression = Some(
+        match header.compression {
+            Compression::Uncompressed => "none",
+            Compression::RunLength => "rle",
+            Compression::ZipSingle => "zips",
+            Compression::Zip => "zip",
+        }
+        .into(),
+    );
+    info.palette_size = Some(0);
+    let mut has_metadata = false;
+    let (mut density, mut aspect) = (None, 1.0);
+    let mut i = 8;
+    while let Some((name, kind, value)) = read_attribute(data, &mut i)? {
+        has_metadata |= !REQUIRED_ATTRIBUTES.contains(&name.as_str());
+        // The density is horizontal, and the aspect ratio gives the vertical one
+        if kind == "float" && value.len() == 4 {
+            let number = f64::from(f32::from_bits(u32_le(value)));
+            match name.as_str() {
+                "xDensity" => density = Some(number),
+                "pixelAspectRatio" => aspect = number,
+                _ => {}
+            }
+        }
+        info.field(&name, describe_attribute(&kind, value));
+    }
+    info.dpi = density.map(|x| (x, x * aspect));
+    info.metadata = Some(has_metadata);
+    Ok(info)
+}
+
+pub fn parse_image(data: &[u8]) -> EXRResult<FloatImage> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> EXRResult<FloatImage> {
+    let header = parse_header(data)?;
+    let (x_min, y_min, x_max, y_max) = header.data_window;
+    if x_max < x_min || y_max < y_min {
+        return invalid_format(header.data_window_offset, "empty data window");
+    }
+    let width = (i64::from(x_max) - i64::from(x_min) + 1) as usize;
+    let height = (i64::from(y_max) - i64::from(y_min) + 1) as usize;
+    // Everything in the header other than the magic number and version is an attribute
+    limits.check_metadata((header.offset - 8) as u64)?;
+    limits.check_image(
+        width as u64,
+        height as u64,
+        mem::size_of::<FloatRGBA>() as u64,
+    )?;
+    let lines_per_block = header.compression.lines_per_block();
+    let block_count = height.div_ceil(lines_per_block);
+    if data.len() < header.offset + 8 * block_count {
+        return invalid_format(data.len(), "insufficient offset table length");
+    }
+    let pixel_size: usize = header.channels.iter().map(|c| c.pixel_type.size()).sum();
+    let line_size = width * pixel_size;
+    limits.check_alloc((line_size * lines_per_block) as u64)?;
+    // Where each channel ends up in a pixel, if it ends up anywhere
+    let targets: Vec<Option<usize>> = header
+        .channels
+        .iter()
+        .map(|c| match c.name.as_str() {
+            "R" | "Y" => Some(0),
+            "G" => Some(1),
+            "B" => Some(2),
+            "A" => Some(3),
+            _ => None,
+        })
+        .collect();
+    let is_gray = header
+        .channels
+        .iter()
+        .all(|c| c.name != "R" && c.name != "G" && c.name != "B");
+
+    let mut image = FloatImage::new(width as u32, height as u32);
+    for block in 0..block_count {
+        let entry = header.offset + 8 * block;
+        let offset = u64_le(&data[entry..]) as usize;
+        if offset >= data.len() || data.len() - offset < 8 {
+            return invalid_format(entry, "block offset past the end of the file");
+        }
+        let y_start = i64::from(i32_le(&data[offset..])) - i64::from(y_min);
+        let size = u32_le(&data[offset + 4..]) as usize;
+        if y_start < 0 || y_start as usize >= height {
+            return invalid_format(offset, "block outside of the data window");
+        }
+        if data.len() - offset - 8 < size {
+            return invalid_format(offset + 4, "block extends past the end of the file");
+        }
+        let y_start = y_start as usize;
+        let lines = lines_per_block.min(height - y_start);
+        let block_data = &data[offset + 8..offset + 8 + size];
+        let expected = lines * line_size;
+        let raw = offset_by(
+            offset + 8,
+            decompress(header.compression, block_data, expected),
+        )?;
+        for line in 0..lines {
+            let y = (y_start + line) as u32;
+            let mut pixels = vec![[0.0, 0.0, 0.0, 1.0]; width];
+            let mut i = line * line_size;
+            for (channel, target) in header.channels.iter().zip(&targets) {
+                let size = channel.pixel_type.size();
+                for pixel in &mut pixels {
+                    if let Some(t) = target {
+                        pixel[*t] = read_sample(channel.pixel_type, &raw[i..]);
+                    }
+                    i += size;
+                }
+            }
+            for (x, p) in pixels.iter().enumerate() {
+                let pixel = if is_gray {
+                    FloatRGBA::new(p[0], p[0], p[0], p[3])
+                } else {
+                    FloatRGBA::new(p[0], p[1], p[2], p[3])
+                };
+                image.write(x as u32, y, pixel);
+            }
+        }
+    }
+    Ok(image)
+}
+
+fn write_attribute<W: io::Write>(
+    writer: &mut W,
+    name: &str,
+    kind: &str,
+    value: &[u8],
+) -> io::Result<()> {
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(kind.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(value)
+}
+
+fn write_sample(out: &mut Vec<u8>, pixel_type: PixelType, value: f32) {
+    match pixel_type {
+        PixelType::Uint => out.extend(&(value.max(0.0) as u32).to_le_bytes()),
+        PixelType::Half => out.extend(&f32_to_half(value).to_le_bytes()),
+        PixelType::Float => out.extend(&value.to_bits().to_le_bytes()),
+    }
+}
+
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a, FloatRGBA>>>(
+    writer: &mut W,
+    image: I,
+    options: Options,
+) -> io::Result<()> {
+    let image = image.into();
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let lines_per_block = options.compression.lines_per_block();
+    let mut blocks = Vec::new();
+    for y_start in (0..height).step_by(lines_per_block) {
+        let mut raw = Vec::new();
+        for y in y_start..height.min(y_start + lines_per_block) {
+            // Channels are stored in alphabetical order: A, B, G, R
+            for c in 0..4 {
+                for x in 0..width {
+                    let pixel = image.read(x as u32, y as u32);
+                    let value = [pixel.a, pixel.b, pixel.g, pixel.r][c];
+                    write_sample(&mut raw, options.pixel_type, value);
+                }
+            }
+        }
+        let compressed = match options.compression {
+            Compression::Uncompressed => raw.clone(),
+            Compression::RunLength => rle_compress(&predict(&raw)),
+            Compression::ZipSingle | Compression::Zip => zlib::compress(&predict(&raw)),
+        };
+        let data = if compressed.len() < raw.len() {
+            compressed
+        } else {
+            raw
+        };
+        blocks.push((y_start as i32, data));
+    }
+
+    let mut header = Vec::new();
+    header.extend(&[0x76, 0x2F, 0x31, 0x01, 2, 0, 0, 0]);
+    let pixel_type: u32 = match options.pixel_type {
+        PixelType::Uint => 0,
+        PixelType::Half => 1,
+        PixelType::Float => 2,
+    };
+    let mut channels = Vec::new();
+    for name in &["A", "B", "G", "R"] {
+        channels.extend(name.as_bytes());
+        channels.push(0);
+        channels.extend(&pixel_type.to_le_bytes());
+        // pLinear, followed by 3 reserved bytes
+        channels.extend(&[0, 0, 0, 0]);
+        channels.extend(&1i32.to_le_bytes());
+        channels.extend(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let mut window = Vec::new();
+    for v in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend(&v.to_le_bytes());
+    }
+    write_attribute(&mut header, "channels", "chlist", &channels)?;
+    write_attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[options.compression.into()],
+    )?;
+    write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    )?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    )?;
+    header.push(0);
+
+    writer.write_all(&header)?;
+    let mut offset = (header.len() + 8 * blocks.len()) as u64;
+    for (_, data) in &blocks {
+        writer.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+    for (y, data) in &blocks {
+        writer.write_all(&y.to_le_bytes())?;
+        writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        writer.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Reads and writes OpenEXR images, keeping their floating point pixels
+pub struct EXRCodec;
+
+impl ImageDecoder for EXRCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::High)
+            .map_err(MageError::from)
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+impl ImageEncoder for EXRCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        _: &EncodeOptions,
+    ) -> MageResult<()> {
+        write_image(&mut writer, &image.into_high(), Options::default())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_half() {
+        for &value in &[0.0, 1.0, -2.5, 0.000_061_035_156, 65504.0, 5.960_464_5e-8] {
+            assert_eq!(half_to_f32(f32_to_half(value)), value);
+        }
+        assert_eq!(f32_to_half(1.0), 0x3C00);
+        assert_eq!(f32_to_half(1e6), 0x7C00);
+        // 1 + 2^-11 lies halfway between two halves, and rounds to even
+        assert_eq!(f32_to_half(1.000_488_3), 0x3C00);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = FloatImage::new(20, 20);
+        for y in 0..20 {
+            for x in 0..20 {
+                let v = (x * y) as f32 / 8.0;
+                image.write(x, y, FloatRGBA::new(v, 0.5, 2.0, 1.0));
+            }
+        }
+        let compressions = [
+            Compression::Uncompressed,
+            Compression::RunLength,
+            Compression::ZipSingle,
+            Compression::Zip,
+        ];
+        for &compression in &compressions {
+            for &pixel_type in &[PixelType::Half, PixelType::Float] {
+                let options = Options {
+                    compression,
+                    pixel_type,
+                };
+                let mut data = Vec::new();
+                write_image(&mut data, &image, options).unwrap();
+                let read = parse_image(&data).unwrap();
+                for y in 0..20 {
+                    for x in 0..20 {
+                        assert_eq!(read.read(x, y), image.read(x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let options = Options {
+            compression: Compression::Uncompressed,
+            pixel_type: PixelType::Half,
+        };
+        let mut data = Vec::new();
+        write_image(&mut data, &FloatImage::new(2, 2), options).unwrap();
+        let offset_of = |data: &[u8]| match parse_image(data) {
+            Err(EXRError::InvalidFormat(_, offset)) => offset,
+            _ => panic!("invalid file decoded"),
+        };
+        // Point the second block past the end of the file
+        let table = parse_header(&data).unwrap().offset;
+        let mut broken = data.clone();
+        broken[table + 8..table + 16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(offset_of(&broken), table + 8);
+        // Give the first channel, named "A", an unknown pixel type
+        let chlist = data.windows(7).position(|w| w == b"chlist\0").unwrap();
+        let pixel_type = chlist + 7 + 4 + 2;
+        let mut broken = data.clone();
+        broken[pixel_type] = 9;
+        assert_eq!(offset_of(&broken), pixel_type);
+    }
+
+    #[test]
+    fn test_info() {
+        let mut data = Vec::new();
+        write_image(&mut data, &FloatImage::new(3, 2), Options::default()).unwrap();
+        let info = info(&data).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.bit_depth, Some(64));
+        assert_eq!(info.color_type.as_deref(), Some("rgba"));
+        assert_eq!(info.compression.as_deref(), Some("zip"));
+        assert_eq!(info.dpi, None);
+        assert_eq!(info.metadata, Some(false));
+        assert_eq!(info.fields[0], ("channels".into(), "A, B, G, R".into()));
+        assert_eq!(
+            info.fields[2],
+            ("dataWindow".into(), "(0, 0) to (2, 1)".into())
+        );
+    }
+}
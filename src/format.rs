@@ -0,0 +1,136 @@
+use crate::codec::{self, Codec, CODECS};
+// This module figures out the format of a file, either from its first few
+// bytes, using the magic in the codec registry, or from its name.
+
+/// How many bytes from the start of a file are enough to detect its format
+///
+/// This covers the longest magic, with room for text formats to start with
+/// some whitespace.
+pub const SNIFF_LEN: usize = 64;
+
+/// How sure we are that a file is in the format we detected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Confidence {
+    /// The file starts with a signature unique to the format
+    Certain,
+    /// The first bytes fit the format, but could easily appear in other files
+    Likely,
+    /// Nothing in the contents matched, so we went by the extension
+    Guess,
+}
+
+impl Confidence {
+    /// A short description of how the format was detected
+    pub fn describe(self) -> &'static str {
+        match self {
+            Confidence::Certain => "certain",
+            Confidence::Likely => "likely, from a short signature",
+            Confidence::Guess => "guessed from the file extension",
+        }
+    }
+}
+
+/// The result of detecting the format of a file
+#[derive(Clone, Copy)]
+pub struct Detection {
+    pub codec: &'static Codec,
+    pub confidence: Confidence,
+}
+
+/// Find the codec whose magic matches the start of a file
+///
+/// Strong magic is checked first, and we return whether or not it was weak.
+fn sniff(data: &[u8]) -> Option<(&'static Codec, bool)> {
+    // Text formats can have some leading whitespace
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(0);
+    for &weak in &[false, true] {
+        for codec in CODECS.iter() {
+            let data = if codec.is_text { &data[start..] } else { data };
+            if codec
+                .magic
+                .iter()
+                .any(|m| m.weak == weak && m.matches(data))
+            {
+                return Some((codec, weak));
+            }
+        }
+    }
+    None
+}
+
+/// Detect the format of a file, using its contents, and its path if we have one
+///
+/// Signatures in the contents take precedence over the extension, which is
+/// only used when nothing else matches.
+pub fn detect(data: &[u8], path: Option<&str>) -> Option<Detection> {
+    let from_path = path.and_then(codec::by_path);
+    match sniff(data) {
+        Some((codec, false)) => Some(Detection {
+            codec,
+            confidence: Confidence::Certain,
+        }),
+        Some((codec, true)) => {
+            // A matching extension removes most of the doubt
+            let agrees = from_path.is_some_and(|c| std::ptr::eq(c, codec));
+            let confidence = if agrees {
+                Confidence::Certain
+            } else {
+                Confidence::Likely
+            };
+            Some(Detection { codec, confidence })
+        }
+        None => from_path.map(|codec| Detection {
+            codec,
+            confidence: Confidence::Guess,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let png = b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR";
+        let detected = detect(png, Some("image.bmp")).unwrap();
+        assert_eq!(detected.codec.name, "png");
+        assert_eq!(detected.confidence, Confidence::Certain);
+        let detected = detect(b"P6\n2 2\n255\n", None).unwrap();
+        assert_eq!(detected.codec.name, "pnm");
+        assert_eq!(detected.confidence, Confidence::Likely);
+        let detected = detect(b"\n/* XPM */", None).unwrap();
+        assert_eq!(detected.codec.name, "xpm");
+        let detected = detect(b"\0\0\0\0", Some("dir.v2/texture.DDS")).unwrap();
+        assert_eq!(detected.codec.name, "dds");
+        assert_eq!(detected.confidence, Confidence::Guess);
+        assert!(detect(b"\0\0\0\0", Some("notes")).is_none());
+    }
+
+    #[test]
+    fn test_sniff_len() {
+        for codec in CODECS.iter() {
+            for magic in codec.magic {
+                let parts = magic.parts.iter().map(|&(offset, b)| offset + b.len());
+                let one_of = magic.one_of.iter().map(|&(offset, _)| offset + 1);
+                assert!(parts.chain(one_of).all(|end| end <= SNIFF_LEN / 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_weak_magic() {
+        assert!(detect(b"P1x is plain text", None).is_none());
+        assert_eq!(detect(b"P4 2 2\n", None).unwrap().codec.name, "pnm");
+        assert!(detect(&[0x01, 0xDA, 0x07, 0x01], None).is_none());
+        assert_eq!(
+            detect(&[0x01, 0xDA, 0x01, 0x02], None).unwrap().codec.name,
+            "sgi"
+        );
+        assert!(detect(&[0x0A, 0x05, 0x09], None).is_none());
+        assert_eq!(detect(&[0x0A, 0x05, 0x01], None).unwrap().codec.name, "pcx");
+    }
+}
@@ -0,0 +1,420 @@
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{FloatImage, FloatRGBA, ImageView};
+use crate::info::Info;
+use crate::limits::{LimitError, Limits};
+use std::io;
+use std::mem;
+// The format is described in Greg Ward's "Real Pixels" in Graphics Gems II,
+// and the run length encoding follows the reference implementation in
+// Radiance: http://radsite.lbl.gov/radiance/refer/filefmts.pdf
+
+/// Represents the errors we can encounter when reading a Radiance HDR file
+#[derive(Debug)]
+pub enum HDRError {
+    /// The format of the file doesn't match the specification
+    InvalidFormat(String),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This is the case for XYZE pixels, or unusual scanline orders.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type HDRResult<T> = Result<T, HDRError>;
+
+impl From<HDRError> for MageError {
+    fn from(error: HDRError) -> Self {
+        match error {
+            HDRError::InvalidFormat(message) => MageError::invalid("hdr", message),
+            HDRError::UnsupportedFormat(message) => MageError::unsupported("hdr", message),
+            HDRError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for HDRError {
+    fn from(error: LimitError) -> Self {
+        HDRError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(s: S) -> HDRResult<T> {
+    Err(HDRError::InvalidFormat(s.into()))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> HDRResult<T> {
+    Err(HDRError::UnsupportedFormat(s.into()))
+}
+
+/// The information in the textual header of the file
+#[derive(Debug)]
+struct Header {
+    width: u32,
+    height: u32,
+    /// Whether the first scanline is at the bottom of the image
+    bottom_up: bool,
+    /// What the pixels were multiplied by after being computed
+    exposure: f32,
+    /// At what index does the pixel data start
+    offset: usize,
+}
+
+/// Read a line of the header, returning it along with the index after it
+fn read_line(data: &[u8], start: usize) -> HDRResult<(&str, usize)> {
+    let end = match data[start..].iter().position(|&b| b == b'\n') {
+        Some(i) => start + i,
+        None => return invalid_format("header isn't terminated"),
+    };
+    match std::str::from_utf8(&data[start..end]) {
+        Ok(line) => Ok((line, end + 1)),
+        Err(_) => invalid_format("header isn't valid text"),
+    }
+}
+
+fn parse_resolution(line: &str) -> HDRResult<(u32, u32, bool)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 4 {
+        return invalid_format("malformed resolution line");
+    }
+    let height = parts[1].parse::<u32>();
+    let width = parts[3].parse::<u32>();
+    let (height, width) = match (height, width) {
+        (Ok(height), Ok(width)) => (height, width),
+        _ => return invalid_format("malformed resolution line"),
+    };
+    match (parts[0], parts[2]) {
+        ("-Y", "+X") => Ok((width, height, false)),
+        ("+Y", "+X") => Ok((width, height, true)),
+        _ => unsupported_format("unsupported scanline order"),
+    }
+}
+
+fn parse_header(data: &[u8]) -> HDRResult<Header> {
+    if !data.starts_with(b"#?") {
+        return invalid_format("header didn't start with '#?'");
+    }
+    let (_, mut i) = read_line(data, 0)?;
+    let mut exposure = 1.0;
+    loop {
+        let (line, next) = read_line(data, i)?;
+        i = next;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return unsupported_format("only RGBE pixels are supported");
+            }
+        } else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+            match value.trim().parse::<f32>() {
+                // Exposures accumulate when a file is processed several times
+                Ok(value) if value > 0.0 => exposure *= value,
+                _ => return invalid_format("malformed exposure"),
+            }
+        }
+    }
+    let (line, offset) = read_line(data, i)?;
+    let (width, height, bottom_up) = parse_resolution(line)?;
+    Ok(Header {
+        width,
+        height,
+        bottom_up,
+        exposure,
+        offset,
+    })
+}
+
+/// Read a scanline of RGBE pixels, returning the index after it
+fn read_scanline(data: &[u8], mut i: usize, line: &mut [[u8; 4]]) -> HDRResult<usize> {
+    let width = line.len();
+    let is_rle = (8..0x8000).contains(&width)
+        && data.len() >= i + 4
+        && data[i] == 2
+        && data[i + 1] == 2
+        && data[i + 2] & 0x80 == 0;
+    if !is_rle {
+        if data.len() < i + 4 * width {
+            return invalid_format("insufficient pixel data");
+        }
+        if data[i..i + 3] == [1, 1, 1] {
+            return unsupported_format("old style run length encoding is not supported");
+        }
+        for pixel in line.iter_mut() {
+            pixel.copy_from_slice(&data[i..i + 4]);
+            i += 4;
+        }
+        return Ok(i);
+    }
+    if ((data[i + 2] as usize) << 8 | data[i + 3] as usize) != width {
+        return invalid_format("scanline width mismatch");
+    }
+    i += 4;
+    // Each component is run length encoded separately
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            if i >= data.len() {
+                return invalid_format("insufficient pixel data");
+            }
+            let count = data[i] as usize;
+            i += 1;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width || i >= data.len() {
+                    return invalid_format("bad scanline run");
+                }
+                for pixel in &mut line[x..x + count] {
+                    pixel[channel] = data[i];
+                }
+                i += 1;
+                x += count;
+            } else {
+                if count == 0 || x + count > width || i + count > data.len() {
+                    return invalid_format("bad scanline data");
+                }
+                for pixel in &mut line[x..x + count] {
+                    pixel[channel] = data[i];
+                    i += 1;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(i)
+}
+
+fn from_rgbe(rgbe: [u8; 4]) -> FloatRGBA {
+    if rgbe[3] == 0 {
+        return FloatRGBA::new(0.0, 0.0, 0.0, 1.0);
+    }
+    let f = 2f32.powi(i32::from(rgbe[3]) - (128 + 8));
+    let r = f32::from(rgbe[0]) * f;
+    let g = f32::from(rgbe[1]) * f;
+    let b = f32::from(rgbe[2]) * f;
+    FloatRGBA::new(r, g, b, 1.0)
+}
+
+fn to_rgbe(pixel: FloatRGBA) -> [u8; 4] {
+    let v = f64::from(pixel.r.max(pixel.g).max(pixel.b));
+    if v.is_nan() || v <= 1e-32 {
+        return [0; 4];
+    }
+    if !v.is_finite() {
+        return [255, 255, 255, 255];
+    }
+    // We need v = m * 2^e with m in [0.5, 1)
+    let mut e = (v.log2().floor() as i32).saturating_add(1);
+    let m = v * 2f64.powi(-e);
+    if m >= 1.0 {
+        e += 1;
+    } else if m < 0.5 {
+        e -= 1;
+    }
+    if e.saturating_add(128) > 255 {
+        return [255, 255, 255, 255];
+    }
+    let scale = 256.0 * 2f64.powi(-e);
+    let component = |c: f32| (f64::from(c) * scale).clamp(0.0, 255.0) as u8;
+    [
+        component(pixel.r),
+        component(pixel.g),
+        component(pixel.b),
+        (e + 128).max(0) as u8,
+    ]
+}
+
+/// Describe a file, with every variable in its header
+pub fn info(data: &[u8]) -> HDRResult<Info> {
+    let header = parse_header(data)?;
+    let mut info = Info::new(header.width, header.height);
+    info.bit_depth = Some(32);
+    info.color_type = Some("rgbe".into());
+    info.palette_size = Some(0);
+    // Scanlines in the newer run length encoding start with two 2s
+    let is_rle = (8..0x8000).contains(&header.width)
+        && data.get(header.offset..header.offset + 2) == Some(&[2, 2]);
+    info.compression = Some(if is_rle { "rle" } else { "none" }.into());
+    let mut has_comments = false;
+    let (_, mut i) = read_line(data, 0)?;
+    loop {
+        let (line, next) = read_line(data, i)?;
+        i = next;
+        if line.is_empty() {
+            break;
+        }
+        // Anything other than a variable is left by the programs that made the file
+        match line.split_once('=') {
+            Some((name, value)) => info.field(name, value.trim()),
+            None => has_comments = true,
+        }
+    }
+    info.metadata = Some(has_comments);
+    info.field("resolution", read_line(data, i)?.0);
+    Ok(info)
+}
+
+pub fn parse_image(data: &[u8]) -> HDRResult<FloatImage> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> HDRResult<FloatImage> {
+    let header = parse_header(data)?;
+    let (width, height) = (u64::from(header.width), u64::from(header.height));
+    limits.check_image(width, height, mem::size_of::<FloatRGBA>() as u64)?;
+    let mut image = FloatImage::new(header.width, header.height);
+    let mut line = vec![[0; 4]; header.width as usize];
+    let mut i = header.offset;
+    for row in 0..header.height {
+        i = read_scanline(data, i, &mut line)?;
+        let y = if header.bottom_up {
+            header.height - 1 - row
+        } else {
+            row
+        };
+        for (x, &rgbe) in line.iter().enumerate() {
+            let mut pixel = from_rgbe(rgbe);
+            pixel.r /= header.exposure;
+            pixel.g /= header.exposure;
+            pixel.b /= header.exposure;
+            image.write(x as u32, y, pixel);
+        }
+    }
+    Ok(image)
+}
+
+/// Write one component of a scanline with run length encoding
+fn write_runs<W: io::Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let run_at = |i: usize| {
+        let mut run = 1;
+        while i + run < data.len() && run < 127 && data[i + run] == data[i] {
+            run += 1;
+        }
+        run
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let run = run_at(i);
+        if run >= 3 {
+            writer.write_all(&[128 + run as u8, data[i]])?;
+            i += run;
+            continue;
+        }
+        let start = i;
+        while i < data.len() && i - start < 128 && run_at(i) < 3 {
+            i += 1;
+        }
+        writer.write_all(&[(i - start) as u8])?;
+        writer.write_all(&data[start..i])?;
+    }
+    Ok(())
+}
+
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a, FloatRGBA>>>(
+    writer: &mut W,
+    image: I,
+) -> io::Result<()> {
+    let image = image.into();
+    writer.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+    writeln!(writer, "-Y {} +X {}", image.height, image.width)?;
+    let width = image.width as usize;
+    let mut line = vec![[0; 4]; width];
+    let mut channel = vec![0; width];
+    for y in 0..image.height {
+        for (x, pixel) in line.iter_mut().enumerate() {
+            *pixel = to_rgbe(image.read(x as u32, y));
+        }
+        // Run length encoding can only describe these widths
+        if !(8..0x8000).contains(&width) {
+            for pixel in &line {
+                writer.write_all(pixel)?;
+            }
+            continue;
+        }
+        writer.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+        for c in 0..4 {
+            for (value, pixel) in channel.iter_mut().zip(&line) {
+                *value = pixel[c];
+            }
+            write_runs(writer, &channel)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads and writes Radiance RGBE images, keeping their floating point pixels
+pub struct HDRCodec;
+
+impl ImageDecoder for HDRCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::High)
+            .map_err(MageError::from)
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+impl ImageEncoder for HDRCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        _: &EncodeOptions,
+    ) -> MageResult<()> {
+        write_image(&mut writer, &image.into_high())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_rgbe_clamps() {
+        let infinite = FloatRGBA::new(f32::INFINITY, 0.5, 0.0, 1.0);
+        assert_eq!(to_rgbe(infinite), [255, 255, 255, 255]);
+        let huge = FloatRGBA::new(f32::MAX, f32::MAX, f32::MAX, 1.0);
+        assert_eq!(to_rgbe(huge), [255, 255, 255, 255]);
+        let negative = FloatRGBA::new(f32::NEG_INFINITY, f32::NAN, -1.0, 1.0);
+        assert_eq!(to_rgbe(negative), [0; 4]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = FloatImage::new(10, 2);
+        for x in 0..10 {
+            image.write(x, 0, FloatRGBA::new(0.5, 0.25, 4.0, 1.0));
+            image.write(x, 1, FloatRGBA::new(x as f32, 0.0, 1.0, 1.0));
+        }
+        let mut data = Vec::new();
+        write_image(&mut data, &image).unwrap();
+        let read = parse_image(&data).unwrap();
+        assert_eq!(read.width, 10);
+        assert_eq!(read.height, 2);
+        for y in 0..2 {
+            for x in 0..10 {
+                assert_eq!(read.read(x, y), image.read(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_info() {
+        let mut data = Vec::new();
+        write_image(&mut data, &FloatImage::new(10, 2)).unwrap();
+        let info = info(&data).unwrap();
+        assert_eq!((info.width, info.height), (10, 2));
+        assert_eq!(info.compression.as_deref(), Some("rle"));
+        assert_eq!(info.fields[0], ("FORMAT".into(), "32-bit_rle_rgbe".into()));
+        assert_eq!(info.fields[1], ("resolution".into(), "-Y 2 +X 10".into()));
+    }
+}
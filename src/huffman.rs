@@ -0,0 +1,119 @@
+// Both DEFLATE and lossless WebP pack their bits starting from the least
+// significant bit of each byte, and both use canonical prefix codes, so the
+// pieces needed to read them live here.
+
+/// The longest prefix code either format allows
+pub const MAX_CODE_LENGTH: usize = 15;
+
+/// Reads bits from a slice of bytes, least significant bit first
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    // The index of the next byte to pull into the buffer
+    pos: usize,
+    buffer: u64,
+    // How many bits in the buffer are still unread
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Read the next `n` bits, with the first bit read being the lowest
+    ///
+    /// `n` can be at most 32. This returns `None` if the data runs out.
+    pub fn read_bits(&mut self, n: u32) -> Option<u32> {
+        debug_assert!(n <= 32);
+        while self.count < n {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.buffer |= u64::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = (self.buffer & ((1u64 << n) - 1)) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Some(value)
+    }
+
+    /// Skip the remaining bits in the current byte
+    pub fn align_to_byte(&mut self) {
+        let extra = self.count % 8;
+        self.buffer >>= extra;
+        self.count -= extra;
+    }
+}
+
+/// A canonical prefix code, decoded one bit at a time
+///
+/// This follows the approach of zlib's `puff`: we only store how many codes
+/// there are of each length, and the symbols sorted by their code.
+pub struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build a code from the length of each symbol's code
+    ///
+    /// A length of 0 means that the symbol isn't used. This returns `None`
+    /// if the lengths describe more codes than can exist.
+    pub fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            if len as usize > MAX_CODE_LENGTH {
+                return None;
+            }
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left <<= 1;
+            left -= i32::from(count);
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+        for len in 1..=MAX_CODE_LENGTH {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_LENGTH + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Some(Huffman { counts, symbols })
+    }
+
+    /// Read a single symbol
+    ///
+    /// This returns `None` if the data runs out, or if the bits read
+    /// don't correspond to any code.
+    pub fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for &count in &self.counts[1..] {
+            code |= reader.read_bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Some(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        None
+    }
+}
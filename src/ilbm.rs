@@ -0,0 +1,338 @@
+use crate::codec::{DecodeOptions, Decoded, ImageDecoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, RGBA_BYTES, RGBA};
+use crate::limits::{LimitError, Limits};
+// The structures and parsing in this module are based off of Electronic Arts'
+// "ILBM IFF Interleaved Bitmap" specification, along with the Amiga ROM
+// Kernel Reference Manual for the HAM and extra half-brite display modes.
+
+fn u16_be(data: &[u8]) -> u16 {
+    ((data[0] as u16) << 8) | (data[1] as u16)
+}
+
+fn u32_be(data: &[u8]) -> u32 {
+    ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | (data[3] as u32)
+}
+
+/// Represents the errors we can encounter when reading an ILBM file
+#[derive(Debug)]
+pub enum ILBMError {
+    /// The format of the file doesn't match the specification
+    InvalidFormat(String),
+    /// The format of the file is valid, but we don't support it
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type ILBMResult<T> = Result<T, ILBMError>;
+
+impl From<ILBMError> for MageError {
+    fn from(error: ILBMError) -> Self {
+        match error {
+            ILBMError::InvalidFormat(message) => MageError::invalid("ilbm", message),
+            ILBMError::UnsupportedFormat(message) => MageError::unsupported("ilbm", message),
+            ILBMError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for ILBMError {
+    fn from(error: LimitError) -> Self {
+        ILBMError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(s: S) -> ILBMResult<T> {
+    Err(ILBMError::InvalidFormat(s.into()))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> ILBMResult<T> {
+    Err(ILBMError::UnsupportedFormat(s.into()))
+}
+
+/// The viewport mode flag for hold and modify images
+const CAMG_HAM: u32 = 0x800;
+/// The viewport mode flag for extra half-brite images
+const CAMG_EHB: u32 = 0x80;
+
+/// How the transparency of an image is described
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Masking {
+    None,
+    /// An extra plane follows the others, with set bits being opaque
+    HasMask,
+    /// One color of the palette is transparent
+    TransparentColor,
+    /// This is meant for drawing programs, and we treat it like `None`
+    Lasso,
+}
+
+/// The contents of the BMHD chunk, describing the bitmap
+#[derive(Debug)]
+struct BitmapHeader {
+    width: usize,
+    height: usize,
+    /// How many bitplanes there are, not including the mask
+    planes: usize,
+    masking: Masking,
+    /// Whether or not rows are compressed with ByteRun1
+    is_compressed: bool,
+    transparent_color: usize,
+}
+
+fn parse_bitmap_header(data: &[u8]) -> ILBMResult<BitmapHeader> {
+    if data.len() < 20 {
+        return invalid_format("insufficient BMHD length");
+    }
+    let masking = match data[9] {
+        0 => Masking::None,
+        1 => Masking::HasMask,
+        2 => Masking::TransparentColor,
+        3 => Masking::Lasso,
+        _ => return invalid_format("unknown masking"),
+    };
+    let is_compressed = match data[10] {
+        0 => false,
+        1 => true,
+        _ => return unsupported_format("unknown compression"),
+    };
+    Ok(BitmapHeader {
+        width: u16_be(data) as usize,
+        height: u16_be(&data[2..]) as usize,
+        planes: data[8] as usize,
+        masking,
+        is_compressed,
+        transparent_color: u16_be(&data[12..]) as usize,
+    })
+}
+
+/// Undo ByteRun1 compression, the PackBits scheme used in IFF files
+fn unpack_byte_run(data: &[u8], expected: usize) -> ILBMResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected);
+    let mut i = 0;
+    while out.len() < expected {
+        if i >= data.len() {
+            return invalid_format("insufficient body data");
+        }
+        let n = data[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let count = n as usize + 1;
+            if i + count > data.len() {
+                return invalid_format("literal run past the end of the body");
+            }
+            out.extend_from_slice(&data[i..i + count]);
+            i += count;
+        } else if n != -128 {
+            if i >= data.len() {
+                return invalid_format("repeat run past the end of the body");
+            }
+            let count = (-(n as isize)) as usize + 1;
+            out.extend(std::iter::repeat_n(data[i], count));
+            i += 1;
+        }
+    }
+    out.truncate(expected);
+    Ok(out)
+}
+
+/// Split the FORM into its chunks, padded to an even number of bytes
+fn parse_chunks(data: &[u8]) -> ILBMResult<Vec<(&[u8], &[u8])>> {
+    if data.len() < 12 || &data[0..4] != b"FORM" {
+        return invalid_format("file didn't start with a FORM");
+    }
+    match &data[8..12] {
+        b"ILBM" => {}
+        b"PBM " => return unsupported_format("chunky PBM images are not supported"),
+        _ => return invalid_format("FORM isn't an ILBM"),
+    }
+    let end = (8 + u32_be(&data[4..]) as usize).min(data.len());
+    let mut chunks = Vec::new();
+    let mut i = 12;
+    while i + 8 <= end {
+        let id = &data[i..i + 4];
+        let size = u32_be(&data[i + 4..]) as usize;
+        let start = i + 8;
+        if end - start < size {
+            return invalid_format("chunk extends past the end of the file");
+        }
+        chunks.push((id, &data[start..start + size]));
+        i = start + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+pub fn parse_image(data: &[u8]) -> ILBMResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> ILBMResult<Image> {
+    let mut header = None;
+    let mut palette: Vec<RGBA> = Vec::new();
+    let mut camg = 0;
+    let mut body = None;
+    for (id, chunk) in parse_chunks(data)? {
+        match id {
+            b"BMHD" => header = Some(parse_bitmap_header(chunk)?),
+            b"CMAP" => {
+                palette = chunk
+                    .chunks_exact(3)
+                    .map(|c| RGBA::new(c[0], c[1], c[2], 0xFF))
+                    .collect()
+            }
+            b"CAMG" if chunk.len() >= 4 => camg = u32_be(chunk),
+            b"BODY" => body = Some(chunk),
+            _ => {}
+        }
+    }
+    let header = match header {
+        Some(header) => header,
+        None => return invalid_format("missing BMHD chunk"),
+    };
+    let body = match body {
+        Some(body) => body,
+        None => return invalid_format("missing BODY chunk"),
+    };
+    let (width, height) = (header.width as u64, header.height as u64);
+    limits.check_image(width, height, RGBA_BYTES as u64)?;
+    let is_ham = camg & CAMG_HAM != 0;
+    if header.planes == 0
+        || header.planes > 32
+        || (is_ham && header.planes != 6 && header.planes != 8)
+    {
+        return unsupported_format("unsupported plane count");
+    }
+    // Extra half-brite images have a second half of the palette at half brightness
+    if camg & CAMG_EHB != 0 && palette.len() >= 32 {
+        palette.truncate(32);
+        for i in 0..32 {
+            let c = palette[i];
+            palette.push(RGBA::new(c.r / 2, c.g / 2, c.b / 2, 0xFF));
+        }
+    }
+
+    let row_bytes = header.width.div_ceil(16) * 2;
+    let stored_planes = header.planes + (header.masking == Masking::HasMask) as usize;
+    let expected = row_bytes * stored_planes * header.height;
+    limits.check_alloc(expected as u64)?;
+    let raw = if header.is_compressed {
+        unpack_byte_run(body, expected)?
+    } else if body.len() < expected {
+        return invalid_format("insufficient body data");
+    } else {
+        body[..expected].to_vec()
+    };
+
+    let mut image = Image::new(header.width as u32, header.height as u32);
+    for y in 0..header.height {
+        let row = &raw[y * row_bytes * stored_planes..(y + 1) * row_bytes * stored_planes];
+        let bit = |plane: usize, x: usize| (row[plane * row_bytes + x / 8] >> (7 - x % 8)) & 1;
+        // Hold and modify pixels build on the pixel to their left
+        let mut previous = palette.first().cloned().unwrap_or(RGBA::new(0, 0, 0, 0xFF));
+        for x in 0..header.width {
+            let mut value: u32 = 0;
+            for plane in 0..header.planes {
+                value |= u32::from(bit(plane, x)) << plane;
+            }
+            let mut color = match header.planes {
+                24 => RGBA::new(value as u8, (value >> 8) as u8, (value >> 16) as u8, 0xFF),
+                32 => RGBA::new(
+                    value as u8,
+                    (value >> 8) as u8,
+                    (value >> 16) as u8,
+                    (value >> 24) as u8,
+                ),
+                _ if is_ham => {
+                    let data_bits = header.planes - 2;
+                    let control = value >> data_bits;
+                    let v = value & ((1 << data_bits) - 1);
+                    // Scale the data bits up to fill a byte
+                    let scaled = if data_bits == 4 {
+                        (v * 0x11) as u8
+                    } else {
+                        (v << 2 | v >> 4) as u8
+                    };
+                    match control {
+                        0 => palette.get(v as usize).cloned().unwrap_or(previous),
+                        1 => RGBA {
+                            b: scaled,
+                            ..previous
+                        },
+                        2 => RGBA {
+                            r: scaled,
+                            ..previous
+                        },
+                        _ => RGBA {
+                            g: scaled,
+                            ..previous
+                        },
+                    }
+                }
+                _ => match palette.get(value as usize) {
+                    Some(&color) => color,
+                    // Without a palette, we spread the indices across grays
+                    None => {
+                        // With up to 31 planes, this can only fit in 64 bits
+                        let max = (1u64 << header.planes) - 1;
+                        let v = (u64::from(value) * 255 / max) as u8;
+                        RGBA::new(v, v, v, 0xFF)
+                    }
+                },
+            };
+            previous = color;
+            let transparent = match header.masking {
+                Masking::HasMask => bit(header.planes, x) == 0,
+                Masking::TransparentColor => {
+                    !is_ham && header.planes <= 8 && value as usize == header.transparent_color
+                }
+                _ => false,
+            };
+            if transparent {
+                color.a = 0;
+            }
+            image.write(x as u32, y as u32, color);
+        }
+    }
+    Ok(image)
+}
+
+/// Decodes the planar images of the Amiga, including HAM and extra half-brite
+pub struct ILBMCodec;
+
+impl ImageDecoder for ILBMCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::Low)
+            .map_err(MageError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_byte_run() {
+        // A literal run of 2 bytes, a repeat of 3 bytes, and a no-op
+        let data = [0x01, 0xAA, 0xBB, 0xFE, 0xCC, 0x80, 0x00, 0xDD];
+        let out = unpack_byte_run(&data, 6).unwrap();
+        assert_eq!(out, vec![0xAA, 0xBB, 0xCC, 0xCC, 0xCC, 0xDD]);
+    }
+
+    #[test]
+    fn test_deep_grays() {
+        // A single pixel with every one of its 25 planes set, and no palette
+        let mut bmhd = vec![0, 1, 0, 1, 0, 0, 0, 0, 25];
+        bmhd.resize(20, 0);
+        let mut data = b"FORM\0\0\0\0ILBMBMHD\0\0\0\x14".to_vec();
+        data.extend_from_slice(&bmhd);
+        data.extend_from_slice(b"BODY\0\0\0\x32");
+        data.extend_from_slice(&[0xFF; 50]);
+        let size = (data.len() - 8) as u32;
+        data[4..8].copy_from_slice(&size.to_be_bytes());
+        let image = parse_image(&data).unwrap();
+        assert_eq!(image.read(0, 0), RGBA::new(0xFF, 0xFF, 0xFF, 0xFF));
+    }
+}
@@ -1,4 +1,68 @@
-use crate::sdl2::render::{Texture, UpdateTextureError};
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::slice::ChunksExactMut;
+// Images are generic over the kind of pixel they hold, so that a decoder can
+// return a grayscale image without inflating it into RGBA, or keep all 16 bits
+// of each channel. Every kind of pixel can go to and from floating point RGBA,
+// which is how we convert between them.
+
+/// A kind of pixel that an image can be made of
+pub trait Pixel: Copy + PartialEq + fmt::Debug + Send + Sync + 'static {
+    /// The type of each channel, like `u8` or `f32`
+    type Subpixel: Copy + Default + PartialEq + fmt::Debug + Send + Sync + 'static;
+    /// How many channels each pixel has
+    const CHANNELS: usize;
+
+    /// Read a pixel from its channels, in order
+    fn from_channels(channels: &[Self::Subpixel]) -> Self;
+
+    /// Write the channels of this pixel out, in order
+    fn to_channels(self, out: &mut [Self::Subpixel]);
+
+    /// Convert this pixel into floating point RGBA
+    ///
+    /// Integer channels are scaled to go from 0.0 to 1.0.
+    fn to_float(self) -> FloatRGBA;
+
+    /// Convert a floating point color into this kind of pixel
+    ///
+    /// Integer channels are clamped and rounded, grayscale pixels keep the
+    /// luma of the color, and pixels without alpha drop it.
+    fn from_float(color: FloatRGBA) -> Self;
+
+    /// Convert this pixel into another kind of pixel
+    ///
+    /// This is lossless when the other kind has every channel of this one, with
+    /// at least as much precision. Otherwise, we lose color, alpha, or precision.
+    fn convert<Q: Pixel>(self) -> Q {
+        Q::from_float(self.to_float())
+    }
+}
+
+/// Convert an integer channel into a float between 0.0 and 1.0
+fn unit(value: u16, max: f32) -> f32 {
+    f32::from(value) / max
+}
+
+/// Convert a float between 0.0 and 1.0 into an integer channel
+fn quantize(value: f32, max: f32) -> f32 {
+    // Casting NaN to an integer gives 0
+    (value.clamp(0.0, 1.0) * max).round()
+}
+
+fn quantize_u8(value: f32) -> u8 {
+    quantize(value, 255.0) as u8
+}
+
+fn quantize_u16(value: f32) -> u16 {
+    quantize(value, 65535.0) as u16
+}
+
+/// The luma of a color, with the weights from Rec. 601
+fn luma(color: FloatRGBA) -> f32 {
+    0.299 * color.r + 0.587 * color.g + 0.114 * color.b
+}
 
 /// Represents a Color in RGBA format
 ///
@@ -23,14 +87,250 @@ impl RGBA {
 
 pub const RGBA_BYTES: usize = 4;
 
-pub struct Image {
-    // The raw data stored with 4 bytes per color.
+/// An 8 bit RGBA pixel, under the same naming as the other kinds of pixel
+pub type Rgba8 = RGBA;
+
+impl Pixel for RGBA {
+    type Subpixel = u8;
+    const CHANNELS: usize = 4;
+
+    fn from_channels(c: &[u8]) -> Self {
+        RGBA::new(c[0], c[1], c[2], c[3])
+    }
+
+    fn to_channels(self, out: &mut [u8]) {
+        out[0] = self.r;
+        out[1] = self.g;
+        out[2] = self.b;
+        out[3] = self.a;
+    }
+
+    fn to_float(self) -> FloatRGBA {
+        let f = |c: u8| unit(u16::from(c), 255.0);
+        FloatRGBA::new(f(self.r), f(self.g), f(self.b), f(self.a))
+    }
+
+    fn from_float(color: FloatRGBA) -> Self {
+        let q = quantize_u8;
+        RGBA::new(q(color.r), q(color.g), q(color.b), q(color.a))
+    }
+}
+
+/// An 8 bit grayscale pixel, without transparency
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Luma8 {
+    pub luma: u8,
+}
+
+impl Luma8 {
+    pub fn new(luma: u8) -> Luma8 {
+        Luma8 { luma }
+    }
+}
+
+impl Pixel for Luma8 {
+    type Subpixel = u8;
+    const CHANNELS: usize = 1;
+
+    fn from_channels(c: &[u8]) -> Self {
+        Luma8::new(c[0])
+    }
+
+    fn to_channels(self, out: &mut [u8]) {
+        out[0] = self.luma;
+    }
+
+    fn to_float(self) -> FloatRGBA {
+        let l = unit(u16::from(self.luma), 255.0);
+        FloatRGBA::new(l, l, l, 1.0)
+    }
+
+    fn from_float(color: FloatRGBA) -> Self {
+        Luma8::new(quantize_u8(luma(color)))
+    }
+}
+
+/// An 8 bit grayscale pixel, with transparency
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LumaA8 {
+    pub luma: u8,
+    pub a: u8,
+}
+
+impl LumaA8 {
+    pub fn new(luma: u8, a: u8) -> LumaA8 {
+        LumaA8 { luma, a }
+    }
+}
+
+impl Pixel for LumaA8 {
+    type Subpixel = u8;
+    const CHANNELS: usize = 2;
+
+    fn from_channels(c: &[u8]) -> Self {
+        LumaA8::new(c[0], c[1])
+    }
+
+    fn to_channels(self, out: &mut [u8]) {
+        out[0] = self.luma;
+        out[1] = self.a;
+    }
+
+    fn to_float(self) -> FloatRGBA {
+        let l = unit(u16::from(self.luma), 255.0);
+        FloatRGBA::new(l, l, l, unit(u16::from(self.a), 255.0))
+    }
+
+    fn from_float(color: FloatRGBA) -> Self {
+        LumaA8::new(quantize_u8(luma(color)), quantize_u8(color.a))
+    }
+}
+
+/// An 8 bit RGB pixel, without transparency
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rgb8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb8 {
+    pub fn new(r: u8, g: u8, b: u8) -> Rgb8 {
+        Rgb8 { r, g, b }
+    }
+}
+
+impl Pixel for Rgb8 {
+    type Subpixel = u8;
+    const CHANNELS: usize = 3;
+
+    fn from_channels(c: &[u8]) -> Self {
+        Rgb8::new(c[0], c[1], c[2])
+    }
+
+    fn to_channels(self, out: &mut [u8]) {
+        out[0] = self.r;
+        out[1] = self.g;
+        out[2] = self.b;
+    }
+
+    fn to_float(self) -> FloatRGBA {
+        let f = |c: u8| unit(u16::from(c), 255.0);
+        FloatRGBA::new(f(self.r), f(self.g), f(self.b), 1.0)
+    }
+
+    fn from_float(color: FloatRGBA) -> Self {
+        let q = quantize_u8;
+        Rgb8::new(q(color.r), q(color.g), q(color.b))
+    }
+}
+
+/// A 16 bit RGBA pixel, for formats with more precision than 8 bits
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rgba16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub a: u16,
+}
+
+impl Rgba16 {
+    pub fn new(r: u16, g: u16, b: u16, a: u16) -> Rgba16 {
+        Rgba16 { r, g, b, a }
+    }
+}
+
+impl Pixel for Rgba16 {
+    type Subpixel = u16;
+    const CHANNELS: usize = 4;
+
+    fn from_channels(c: &[u16]) -> Self {
+        Rgba16::new(c[0], c[1], c[2], c[3])
+    }
+
+    fn to_channels(self, out: &mut [u16]) {
+        out[0] = self.r;
+        out[1] = self.g;
+        out[2] = self.b;
+        out[3] = self.a;
+    }
+
+    fn to_float(self) -> FloatRGBA {
+        let f = |c: u16| unit(c, 65535.0);
+        FloatRGBA::new(f(self.r), f(self.g), f(self.b), f(self.a))
+    }
+
+    fn from_float(color: FloatRGBA) -> Self {
+        let q = quantize_u16;
+        Rgba16::new(q(color.r), q(color.g), q(color.b), q(color.a))
+    }
+}
+
+impl From<Luma8> for LumaA8 {
+    fn from(pixel: Luma8) -> Self {
+        LumaA8::new(pixel.luma, 0xFF)
+    }
+}
+
+impl From<Luma8> for Rgb8 {
+    fn from(pixel: Luma8) -> Self {
+        Rgb8::new(pixel.luma, pixel.luma, pixel.luma)
+    }
+}
+
+impl From<Luma8> for RGBA {
+    fn from(pixel: Luma8) -> Self {
+        RGBA::new(pixel.luma, pixel.luma, pixel.luma, 0xFF)
+    }
+}
+
+impl From<LumaA8> for RGBA {
+    fn from(pixel: LumaA8) -> Self {
+        RGBA::new(pixel.luma, pixel.luma, pixel.luma, pixel.a)
+    }
+}
+
+impl From<Rgb8> for RGBA {
+    fn from(pixel: Rgb8) -> Self {
+        RGBA::new(pixel.r, pixel.g, pixel.b, 0xFF)
+    }
+}
+
+impl From<RGBA> for Rgba16 {
+    fn from(pixel: RGBA) -> Self {
+        // Repeating the byte maps 0xFF to 0xFFFF
+        let widen = |c: u8| u16::from(c) * 0x101;
+        Rgba16::new(
+            widen(pixel.r),
+            widen(pixel.g),
+            widen(pixel.b),
+            widen(pixel.a),
+        )
+    }
+}
+
+impl From<RGBA> for FloatRGBA {
+    fn from(pixel: RGBA) -> Self {
+        pixel.to_float()
+    }
+}
+
+impl From<Rgba16> for FloatRGBA {
+    fn from(pixel: Rgba16) -> Self {
+        pixel.to_float()
+    }
+}
+
+/// An image made of a certain kind of pixel, which is 8 bit RGBA by default
+#[derive(Clone)]
+pub struct Image<P: Pixel = RGBA> {
+    // The raw data stored with every channel of every pixel in a row.
     //
-    // We use this raw representation instead of storing RGBA, because
+    // We use this raw representation instead of storing pixels, because
     // it's the preferred format for passing to renderers like SDL,
     // which is one of the more common uses of this type.
-    data: Vec<u8>,
-    // How many pixels are in a row
+    data: Vec<P::Subpixel>,
+    // How many pixels are in a row, which views keep as their stride
     row_width: usize,
     /// How many pixels are in a row of the image
     pub width: u32,
@@ -38,14 +338,42 @@ pub struct Image {
     pub height: u32,
 }
 
-impl Image {
+/// Panic unless a rectangle fits inside an image of a certain size
+fn check_rectangle(outer: (u32, u32), x: u32, y: u32, width: u32, height: u32) {
+    let fits = |start: u32, len: u32, max: u32| u64::from(start) + u64::from(len) <= u64::from(max);
+    assert!(
+        fits(x, width, outer.0) && fits(y, height, outer.1),
+        "a {}x{} rectangle at ({}, {}) doesn't fit inside a {}x{} image",
+        width,
+        height,
+        x,
+        y,
+        outer.0,
+        outer.1
+    );
+}
+
+/// Find the raw channels holding a rectangle, with rows `row_width` pixels apart
+///
+/// This ends right after the last pixel of the rectangle, instead of at the
+/// end of the row it's in.
+fn rectangle<P: Pixel>(row_width: usize, x: u32, y: u32, width: u32, height: u32) -> Range<usize> {
+    if width == 0 || height == 0 {
+        return 0..0;
+    }
+    let start = P::CHANNELS * (row_width * (y as usize) + (x as usize));
+    let len = P::CHANNELS * (row_width * (height as usize - 1) + (width as usize));
+    start..start + len
+}
+
+impl<P: Pixel> Image<P> {
     /// Construct a new image of certain dimensions
     ///
     /// The image will be completely filled with black, transparent pixels.
-    pub fn new(width: u32, height: u32) -> Image {
+    pub fn new(width: u32, height: u32) -> Image<P> {
         let row_width = width as usize;
         let row_height = height as usize;
-        let data = vec![0; RGBA_BYTES * row_width * row_height];
+        let data = vec![P::Subpixel::default(); P::CHANNELS * row_width * row_height];
         Image {
             data,
             row_width,
@@ -63,74 +391,636 @@ impl Image {
     ///
     /// This function doesn't check whether or not the pixel is in the
     /// bounds of the image.
-    pub fn read(&self, x: u32, y: u32) -> RGBA {
-        let i = RGBA_BYTES * (self.row_width * (y as usize) + (x as usize));
-        let r = self.data[i];
-        let g = self.data[i + 1];
-        let b = self.data[i + 2];
-        let a = self.data[i + 3];
-        RGBA { r, g, b, a }
+    pub fn read(&self, x: u32, y: u32) -> P {
+        let i = P::CHANNELS * (self.row_width * (y as usize) + (x as usize));
+        P::from_channels(&self.data[i..i + P::CHANNELS])
     }
 
     /// Write a pixel at a specific spot in the iamge
     ///
     /// This function doesn't check whether or not the pixel is in the bounds
     /// of the image.
-    pub fn write(&mut self, x: u32, y: u32, pixel: RGBA) {
-        let i = RGBA_BYTES * (self.row_width * (y as usize) + (x as usize));
-        self.data[i] = pixel.r;
-        self.data[i + 1] = pixel.g;
-        self.data[i + 2] = pixel.b;
-        self.data[i + 3] = pixel.a;
+    pub fn write(&mut self, x: u32, y: u32, pixel: P) {
+        let i = P::CHANNELS * (self.row_width * (y as usize) + (x as usize));
+        pixel.to_channels(&mut self.data[i..i + P::CHANNELS]);
+    }
+
+    /// The raw channels of the image, pixel after pixel, row after row
+    pub fn as_raw(&self) -> &[P::Subpixel] {
+        &self.data
+    }
+
+    /// Borrow the whole image as a view
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            data: &self.data,
+            row_width: self.row_width,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Borrow the whole image as a view that can be written to
+    pub fn as_view_mut(&mut self) -> ImageViewMut<'_, P> {
+        ImageViewMut {
+            data: &mut self.data,
+            row_width: self.row_width,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Borrow a rectangle of this image, without copying it
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle doesn't fit inside the image.
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> ImageView<'_, P> {
+        self.as_view().view(x, y, width, height)
+    }
+
+    /// Borrow a rectangle of this image to write to, without copying it
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle doesn't fit inside the image.
+    pub fn view_mut(&mut self, x: u32, y: u32, width: u32, height: u32) -> ImageViewMut<'_, P> {
+        self.as_view_mut().into_view_mut(x, y, width, height)
+    }
+
+    /// Convert every pixel of this image into another kind of pixel
+    ///
+    /// See `Pixel::convert` for when this loses information.
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        self.as_view().convert()
+    }
+
+    /// Construct an image by calling a function with the coordinates of each pixel
+    pub fn from_fn<F: FnMut(u32, u32) -> P>(width: u32, height: u32, mut f: F) -> Image<P> {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.write(x, y, f(x, y));
+            }
+        }
+        image
+    }
+
+    /// Construct an image from its raw channels, pixel after pixel, row after row
+    ///
+    /// This returns `None` if there aren't exactly enough channels for an
+    /// image of this size.
+    pub fn from_raw(width: u32, height: u32, data: Vec<P::Subpixel>) -> Option<Image<P>> {
+        let expected = (P::CHANNELS as u64) * u64::from(width) * u64::from(height);
+        if data.len() as u64 != expected {
+            return None;
+        }
+        Some(Image {
+            data,
+            row_width: width as usize,
+            width,
+            height,
+        })
+    }
+
+    /// Take the raw channels out of the image, pixel after pixel, row after row
+    pub fn into_raw(self) -> Vec<P::Subpixel> {
+        self.data
+    }
+
+    /// Read a pixel, or get `None` if it's outside of the image
+    pub fn get(&self, x: u32, y: u32) -> Option<P> {
+        self.as_view().get(x, y)
+    }
+
+    /// Borrow the channels of a pixel to write to, or get `None` if it's outside of the image
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut [P::Subpixel]> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        let i = P::CHANNELS * (self.row_width * (y as usize) + (x as usize));
+        Some(&mut self.data[i..i + P::CHANNELS])
     }
 
-    /// Fill a texture with the pixels in this image
-    pub fn fill(&self, texture: &mut Texture) -> Result<(), UpdateTextureError> {
-        texture.update(None, &self.data, RGBA_BYTES * self.row_width)
+    /// Iterate over the pixels of the image, along with their coordinates
+    pub fn enumerate_pixels(&self) -> EnumeratePixels<'_, P> {
+        EnumeratePixels {
+            pixels: self.into_iter(),
+        }
+    }
+
+    /// Iterate over the channels of each pixel, to write to them
+    pub fn pixels_mut(&mut self) -> PixelsMut<'_, P> {
+        PixelsMut::new(&mut self.data)
+    }
+
+    /// Iterate over the rows of the image, from the top down
+    pub fn rows(&self) -> Rows<'_, P> {
+        Rows {
+            view: self.as_view(),
+            front: 0,
+            back: self.height,
+        }
+    }
+
+    /// Iterate over the rows of the image to write to them, from the top down
+    pub fn rows_mut(&mut self) -> RowsMut<'_, P> {
+        RowsMut {
+            data: &mut self.data,
+            row_size: P::CHANNELS * self.row_width,
+            rows: self.height as usize,
+        }
     }
 }
 
-/// Represents an iterator over the pixels of an image
-pub struct ImageIterator<'a> {
-    image: &'a Image,
-    index: usize,
+/// A rectangle of an image, borrowed without copying
+///
+/// Views have the same operations as images, but their rows aren't next to
+/// each other in memory, since the rest of each row of the parent image comes
+/// in between.
+#[derive(Clone, Copy)]
+pub struct ImageView<'a, P: Pixel = RGBA> {
+    // The channels from the first pixel of the view to its last one
+    data: &'a [P::Subpixel],
+    // How many pixels are in a row of the parent image
+    row_width: usize,
+    /// How many pixels are in a row of the view
+    pub width: u32,
+    /// How many rows of pixels there are
+    pub height: u32,
 }
 
-impl<'a> ImageIterator<'a> {
-    fn new(image: &'a Image) -> Self {
-        ImageIterator { image, index: 0 }
+impl<'a, P: Pixel> ImageView<'a, P> {
+    /// Check whether or not x and y are in the bounds of this view
+    pub fn in_bounds(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height
+    }
+
+    /// Read a pixel at a specific spot in the view
+    ///
+    /// This function doesn't check whether or not the pixel is in the
+    /// bounds of the view.
+    pub fn read(&self, x: u32, y: u32) -> P {
+        let i = P::CHANNELS * (self.row_width * (y as usize) + (x as usize));
+        P::from_channels(&self.data[i..i + P::CHANNELS])
+    }
+
+    /// Read a pixel, or get `None` if it's outside of the view
+    pub fn get(&self, x: u32, y: u32) -> Option<P> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        Some(self.read(x, y))
     }
+
+    /// How many pixels apart the start of each row is in memory
+    pub fn stride(&self) -> usize {
+        self.row_width
+    }
+
+    /// The raw channels of the view, from its first pixel to its last
+    ///
+    /// The start of each row is `stride` pixels after the last one, so this
+    /// includes the pixels of the parent image in between.
+    pub fn as_raw(&self) -> &'a [P::Subpixel] {
+        self.data
+    }
+
+    /// Borrow a rectangle of this view
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle doesn't fit inside the view.
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> ImageView<'a, P> {
+        check_rectangle((self.width, self.height), x, y, width, height);
+        let range = rectangle::<P>(self.row_width, x, y, width, height);
+        ImageView {
+            data: &self.data[range],
+            row_width: self.row_width,
+            width,
+            height,
+        }
+    }
+
+    /// Copy the pixels in this view into a new image
+    pub fn to_image(&self) -> Image<P> {
+        let row_size = P::CHANNELS * self.width as usize;
+        let mut data = Vec::with_capacity(row_size * self.height as usize);
+        for y in 0..self.height as usize {
+            let start = P::CHANNELS * self.row_width * y;
+            data.extend_from_slice(&self.data[start..start + row_size]);
+        }
+        Image {
+            data,
+            row_width: self.width as usize,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Convert every pixel of this view into another kind of pixel, in a new image
+    ///
+    /// See `Pixel::convert` for when this loses information.
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        let mut out = Image::new(self.width, self.height);
+        for (i, pixel) in self.into_iter().enumerate() {
+            let start = i * Q::CHANNELS;
+            pixel
+                .convert::<Q>()
+                .to_channels(&mut out.data[start..start + Q::CHANNELS]);
+        }
+        out
+    }
+}
+
+impl<'a, P: Pixel> From<&'a Image<P>> for ImageView<'a, P> {
+    fn from(image: &'a Image<P>) -> Self {
+        image.as_view()
+    }
+}
+
+/// A rectangle of an image that can be written to, borrowed without copying
+pub struct ImageViewMut<'a, P: Pixel = RGBA> {
+    // The channels from the first pixel of the view to its last one
+    data: &'a mut [P::Subpixel],
+    // How many pixels are in a row of the parent image
+    row_width: usize,
+    /// How many pixels are in a row of the view
+    pub width: u32,
+    /// How many rows of pixels there are
+    pub height: u32,
+}
+
+impl<'a, P: Pixel> ImageViewMut<'a, P> {
+    /// Check whether or not x and y are in the bounds of this view
+    pub fn in_bounds(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height
+    }
+
+    /// Read a pixel at a specific spot in the view
+    ///
+    /// This function doesn't check whether or not the pixel is in the
+    /// bounds of the view.
+    pub fn read(&self, x: u32, y: u32) -> P {
+        self.as_view().read(x, y)
+    }
+
+    /// Write a pixel at a specific spot in the view
+    ///
+    /// This function doesn't check whether or not the pixel is in the bounds
+    /// of the view.
+    pub fn write(&mut self, x: u32, y: u32, pixel: P) {
+        let i = P::CHANNELS * (self.row_width * (y as usize) + (x as usize));
+        pixel.to_channels(&mut self.data[i..i + P::CHANNELS]);
+    }
+
+    /// Borrow this view, without being able to write to it
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            data: self.data,
+            row_width: self.row_width,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Borrow a rectangle of this view to write to
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle doesn't fit inside the view.
+    pub fn view_mut(&mut self, x: u32, y: u32, width: u32, height: u32) -> ImageViewMut<'_, P> {
+        ImageViewMut {
+            data: self.data,
+            row_width: self.row_width,
+            width: self.width,
+            height: self.height,
+        }
+        .into_view_mut(x, y, width, height)
+    }
+
+    /// Turn this view into one of a rectangle inside of it
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle doesn't fit inside the view.
+    pub fn into_view_mut(self, x: u32, y: u32, width: u32, height: u32) -> ImageViewMut<'a, P> {
+        check_rectangle((self.width, self.height), x, y, width, height);
+        let range = rectangle::<P>(self.row_width, x, y, width, height);
+        ImageViewMut {
+            data: &mut self.data[range],
+            row_width: self.row_width,
+            width,
+            height,
+        }
+    }
+
+    /// Copy the pixels of another view of the same size into this one
+    ///
+    /// # Panics
+    ///
+    /// If the views aren't the same size.
+    pub fn copy_from(&mut self, source: ImageView<P>) {
+        assert_eq!(
+            (self.width, self.height),
+            (source.width, source.height),
+            "views of different sizes"
+        );
+        let row_size = P::CHANNELS * self.width as usize;
+        for y in 0..self.height as usize {
+            let to = P::CHANNELS * self.row_width * y;
+            let from = P::CHANNELS * source.row_width * y;
+            self.data[to..to + row_size].copy_from_slice(&source.data[from..from + row_size]);
+        }
+    }
+}
+
+impl<'a, P: Pixel> From<&'a mut Image<P>> for ImageViewMut<'a, P> {
+    fn from(image: &'a mut Image<P>) -> Self {
+        image.as_view_mut()
+    }
+}
+
+/// Represents an iterator over the pixels of an image, or a view of one
+pub struct ImageIterator<'a, P: Pixel = RGBA> {
+    view: ImageView<'a, P>,
+    // The index of the next pixel from the front, in row order
+    front: usize,
+    // The index after the next pixel from the back
+    back: usize,
+}
+
+impl<'a, P: Pixel> ImageIterator<'a, P> {
+    fn new(view: ImageView<'a, P>) -> Self {
+        let back = view.width as usize * view.height as usize;
+        ImageIterator {
+            view,
+            front: 0,
+            back,
+        }
+    }
+
+    /// The coordinates of the pixel at an index, in row order
+    fn coordinates(&self, index: usize) -> (u32, u32) {
+        let width = self.view.width as usize;
+        ((index % width) as u32, (index / width) as u32)
+    }
+}
+
+impl<'a, P: Pixel> Iterator for ImageIterator<'a, P> {
+    type Item = P;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let (x, y) = self.coordinates(self.front);
+        self.front += 1;
+        Some(self.view.read(x, y))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<'a, P: Pixel> DoubleEndedIterator for ImageIterator<'a, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.back -= 1;
+        let (x, y) = self.coordinates(self.back);
+        Some(self.view.read(x, y))
+    }
+}
+
+impl<'a, P: Pixel> ExactSizeIterator for ImageIterator<'a, P> {}
+
+/// An iterator over the pixels of an image, along with their coordinates
+pub struct EnumeratePixels<'a, P: Pixel = RGBA> {
+    pixels: ImageIterator<'a, P>,
+}
+
+impl<'a, P: Pixel> Iterator for EnumeratePixels<'a, P> {
+    type Item = (u32, u32, P);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pixel = self.pixels.next()?;
+        let (x, y) = self.pixels.coordinates(self.pixels.front - 1);
+        Some((x, y, pixel))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
+    }
+}
+
+impl<'a, P: Pixel> DoubleEndedIterator for EnumeratePixels<'a, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let pixel = self.pixels.next_back()?;
+        let (x, y) = self.pixels.coordinates(self.pixels.back);
+        Some((x, y, pixel))
+    }
+}
+
+impl<'a, P: Pixel> ExactSizeIterator for EnumeratePixels<'a, P> {}
+
+/// An iterator over the rows of an image, each of which iterates over its pixels
+pub struct Rows<'a, P: Pixel = RGBA> {
+    view: ImageView<'a, P>,
+    front: u32,
+    back: u32,
+}
+
+impl<'a, P: Pixel> Iterator for Rows<'a, P> {
+    type Item = ImageIterator<'a, P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let row = self.view.view(0, self.front, self.view.width, 1);
+        self.front += 1;
+        Some(row.into_iter())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.back - self.front) as usize;
+        (len, Some(len))
+    }
+}
+
+impl<'a, P: Pixel> DoubleEndedIterator for Rows<'a, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.view.view(0, self.back, self.view.width, 1).into_iter())
+    }
+}
+
+impl<'a, P: Pixel> ExactSizeIterator for Rows<'a, P> {}
+
+/// An iterator over the channels of each pixel in an image, which can be written to
+///
+/// A pixel can be written into its channels with `Pixel::to_channels`.
+pub struct PixelsMut<'a, P: Pixel = RGBA> {
+    chunks: ChunksExactMut<'a, P::Subpixel>,
+}
+
+impl<'a, P: Pixel> PixelsMut<'a, P> {
+    fn new(data: &'a mut [P::Subpixel]) -> Self {
+        PixelsMut {
+            chunks: data.chunks_exact_mut(P::CHANNELS),
+        }
+    }
+}
+
+impl<'a, P: Pixel> Iterator for PixelsMut<'a, P> {
+    type Item = &'a mut [P::Subpixel];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a, P: Pixel> DoubleEndedIterator for PixelsMut<'a, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.chunks.next_back()
+    }
+}
+
+impl<'a, P: Pixel> ExactSizeIterator for PixelsMut<'a, P> {}
+
+/// An iterator over the rows of an image, each of which can be written to
+pub struct RowsMut<'a, P: Pixel = RGBA> {
+    // The channels of the rows we haven't gone over yet
+    data: &'a mut [P::Subpixel],
+    row_size: usize,
+    rows: usize,
 }
 
-impl<'a> Iterator for ImageIterator<'a> {
-    type Item = RGBA;
+impl<'a, P: Pixel> Iterator for RowsMut<'a, P> {
+    type Item = PixelsMut<'a, P>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_index = self.index + 4;
-        if next_index > self.image.data.len() {
+        if self.rows == 0 {
+            return None;
+        }
+        self.rows -= 1;
+        let (row, rest) = mem::take(&mut self.data).split_at_mut(self.row_size);
+        self.data = rest;
+        Some(PixelsMut::new(row))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.rows, Some(self.rows))
+    }
+}
+
+impl<'a, P: Pixel> DoubleEndedIterator for RowsMut<'a, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.rows == 0 {
             return None;
         }
-        let r = self.image.data[self.index];
-        self.index += 1;
-        let g = self.image.data[self.index];
-        self.index += 1;
-        let b = self.image.data[self.index];
-        self.index += 1;
-        let a = self.image.data[self.index];
-        self.index += 1;
-        Some(RGBA { r, g, b, a })
+        self.rows -= 1;
+        let data = mem::take(&mut self.data);
+        let (rest, row) = data.split_at_mut(data.len() - self.row_size);
+        self.data = rest;
+        Some(PixelsMut::new(row))
+    }
+}
+
+impl<'a, P: Pixel> ExactSizeIterator for RowsMut<'a, P> {}
+
+impl<'a, P: Pixel> IntoIterator for &'a Image<P> {
+    type Item = P;
+    type IntoIter = ImageIterator<'a, P>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        ImageIterator::new(self.as_view())
     }
 }
 
-impl<'a> IntoIterator for &'a Image {
-    type Item = RGBA;
-    type IntoIter = ImageIterator<'a>;
+impl<'a, P: Pixel> IntoIterator for ImageView<'a, P> {
+    type Item = P;
+    type IntoIter = ImageIterator<'a, P>;
 
     fn into_iter(self) -> Self::IntoIter {
         ImageIterator::new(self)
     }
 }
 
+impl<'a, P: Pixel> IntoIterator for &ImageView<'a, P> {
+    type Item = P;
+    type IntoIter = ImageIterator<'a, P>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        ImageIterator::new(*self)
+    }
+}
+
+/// Represents a color with floating point components
+///
+/// Unlike `RGBA`, the components aren't limited to a fixed range, which
+/// lets us hold the high dynamic range data in formats like Radiance HDR.
+/// A component of 1.0 is the brightest a normal display can show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FloatRGBA {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl FloatRGBA {
+    /// Construct a new floating point pixel from the components
+    pub fn new(r: f32, g: f32, b: f32, a: f32) -> FloatRGBA {
+        FloatRGBA { r, g, b, a }
+    }
+}
+
+/// A 32 bit floating point RGBA pixel, under the same naming as the other kinds of pixel
+pub type Rgba32F = FloatRGBA;
+
+impl Pixel for FloatRGBA {
+    type Subpixel = f32;
+    const CHANNELS: usize = 4;
+
+    fn from_channels(c: &[f32]) -> Self {
+        FloatRGBA::new(c[0], c[1], c[2], c[3])
+    }
+
+    fn to_channels(self, out: &mut [f32]) {
+        out[0] = self.r;
+        out[1] = self.g;
+        out[2] = self.b;
+        out[3] = self.a;
+    }
+
+    fn to_float(self) -> FloatRGBA {
+        self
+    }
+
+    // Floating point pixels keep whatever range they're given
+    fn from_float(color: FloatRGBA) -> Self {
+        color
+    }
+}
+
+/// An image with a floating point number for each component
+///
+/// This is the counterpart to `Image` for high dynamic range formats. To
+/// display one of these, it needs to be tone mapped into an `Image` first.
+pub type FloatImage = Image<FloatRGBA>;
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -144,4 +1034,94 @@ mod test {
         assert_eq!(image.read(0, 0), red);
         assert_eq!(image.read(1, 0), red);
     }
+
+    #[test]
+    fn test_convert() {
+        let mut gray = Image::new(2, 1);
+        gray.write(1, 0, Luma8::new(0x80));
+        assert_eq!(gray.as_raw(), &[0, 0x80]);
+        let rgba: Image = gray.convert();
+        assert_eq!(rgba.read(1, 0), RGBA::from(Luma8::new(0x80)));
+        assert_eq!(rgba.as_raw().len(), 8);
+        // Going through every other kind of pixel and back loses nothing
+        let back: Image<Luma8> = rgba
+            .convert::<Rgba16>()
+            .convert::<FloatRGBA>()
+            .convert::<Rgb8>()
+            .convert();
+        assert_eq!(back.as_raw(), gray.as_raw());
+        let color = RGBA::new(0x12, 0x34, 0x56, 0x78);
+        assert_eq!(Rgba16::from(color).convert::<RGBA>(), color);
+        assert_eq!(Rgba16::from(color).r, 0x1212);
+        // Lossy conversions drop alpha, and keep the luma of colors
+        assert_eq!(color.convert::<Rgb8>(), Rgb8::new(0x12, 0x34, 0x56));
+        assert_eq!(
+            RGBA::new(0xFF, 0, 0, 0xFF).convert::<Luma8>(),
+            Luma8::new(76)
+        );
+    }
+
+    #[test]
+    fn test_views() {
+        let mut image = Image::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                image.write(x, y, Luma8::new((10 * y + x) as u8));
+            }
+        }
+        let view = image.view(1, 1, 3, 2);
+        assert_eq!(view.read(0, 0), Luma8::new(11));
+        assert_eq!(view.stride(), 4);
+        let inner = view.view(1, 0, 2, 2);
+        let pixels: Vec<u8> = inner.into_iter().map(|p| p.luma).collect();
+        assert_eq!(pixels, vec![12, 13, 22, 23]);
+        assert_eq!(inner.to_image().as_raw(), &[12, 13, 22, 23]);
+        // Writing to one corner, from another
+        let corner = image.view(0, 0, 2, 1).to_image();
+        let mut view = image.view_mut(2, 2, 2, 1);
+        view.copy_from(corner.as_view());
+        view.view_mut(0, 0, 1, 1).write(0, 0, Luma8::new(99));
+        assert_eq!(image.read(2, 2), Luma8::new(99));
+        assert_eq!(image.read(3, 2), Luma8::new(1));
+    }
+
+    #[test]
+    fn test_access() {
+        let mut image = Image::from_fn(3, 2, |x, y| Luma8::new((10 * y + x) as u8));
+        assert_eq!(image.get(2, 1), Some(Luma8::new(12)));
+        assert_eq!(image.get(3, 0), None);
+        image.get_mut(0, 1).unwrap()[0] = 99;
+        assert!(image.get_mut(0, 2).is_none());
+        let last = image.enumerate_pixels().next_back();
+        assert_eq!(last, Some((2, 1, Luma8::new(12))));
+        assert_eq!(image.enumerate_pixels().len(), 6);
+        assert!(Image::<Luma8>::new(0, 3)
+            .enumerate_pixels()
+            .next()
+            .is_none());
+        let mut pixels = image.into_iter();
+        pixels.next();
+        assert_eq!(pixels.len(), 5);
+        assert_eq!(pixels.next_back(), Some(Luma8::new(12)));
+        let rows: Vec<Vec<u8>> = image.rows().map(|r| r.map(|p| p.luma).collect()).collect();
+        assert_eq!(rows, vec![vec![0, 1, 2], vec![99, 11, 12]]);
+        for (y, row) in image.rows_mut().rev().enumerate() {
+            for channels in row {
+                channels[0] = y as u8;
+            }
+        }
+        for channels in image.pixels_mut().take(1) {
+            Luma8::new(7).to_channels(channels);
+        }
+        let raw = image.into_raw();
+        assert_eq!(raw, vec![7, 1, 1, 0, 0, 0]);
+        assert!(Image::<Luma8>::from_raw(3, 2, raw.clone()).is_some());
+        assert!(Image::<RGBA>::from_raw(3, 2, raw).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_view_outside() {
+        Image::<RGBA>::new(2, 2).view(1, 1, 2, 1);
+    }
 }
@@ -0,0 +1,188 @@
+use std::fmt;
+use std::fmt::Write;
+// This module describes what's inside an image file, without the pixels.
+// Every decoder can give us the dimensions, by decoding the image, but
+// formats can also fill in everything they know from their headers.
+
+/// A value in the headers of a file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Number(i64::from(n))
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Number(i64::from(n))
+    }
+}
+
+impl From<u8> for Value {
+    fn from(n: u8) -> Self {
+        Value::Number(i64::from(n))
+    }
+}
+
+impl From<u16> for Value {
+    fn from(n: u16) -> Self {
+        Value::Number(i64::from(n))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Self {
+        Value::Text(s.into())
+    }
+}
+
+/// Everything we know about an image file
+///
+/// Anything a format can't tell us is left as `None`.
+#[derive(Clone, Debug, Default)]
+pub struct Info {
+    /// The name of the format, as in the codec registry
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// How many bits each pixel takes up in the file
+    pub bit_depth: Option<u32>,
+    /// The channels in each pixel, like "rgba" or "grayscale"
+    pub color_type: Option<String>,
+    pub compression: Option<String>,
+    /// The horizontal and vertical resolution, in dots per inch
+    pub dpi: Option<(f64, f64)>,
+    /// How many colors there are in the palette, with 0 meaning there's none
+    pub palette_size: Option<u32>,
+    /// Whether or not the file contains metadata, like comments or color profiles
+    pub metadata: Option<bool>,
+    /// The fields in the headers of the file, in the order they appear
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Info {
+    /// Create the information for an image we only know the size of
+    pub fn new(width: u32, height: u32) -> Self {
+        Info {
+            width,
+            height,
+            ..Info::default()
+        }
+    }
+
+    /// Add a field from the headers of the file
+    pub fn field<V: Into<Value>>(&mut self, name: &str, value: V) {
+        self.fields.push((name.into(), value.into()));
+    }
+
+    /// Write this information out as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        // Writing to a string can't fail
+        let _ = write!(
+            out,
+            "{{\"format\":{},\"width\":{},\"height\":{}",
+            json_string(self.format),
+            self.width,
+            self.height
+        );
+        let _ = write!(out, ",\"bit_depth\":{}", json_option(&self.bit_depth));
+        let color_type = self.color_type.as_deref().map(json_string);
+        let _ = write!(out, ",\"color_type\":{}", json_option(&color_type));
+        let compression = self.compression.as_deref().map(json_string);
+        let _ = write!(out, ",\"compression\":{}", json_option(&compression));
+        let dpi = self.dpi.map(|(x, y)| format!("[{},{}]", x, y));
+        let _ = write!(out, ",\"dpi\":{}", json_option(&dpi));
+        let _ = write!(out, ",\"palette_size\":{}", json_option(&self.palette_size));
+        let _ = write!(out, ",\"metadata\":{}", json_option(&self.metadata));
+        out.push_str(",\"fields\":{");
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let value = match value {
+                Value::Number(n) => n.to_string(),
+                Value::Text(s) => json_string(s),
+            };
+            let _ = write!(out, "{}:{}", json_string(name), value);
+        }
+        out.push_str("}}");
+        out
+    }
+}
+
+fn json_option<T: fmt::Display>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "null".into(),
+    }
+}
+
+/// Quote a string for JSON, escaping what needs to be
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Convert a resolution in pixels per meter into dots per inch
+pub fn dpi_from_meters(pixels_per_meter: u32) -> f64 {
+    // Rounding to a tenth hides the error from storing whole pixels per meter
+    (f64::from(pixels_per_meter) * 0.0254 * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut info = Info::new(2, 1);
+        info.format = "bmp";
+        info.dpi = Some((72.0, 72.5));
+        info.metadata = Some(false);
+        info.field("FileHeader.size", 138u32);
+        info.field("name", "a \"b\"\n");
+        assert_eq!(
+            info.to_json(),
+            "{\"format\":\"bmp\",\"width\":2,\"height\":1,\"bit_depth\":null,\
+             \"color_type\":null,\"compression\":null,\"dpi\":[72,72.5],\
+             \"palette_size\":null,\"metadata\":false,\
+             \"fields\":{\"FileHeader.size\":138,\"name\":\"a \\\"b\\\"\\n\"}}"
+        );
+        assert_eq!(dpi_from_meters(2835), 72.0);
+    }
+}
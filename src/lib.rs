@@ -0,0 +1,433 @@
+//! Mage reads and writes images in a variety of formats.
+//!
+//! The simplest way to use the library is through `load` and `save`, which
+//! figure out the format of a file from its contents and its name:
+//!
+//! ```no_run
+//! let image = mage::load("photo.bmp")?;
+//! mage::save("photo.png", &image)?;
+//! # Ok::<(), mage::MageError>(())
+//! ```
+//!
+//! High dynamic range formats decode into a `FloatImage`, which `load` tone
+//! maps for us. To keep the original data, use `load_with`, which returns a
+//! `Decoded` image, and `save_with` to write it out again.
+
+pub mod bmp;
+pub mod codec;
+pub mod composite;
+pub mod dds;
+pub mod dissect;
+pub mod error;
+pub mod exr;
+pub mod format;
+pub mod hdr;
+mod huffman;
+pub mod ilbm;
+pub mod image;
+pub mod info;
+pub mod limits;
+pub mod pcx;
+pub mod png;
+pub mod psd;
+pub mod sgi;
+pub mod sunras;
+pub mod tonemap;
+pub mod validate;
+pub mod webp;
+pub mod xbm;
+pub mod xpm;
+mod zlib;
+
+pub use crate::codec::{Codec, DecodeOptions, Decoded, EncodeOptions};
+pub use crate::error::{MageError, MageResult};
+pub use crate::image::{
+    FloatImage, FloatRGBA, Image, ImageView, ImageViewMut, Luma8, LumaA8, Pixel, Rgb8, Rgba16,
+    Rgba32F, Rgba8, RGBA,
+};
+pub use crate::info::Info;
+pub use crate::limits::Limits;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+fn unknown_name(name: &str) -> MageError {
+    MageError::UnsupportedFormat {
+        format: None,
+        message: format!("unknown format '{}'", name),
+    }
+}
+
+/// Decode an image from a reader, using the name of its file as a hint
+///
+/// The format is detected from the first few bytes, which are then put back
+/// in front of the rest, so that the decoder sees the whole file.
+fn decode_from(
+    reader: &mut dyn BufRead,
+    path: Option<&Path>,
+    options: &DecodeOptions,
+) -> MageResult<(Decoded, Vec<String>)> {
+    let name = path.and_then(Path::to_str);
+    // A single read from a pipe might not be enough to go on
+    let mut prefix = Vec::with_capacity(format::SNIFF_LEN);
+    Read::take(&mut *reader, format::SNIFF_LEN as u64).read_to_end(&mut prefix)?;
+    let codec = match format::detect(&prefix, name) {
+        Some(detected) => detected.codec,
+        None => {
+            return Err(MageError::unknown_format(&match path {
+                Some(path) => format!("'{}'", path.display()),
+                None => "the data".into(),
+            }))
+        }
+    };
+    decode_codec(&mut io::Cursor::new(prefix).chain(reader), codec, options)
+}
+
+/// Decode an image from a reader, with a codec we already know
+fn decode_codec(
+    reader: &mut dyn BufRead,
+    codec: &Codec,
+    options: &DecodeOptions,
+) -> MageResult<(Decoded, Vec<String>)> {
+    let (image, warnings) = match codec.decoder {
+        Some(decoder) => decoder.decode_with_warnings(reader, options)?,
+        None => {
+            return Err(MageError::unsupported(
+                codec.name,
+                "reading this format is not supported",
+            ))
+        }
+    };
+    // Nearly every use of the image turns it into ordinary pixels first
+    image.check_low(&options.limits)?;
+    Ok((image, warnings))
+}
+
+/// Read an image from a file, detecting its format
+///
+/// High dynamic range images are tone mapped with Reinhard's operator.
+///
+/// # Examples
+///
+/// ```
+/// let image = mage::load("test_images/1.bmp")?;
+/// assert_eq!((image.width, image.height), (2, 2));
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn load<P: AsRef<Path>>(path: P) -> MageResult<Image> {
+    Ok(load_with(path, &DecodeOptions::default())?.into_low(None))
+}
+
+/// Read an image from a file, keeping the kind of pixels it stores
+///
+/// # Examples
+///
+/// ```
+/// use mage::{DecodeOptions, Decoded};
+///
+/// match mage::load_with("test_images/1.bmp", &DecodeOptions::default())? {
+///     Decoded::Low(image) => assert_eq!(image.width, 2),
+///     _ => unreachable!(),
+/// }
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn load_with<P: AsRef<Path>>(path: P, options: &DecodeOptions) -> MageResult<Decoded> {
+    Ok(load_with_warnings(path, options)?.0)
+}
+
+/// Read an image from a file, along with warnings about what was recovered
+///
+/// Warnings only come up when decoding leniently, describing the problems
+/// in the file that we worked around.
+pub fn load_with_warnings<P: AsRef<Path>>(
+    path: P,
+    options: &DecodeOptions,
+) -> MageResult<(Decoded, Vec<String>)> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| MageError::with_path(path, e))?;
+    let mut reader = BufReader::new(file);
+    decode_from(&mut reader, Some(path), options)
+}
+
+/// Decode an image held in memory, detecting its format from the contents
+///
+/// # Examples
+///
+/// ```
+/// let xpm = b"/* XPM */
+/// static char *dot[] = {
+/// \"1 1 1 1\",
+/// \"x c #FF0000\",
+/// \"x\"
+/// };";
+/// let image = mage::load_from_memory(xpm)?;
+/// assert_eq!(image.read(0, 0), mage::RGBA::new(255, 0, 0, 255));
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn load_from_memory(data: &[u8]) -> MageResult<Image> {
+    Ok(load_from_memory_with(data, &DecodeOptions::default())?.into_low(None))
+}
+
+/// Decode an image held in memory, without converting high dynamic range images
+pub fn load_from_memory_with(mut data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+    Ok(decode_from(&mut data, None, options)?.0)
+}
+
+/// Read an image from any reader, detecting its format from the contents
+///
+/// Formats that support it are decoded as they're read, so this works well
+/// with pipes, sockets, or decompressing readers.
+///
+/// # Examples
+///
+/// ```
+/// let file = std::fs::File::open("test_images/1.bmp")?;
+/// let image = mage::load_from_reader(file)?;
+/// assert_eq!(image.height, 2);
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn load_from_reader<R: Read>(reader: R) -> MageResult<Image> {
+    Ok(decode(BufReader::new(reader), &DecodeOptions::default())?.into_low(None))
+}
+
+/// Decode an image from a buffered reader, without converting high dynamic range images
+///
+/// # Examples
+///
+/// ```
+/// use std::io::BufReader;
+/// use mage::DecodeOptions;
+///
+/// let file = BufReader::new(std::fs::File::open("test_images/1.bmp")?);
+/// let image = mage::decode(file, &DecodeOptions::default())?.into_low(None);
+/// assert_eq!(image.read(1, 0), mage::RGBA::new(0, 255, 0, 255));
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn decode<R: BufRead>(mut reader: R, options: &DecodeOptions) -> MageResult<Decoded> {
+    Ok(decode_from(&mut reader, None, options)?.0)
+}
+
+/// Decode an image from a buffered reader, in the format with a given name
+///
+/// This is useful when the format is known in advance, or can't be detected.
+///
+/// # Examples
+///
+/// ```
+/// use mage::DecodeOptions;
+///
+/// let data: &[u8] = b"#define dot_width 1\n#define dot_height 1\nstatic char dot_bits[] = { 0x01 };";
+/// let image = mage::decode_as(data, "xbm", &DecodeOptions::default())?.into_low(None);
+/// assert_eq!(image.width, 1);
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn decode_as<R: BufRead>(
+    mut reader: R,
+    format: &str,
+    options: &DecodeOptions,
+) -> MageResult<Decoded> {
+    let codec = codec::by_name(format).ok_or_else(|| unknown_name(format))?;
+    Ok(decode_codec(&mut reader, codec, options)?.0)
+}
+
+/// Decode an image from a buffered reader, along with warnings about what was recovered
+///
+/// Without a format, it's detected from the contents. Warnings only come up
+/// when decoding leniently, describing the problems we worked around.
+///
+/// # Examples
+///
+/// ```
+/// use mage::DecodeOptions;
+///
+/// // Cut off the last row of pixels
+/// let data = std::fs::read("test_images/1.bmp")?;
+/// let options = DecodeOptions {
+///     lenient: true,
+///     ..DecodeOptions::default()
+/// };
+/// let (image, warnings) = mage::decode_with_warnings(&data[..130], None, &options)?;
+/// assert_eq!(image.into_low(None).read(0, 1).a, 0);
+/// assert_eq!(warnings.len(), 1);
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn decode_with_warnings<R: BufRead>(
+    mut reader: R,
+    format: Option<&str>,
+    options: &DecodeOptions,
+) -> MageResult<(Decoded, Vec<String>)> {
+    match format {
+        Some(format) => {
+            let codec = codec::by_name(format).ok_or_else(|| unknown_name(format))?;
+            decode_codec(&mut reader, codec, options)
+        }
+        None => decode_from(&mut reader, None, options),
+    }
+}
+
+/// Describe the image in a file, and the headers of the file
+///
+/// # Examples
+///
+/// ```
+/// let info = mage::info("test_images/1.bmp")?;
+/// assert_eq!(info.format, "bmp");
+/// assert_eq!(info.bit_depth, Some(32));
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn info<P: AsRef<Path>>(path: P) -> MageResult<Info> {
+    let path = path.as_ref();
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| MageError::with_path(path, e))?;
+    let codec = match format::detect(&data, path.to_str()) {
+        Some(detected) => detected.codec,
+        None => return Err(MageError::unknown_format(&format!("'{}'", path.display()))),
+    };
+    let mut info = match (codec.decoder, codec.inspector) {
+        (Some(decoder), _) => decoder.info(&data, &DecodeOptions::default())?,
+        (None, Some(inspector)) => inspector.info(&data)?,
+        (None, None) => {
+            return Err(MageError::unsupported(
+                codec.name,
+                "reading this format is not supported",
+            ))
+        }
+    };
+    info.format = codec.name;
+    Ok(info)
+}
+
+/// Find the codec for a format name, or the extension of a path
+fn encoder_for(format: Option<&str>, path: Option<&Path>) -> MageResult<&'static Codec> {
+    let codec = match (format, path) {
+        (Some(name), _) => codec::by_name(name).ok_or_else(|| unknown_name(name))?,
+        (None, Some(path)) => path
+            .to_str()
+            .and_then(codec::by_path)
+            .ok_or_else(|| MageError::unknown_format(&format!("'{}'", path.display())))?,
+        (None, None) => return Err(MageError::unknown_format("the output")),
+    };
+    if codec.encoder.is_none() {
+        return Err(MageError::unsupported(
+            codec.name,
+            "writing this format is not supported",
+        ));
+    }
+    Ok(codec)
+}
+
+/// Turn the stem of a file name into a C identifier, for XBM and XPM
+pub fn identifier(path: &str) -> String {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let stem = name.split('.').next().unwrap_or(name);
+    let mut out: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// Write an image to a file, in the format matching its extension
+///
+/// # Examples
+///
+/// ```no_run
+/// let image = mage::Image::new(16, 16);
+/// mage::save("blank.png", &image)?;
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn save<P: AsRef<Path>>(path: P, image: &Image) -> MageResult<()> {
+    save_with(
+        path,
+        Decoded::Low(image.clone()),
+        None,
+        &EncodeOptions::default(),
+    )
+}
+
+/// Write an image to a file, with an explicit format and options
+///
+/// Without a format, it's picked from the extension of the path. If the
+/// options don't have a name, the stem of the path is used instead.
+pub fn save_with<P: AsRef<Path>>(
+    path: P,
+    image: Decoded,
+    format: Option<&str>,
+    options: &EncodeOptions,
+) -> MageResult<()> {
+    let path = path.as_ref();
+    let codec = encoder_for(format, Some(path))?;
+    let mut options = options.clone();
+    if options.name.is_empty() {
+        options.name = identifier(&path.to_string_lossy());
+    }
+    let file = File::create(path).map_err(|e| MageError::with_path(path, e))?;
+    let mut writer = io::BufWriter::new(file);
+    encode(&mut writer, image, codec.name, &options)?;
+    writer.flush().map_err(|e| MageError::with_path(path, e))
+}
+
+/// Encode an image into any writer, in the format with a given name
+///
+/// # Examples
+///
+/// ```
+/// use mage::{Decoded, EncodeOptions, Image};
+///
+/// let mut out = Vec::new();
+/// let image = Decoded::Low(Image::new(1, 1));
+/// mage::encode(&mut out, image, "png", &EncodeOptions::default())?;
+/// assert!(out.starts_with(b"\x89PNG"));
+/// # Ok::<(), mage::MageError>(())
+/// ```
+pub fn encode<W: Write>(
+    mut writer: W,
+    image: Decoded,
+    format: &str,
+    options: &EncodeOptions,
+) -> MageResult<()> {
+    let codec = encoder_for(Some(format), None)?;
+    // `encoder_for` has already checked that there's an encoder
+    let encoder = codec.encoder.unwrap();
+    encoder.encode(&mut writer, image, options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A reader giving out a single byte at a time, like a slow pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(out)) => {
+                    *out = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_trickle() {
+        let data = std::fs::read("test_images/1.bmp").unwrap();
+        let reader = BufReader::with_capacity(1, Trickle(&data));
+        let image = decode(reader, &DecodeOptions::default()).unwrap();
+        assert_eq!(image.into_low(None).read(1, 0), RGBA::new(0, 255, 0, 255));
+        let xpm = b"/* XPM */\nstatic char *dot[] = {\"1 1 1 1\", \"x c #00F\", \"x\"};";
+        let reader = BufReader::with_capacity(1, Trickle(xpm));
+        let image = decode(reader, &DecodeOptions::default()).unwrap();
+        assert_eq!(image.into_low(None).read(0, 0), RGBA::new(0, 0, 255, 255));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::MageError;
+// Decoders trust the headers of a file to tell them how much memory to
+// allocate, so a tiny file can claim to hold an enormous image. Checking those
+// sizes against limits before allocating anything turns these decompression
+// bombs into errors, instead of running out of memory.
+
+/// The largest images, and other parts of files, that decoders will accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// How many pixels can be in a row of the image
+    pub max_width: u32,
+    /// How many rows of pixels there can be
+    pub max_height: u32,
+    /// How many pixels there can be in total
+    pub max_pixels: u64,
+    /// How many bytes a decoder can allocate for a single buffer
+    pub max_alloc: u64,
+    /// How many bytes of metadata, like comments or attributes, a file can have
+    pub max_metadata: u64,
+    /// How many frames, layers, or elements of a texture array a file can have
+    pub max_frames: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_width: 1 << 16,
+            max_height: 1 << 16,
+            max_pixels: 1 << 28,
+            max_alloc: 1 << 30,
+            max_metadata: 1 << 26,
+            max_frames: 1 << 12,
+        }
+    }
+}
+
+/// The limit that a file went over, and by how much
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitError(pub String);
+
+pub type LimitResult = Result<(), LimitError>;
+
+impl From<LimitError> for MageError {
+    fn from(error: LimitError) -> Self {
+        MageError::LimitsExceeded(error.0)
+    }
+}
+
+fn check(what: &str, value: u64, max: u64) -> LimitResult {
+    if value > max {
+        return Err(LimitError(format!(
+            "{} is {}, but can be at most {}",
+            what, value, max
+        )));
+    }
+    Ok(())
+}
+
+impl Limits {
+    /// Limits that accept anything, for files that we trust
+    pub fn unlimited() -> Self {
+        Limits {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+            max_alloc: u64::MAX,
+            max_metadata: u64::MAX,
+            max_frames: u32::MAX,
+        }
+    }
+
+    /// Check the dimensions of an image, before allocating its pixels
+    ///
+    /// Each pixel takes up a certain number of bytes, which is 4 for `Image`,
+    /// and 16 for `FloatImage`.
+    pub fn check_image(&self, width: u64, height: u64, bytes_per_pixel: u64) -> LimitResult {
+        check("the width", width, u64::from(self.max_width))?;
+        check("the height", height, u64::from(self.max_height))?;
+        let pixels = width.saturating_mul(height);
+        check("the pixel count", pixels, self.max_pixels)?;
+        self.check_alloc(pixels.saturating_mul(bytes_per_pixel))
+    }
+
+    /// Check the size of a buffer, before allocating it
+    pub fn check_alloc(&self, bytes: u64) -> LimitResult {
+        check("the allocation size", bytes, self.max_alloc)
+    }
+
+    /// Check the size of some metadata, before reading it
+    pub fn check_metadata(&self, bytes: u64) -> LimitResult {
+        check("the metadata size", bytes, self.max_metadata)
+    }
+
+    /// Check how many frames, layers, or texture elements there are
+    pub fn check_frames(&self, frames: u64) -> LimitResult {
+        check("the frame count", frames, u64::from(self.max_frames))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_image() {
+        let limits = Limits {
+            max_width: 100,
+            max_pixels: 1000,
+            ..Limits::default()
+        };
+        assert!(limits.check_image(100, 10, 4).is_ok());
+        assert_eq!(
+            limits.check_image(101, 1, 4),
+            Err(LimitError(
+                "the width is 101, but can be at most 100".into()
+            ))
+        );
+        assert!(limits.check_image(20, 100, 4).is_err());
+        // The allocation can't overflow, no matter what the header says
+        let limits = Limits::unlimited();
+        let most = u64::from(u32::MAX);
+        assert!(limits.check_image(most, most, 16).is_ok());
+        let limits = Limits {
+            max_alloc: 1 << 20,
+            ..Limits::unlimited()
+        };
+        assert!(limits.check_image(1 << 16, 1 << 16, 4).is_err());
+    }
+}
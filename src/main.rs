@@ -1,15 +1,31 @@
-use std::io;
+use std::process;
 
 extern crate structopt;
 use structopt::StructOpt;
+#[cfg(feature = "viewer")]
 extern crate sdl2;
 
-mod bmp;
+mod batch;
 mod cli;
+#[cfg(feature = "viewer")]
 mod display;
-mod image;
 
-fn main() -> io::Result<()> {
+/// The exit code when some of the files in a batch couldn't be converted
+///
+/// This comes after the codes `MageError::exit_code` uses.
+const BATCH_FAILURES: i32 = 7;
+
+fn main() {
     let opt = cli::Opt::from_args();
-    opt.dispatch()
+    match opt.dispatch() {
+        Ok(cli::Status::Success) => {}
+        Ok(cli::Status::BatchFailures { failed, total }) => {
+            eprintln!("mage: {} of {} files couldn't be converted", failed, total);
+            process::exit(BATCH_FAILURES);
+        }
+        Err(e) => {
+            eprintln!("mage: {}", e);
+            process::exit(e.exit_code());
+        }
+    }
 }
@@ -0,0 +1,426 @@
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, ImageView, RGBA_BYTES, RGBA};
+use crate::info::Info;
+use crate::limits::{LimitError, Limits};
+use std::io;
+// The structures and parsing in this module are based off of ZSoft's
+// "Technical Reference Manual" for the PCX format.
+
+fn u16_le(data: &[u8]) -> u16 {
+    (data[0] as u16) | ((data[1] as u16) << 8)
+}
+
+fn write_u16_le<W: io::Write>(writer: &mut W, num: u16) -> io::Result<()> {
+    writer.write_all(&[num as u8, (num >> 8) as u8])
+}
+
+/// Represents the errors we can encounter when reading a PCX file
+#[derive(Debug)]
+pub enum PCXError {
+    /// The format of the file doesn't match the specification
+    ///
+    /// This also holds the offset of the offending bytes in the file.
+    InvalidFormat(String, usize),
+    /// The format of the file is valid, but we don't support it
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type PCXResult<T> = Result<T, PCXError>;
+
+impl From<PCXError> for MageError {
+    fn from(error: PCXError) -> Self {
+        match error {
+            PCXError::InvalidFormat(message, offset) => MageError::InvalidFormat {
+                format: "pcx",
+                message,
+                offset: Some(offset),
+            },
+            PCXError::UnsupportedFormat(message) => MageError::unsupported("pcx", message),
+            PCXError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for PCXError {
+    fn from(error: LimitError) -> Self {
+        PCXError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(offset: usize, s: S) -> PCXResult<T> {
+    Err(PCXError::InvalidFormat(s.into(), offset))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> PCXResult<T> {
+    Err(PCXError::UnsupportedFormat(s.into()))
+}
+
+/// The size of the header at the start of each file
+const HEADER_SIZE: usize = 128;
+
+/// The marker before the 256 color palette at the end of the file
+const VGA_PALETTE_MARKER: u8 = 0x0C;
+
+/// This contains the data in the header for PCX
+#[derive(Debug)]
+struct Header {
+    /// Whether or not scanlines are run length encoded
+    is_rle: bool,
+    /// How many bits are in each pixel of a single plane
+    bits_per_pixel: u8,
+    width: u32,
+    height: u32,
+    /// The 16 color palette, used by images with 4 bits or less
+    ega_palette: [u8; 48],
+    /// How many color planes there are
+    planes: u8,
+    /// How many bytes are in a scanline of a single plane
+    bytes_per_line: usize,
+}
+
+fn parse_header(data: &[u8]) -> PCXResult<Header> {
+    if data.len() < HEADER_SIZE {
+        return invalid_format(data.len(), "insufficient header length");
+    }
+    if data[0] != 0x0A {
+        return invalid_format(0, "header didn't start with 0x0A");
+    }
+    let is_rle = match data[2] {
+        0 => false,
+        1 => true,
+        _ => return invalid_format(2, "unknown encoding"),
+    };
+    let bits_per_pixel = data[3];
+    let x_min = u16_le(&data[4..]);
+    let y_min = u16_le(&data[6..]);
+    let x_max = u16_le(&data[8..]);
+    let y_max = u16_le(&data[10..]);
+    if x_max < x_min || y_max < y_min {
+        return invalid_format(4, "negative image dimensions");
+    }
+    let mut ega_palette = [0; 48];
+    ega_palette.copy_from_slice(&data[16..64]);
+    let planes = data[65];
+    let bytes_per_line = u16_le(&data[66..]) as usize;
+    let header = Header {
+        is_rle,
+        bits_per_pixel,
+        width: u32::from(x_max - x_min) + 1,
+        height: u32::from(y_max - y_min) + 1,
+        ega_palette,
+        planes,
+        bytes_per_line,
+    };
+    if (header.width as usize * header.bits_per_pixel as usize).div_ceil(8) > bytes_per_line {
+        return invalid_format(66, "scanlines are too short for the image width");
+    }
+    Ok(header)
+}
+
+/// Decode the pixel data into raw scanlines, with each plane after the other
+///
+/// This assumes the pixel data starts right after the header.
+fn decode_scanlines(header: &Header, data: &[u8]) -> PCXResult<Vec<u8>> {
+    let end = HEADER_SIZE + data.len();
+    let total = header.bytes_per_line * header.planes as usize * header.height as usize;
+    if !header.is_rle {
+        if data.len() < total {
+            return invalid_format(end, "insufficient pixel data");
+        }
+        return Ok(data[..total].to_vec());
+    }
+    let mut out = Vec::with_capacity(total);
+    let mut i = 0;
+    while out.len() < total {
+        if i >= data.len() {
+            return invalid_format(end, "insufficient pixel data");
+        }
+        let byte = data[i];
+        i += 1;
+        if byte & 0xC0 == 0xC0 {
+            if i >= data.len() {
+                return invalid_format(end, "insufficient pixel data");
+            }
+            let count = (byte & 0x3F) as usize;
+            out.extend(std::iter::repeat_n(data[i], count));
+            i += 1;
+        } else {
+            out.push(byte);
+        }
+    }
+    // Runs can spill over past the last scanline
+    out.truncate(total);
+    Ok(out)
+}
+
+/// Read the bits of a single pixel out of a packed scanline
+fn packed_bits(line: &[u8], x: usize, bits: usize) -> u8 {
+    let bit = x * bits;
+    let shift = 8 - bits - bit % 8;
+    (line[bit / 8] >> shift) & ((1 << bits) - 1)
+}
+
+/// Find the 256 color palette, which images with 8 bit indices keep at the end
+fn vga_palette(data: &[u8]) -> Option<&[u8]> {
+    if data.len() >= HEADER_SIZE + 769 && data[data.len() - 769] == VGA_PALETTE_MARKER {
+        Some(&data[data.len() - 768..])
+    } else {
+        None
+    }
+}
+
+/// Describe a file, with every field in its header
+pub fn info(data: &[u8]) -> PCXResult<Info> {
+    let header = parse_header(data)?;
+    let mut info = Info::new(header.width, header.height);
+    info.bit_depth = Some(u32::from(header.bits_per_pixel) * u32::from(header.planes));
+    let (color_type, palette_size) = match (header.bits_per_pixel, header.planes) {
+        (1, 1) => ("grayscale", 0),
+        (8, 1) if vga_palette(data).is_some() => ("indexed", 256),
+        (8, 1) => ("grayscale", 0),
+        (8, 3) => ("rgb", 0),
+        (8, 4) => ("rgba", 0),
+        (bits, planes) => ("indexed", 1 << (u32::from(bits) * u32::from(planes)).min(4)),
+    };
+    info.color_type = Some(color_type.into());
+    info.palette_size = Some(palette_size);
+    info.compression = Some(if header.is_rle { "rle" } else { "none" }.into());
+    info.dpi = Some((
+        f64::from(u16_le(&data[12..])),
+        f64::from(u16_le(&data[14..])),
+    ));
+    info.metadata = Some(false);
+    info.field("Header.manufacturer", data[0]);
+    info.field("Header.version", data[1]);
+    info.field("Header.encoding", data[2]);
+    info.field("Header.bits_per_pixel", data[3]);
+    let names = ["x_min", "y_min", "x_max", "y_max", "h_dpi", "v_dpi"];
+    for (i, name) in names.iter().enumerate() {
+        info.field(&format!("Header.{}", name), u16_le(&data[4 + 2 * i..]));
+    }
+    info.field("Header.planes", data[65]);
+    info.field("Header.bytes_per_line", u16_le(&data[66..]));
+    info.field("Header.palette_info", u16_le(&data[68..]));
+    Ok(info)
+}
+
+pub fn parse_image(data: &[u8]) -> PCXResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> PCXResult<Image> {
+    let header = parse_header(data)?;
+    let (width, height) = (u64::from(header.width), u64::from(header.height));
+    limits.check_image(width, height, RGBA_BYTES as u64)?;
+    let bits = header.bits_per_pixel as usize;
+    let planes = header.planes as usize;
+    let line_size = header.bytes_per_line * planes;
+    limits.check_alloc(line_size as u64 * height)?;
+    let raw = decode_scanlines(&header, &data[HEADER_SIZE..])?;
+    let vga_palette = vga_palette(data);
+    let palette_color = |palette: &[u8], index: usize| {
+        let i = 3 * index;
+        RGBA::new(palette[i], palette[i + 1], palette[i + 2], 0xFF)
+    };
+    let mut image = Image::new(header.width, header.height);
+    for y in 0..header.height as usize {
+        let line = &raw[y * line_size..(y + 1) * line_size];
+        let plane = |p: usize| &line[p * header.bytes_per_line..(p + 1) * header.bytes_per_line];
+        for x in 0..header.width as usize {
+            let color = match (bits, planes) {
+                (1, 1) => {
+                    let v = if packed_bits(line, x, 1) == 1 {
+                        0xFF
+                    } else {
+                        0
+                    };
+                    RGBA::new(v, v, v, 0xFF)
+                }
+                (1, 2..=4) | (2, 1) | (4, 1) => {
+                    // Planar images spread the bits of each index across planes
+                    let mut index = 0;
+                    for p in 0..planes {
+                        index |= (packed_bits(plane(p), x, bits) as usize) << (p * bits);
+                    }
+                    palette_color(&header.ega_palette, index)
+                }
+                (8, 1) => {
+                    let index = line[x] as usize;
+                    match vga_palette {
+                        Some(palette) => palette_color(palette, index),
+                        None => RGBA::new(line[x], line[x], line[x], 0xFF),
+                    }
+                }
+                (8, 3) => RGBA::new(plane(0)[x], plane(1)[x], plane(2)[x], 0xFF),
+                (8, 4) => RGBA::new(plane(0)[x], plane(1)[x], plane(2)[x], plane(3)[x]),
+                _ => return unsupported_format("unsupported pixel format"),
+            };
+            image.write(x as u32, y as u32, color);
+        }
+    }
+    Ok(image)
+}
+
+/// Run length encode a single plane of a scanline
+fn write_plane<W: io::Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut i = 0;
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < 63 && data[i + run] == data[i] {
+            run += 1;
+        }
+        // Single bytes can be written as is, unless they look like a count
+        if run == 1 && data[i] & 0xC0 != 0xC0 {
+            writer.write_all(&[data[i]])?;
+        } else {
+            writer.write_all(&[0xC0 | run as u8, data[i]])?;
+        }
+        i += run;
+    }
+    Ok(())
+}
+
+/// Check if the header of a PCX file can describe an image of a given size
+///
+/// The header holds the last column and row, and the even number of bytes
+/// in each scanline, all in 16 bits. Empty images can't be described at all.
+pub fn fits(width: u32, height: u32) -> bool {
+    (1..=0xFFFE).contains(&width) && (1..=0x1_0000).contains(&height)
+}
+
+const TOO_LARGE: &str = "PCX files hold between 1x1 and 65534x65536 pixels";
+
+/// Write an image as a 24 bit PCX file
+///
+/// PCX has no real notion of transparency, so the alpha channel is dropped.
+/// Images that don't fit in the header are refused, with `InvalidInput`.
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a>>>(
+    writer: &mut W,
+    image: I,
+) -> io::Result<()> {
+    let image = image.into();
+    if !fits(image.width, image.height) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, TOO_LARGE));
+    }
+    // Scanlines need an even number of bytes
+    let bytes_per_line = (image.width as usize).div_ceil(2) * 2;
+    writer.write_all(&[0x0A, 5, 1, 8])?;
+    write_u16_le(writer, 0)?;
+    write_u16_le(writer, 0)?;
+    write_u16_le(writer, (image.width - 1) as u16)?;
+    write_u16_le(writer, (image.height - 1) as u16)?;
+    write_u16_le(writer, 72)?;
+    write_u16_le(writer, 72)?;
+    writer.write_all(&[0; 48])?;
+    writer.write_all(&[0, 3])?;
+    write_u16_le(writer, bytes_per_line as u16)?;
+    // The palette is interpreted as color
+    write_u16_le(writer, 1)?;
+    writer.write_all(&[0; 58])?;
+    let mut planes = vec![vec![0; bytes_per_line]; 3];
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let pixel = image.read(x, y);
+            planes[0][x as usize] = pixel.r;
+            planes[1][x as usize] = pixel.g;
+            planes[2][x as usize] = pixel.b;
+        }
+        for plane in &planes {
+            write_plane(writer, plane)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads and writes run length encoded PCX images
+pub struct PCXCodec;
+
+impl ImageDecoder for PCXCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::Low)
+            .map_err(MageError::from)
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+impl ImageEncoder for PCXCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        options: &EncodeOptions,
+    ) -> MageResult<()> {
+        let image = image.into_low(options.exposure);
+        if !fits(image.width, image.height) {
+            return Err(MageError::unsupported("pcx", TOO_LARGE));
+        }
+        write_image(&mut writer, &image)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = Image::new(5, 3);
+        for y in 0..3 {
+            for x in 0..5 {
+                image.write(x, y, RGBA::new(0xC0 + x as u8, y as u8, 0xFF, 0xFF));
+            }
+        }
+        let mut data = Vec::new();
+        write_image(&mut data, &image).unwrap();
+        let read = parse_image(&data).unwrap();
+        assert_eq!(read.width, 5);
+        assert_eq!(read.height, 3);
+        for y in 0..3 {
+            for x in 0..5 {
+                assert_eq!(read.read(x, y), image.read(x, y));
+            }
+        }
+        match parse_image(&data[..data.len() - 1]) {
+            Err(PCXError::InvalidFormat(_, offset)) => assert_eq!(offset, data.len() - 1),
+            _ => panic!("truncated file decoded"),
+        }
+        data[2] = 7;
+        match parse_image(&data) {
+            Err(PCXError::InvalidFormat(_, offset)) => assert_eq!(offset, 2),
+            _ => panic!("unknown encoding decoded"),
+        }
+    }
+
+    #[test]
+    fn test_unrepresentable_sizes() {
+        for &(width, height) in &[(0, 3), (3, 0), (0xFFFF, 1)] {
+            let mut data = Vec::new();
+            let result = write_image(&mut data, &Image::new(width, height));
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert!(data.is_empty());
+        }
+        assert!(fits(0xFFFE, 0x1_0000));
+    }
+
+    #[test]
+    fn test_info() {
+        let mut data = Vec::new();
+        write_image(&mut data, &Image::new(5, 3)).unwrap();
+        let info = info(&data).unwrap();
+        assert_eq!((info.width, info.height), (5, 3));
+        assert_eq!(info.bit_depth, Some(24));
+        assert_eq!(info.color_type.as_deref(), Some("rgb"));
+        assert_eq!(info.compression.as_deref(), Some("rle"));
+        assert_eq!(info.dpi, Some((72.0, 72.0)));
+        assert_eq!(info.fields[1], ("Header.version".into(), 5u8.into()));
+    }
+}
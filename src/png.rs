@@ -0,0 +1,878 @@
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder, Inspector};
+use crate::dissect::Region;
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, ImageView, Rgba16, RGBA};
+use crate::info::{dpi_from_meters, Info};
+use crate::limits::Limits;
+use crate::validate::Issue;
+use crate::zlib;
+use std::convert::TryFrom;
+use std::io;
+// The structures in this module are based off of the W3C's "Portable Network
+// Graphics (PNG) Specification". We read every color type, bit depth and
+// interlacing method, but only write files as 8 bit RGBA.
+
+/// The signature at the start of every PNG file
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The color type for truecolor images with alpha
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// The filter type applying no transformation to a scanline
+const FILTER_NONE: u8 = 0;
+
+/// The most text we inflate out of a single compressed text chunk
+const MAX_TEXT: usize = 1 << 16;
+
+/// The first column, first row, column step and row step of each Adam7 pass
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Calculate the CRC-32 used to check chunks
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for data in chunks {
+        for &byte in data.iter() {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+fn write_chunk<W: io::Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+/// Write an image as a PNG file
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a>>>(
+    writer: &mut W,
+    image: I,
+) -> io::Result<()> {
+    let image = image.into();
+    writer.write_all(&SIGNATURE)?;
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // 8 bits per sample, with the standard compression, filtering, and no interlacing
+    header.extend_from_slice(&[8, COLOR_TYPE_RGBA, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)?;
+    let mut raw = Vec::with_capacity((4 * image.width as usize + 1) * image.height as usize);
+    for y in 0..image.height {
+        raw.push(FILTER_NONE);
+        for x in 0..image.width {
+            let pixel = image.read(x, y);
+            raw.extend_from_slice(&[pixel.r, pixel.g, pixel.b, pixel.a]);
+        }
+    }
+    write_chunk(writer, b"IDAT", &zlib::compress(&raw))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+fn u32_be(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Split the fields of an image header chunk into regions
+fn dissect_header(data: &[u8], start: usize, regions: &mut Vec<Region>) {
+    let fields = [
+        (4, "width"),
+        (4, "height"),
+        (1, "bit depth"),
+        (1, "color type"),
+        (1, "compression method"),
+        (1, "filter method"),
+        (1, "interlace method"),
+    ];
+    let mut offset = start;
+    for &(len, name) in &fields {
+        let label = match data.get(offset..offset + len) {
+            Some(bytes) if len == 4 => format!("IHDR {} = {}", name, u32_be(bytes)),
+            Some(bytes) => format!("IHDR {} = {}", name, bytes[0]),
+            None => format!("IHDR {}", name),
+        };
+        regions.push(Region::new(offset, len, label));
+        offset += len;
+    }
+}
+
+/// Split a file into its signature, and the parts of each chunk
+pub fn dissect(data: &[u8]) -> Vec<Region> {
+    let signature = if data.starts_with(&SIGNATURE) {
+        "signature"
+    } else {
+        "signature, which doesn't match"
+    };
+    let mut regions = vec![Region::new(0, SIGNATURE.len(), signature)];
+    let mut i = SIGNATURE.len();
+    while i + 8 <= data.len() {
+        let len = u32_be(&data[i..]) as usize;
+        let kind = String::from_utf8_lossy(&data[i + 4..i + 8]).into_owned();
+        regions.push(Region::new(
+            i,
+            4,
+            format!("{} chunk length = {}", kind, len),
+        ));
+        regions.push(Region::new(i + 4, 4, format!("{} chunk type", kind)));
+        let start = i + 8;
+        if kind == "IHDR" && len == 13 {
+            dissect_header(data, start, &mut regions);
+        } else if len > 0 {
+            let label = format!("{} chunk data ({} bytes)", kind, len);
+            regions.push(Region::new(start, len, label));
+        }
+        let crc_at = start + len;
+        let label = match data.get(crc_at..crc_at + 4) {
+            Some(crc) => format!("{} chunk CRC = 0x{:08X}", kind, u32_be(crc)),
+            None => format!("{} chunk CRC", kind),
+        };
+        regions.push(Region::new(crc_at, 4, label));
+        i = crc_at + 4;
+    }
+    regions
+}
+
+/// Check if a combination of bit depth and color type is allowed
+fn valid_depth(color_type: u8, bit_depth: u8) -> bool {
+    match color_type {
+        0 => [1, 2, 4, 8, 16].contains(&bit_depth),
+        3 => [1, 2, 4, 8].contains(&bit_depth),
+        2 | 4 | 6 => bit_depth == 8 || bit_depth == 16,
+        _ => false,
+    }
+}
+
+/// Check the fields of an image header chunk
+fn validate_header(header: &[u8], start: usize, issues: &mut Vec<Issue>) {
+    if u32_be(header) == 0 || u32_be(&header[4..]) == 0 {
+        issues.push(Issue::error(start, "image has no pixels"));
+    }
+    let (bit_depth, color_type) = (header[8], header[9]);
+    if !valid_depth(color_type, bit_depth) {
+        let message = format!(
+            "bit depth {} isn't allowed with color type {}",
+            bit_depth, color_type
+        );
+        issues.push(Issue::error(start + 8, message));
+    }
+    let methods = ["compression", "filter"];
+    for (i, name) in methods.iter().enumerate() {
+        if header[10 + i] != 0 {
+            let message = format!("unknown {} method {}", name, header[10 + i]);
+            issues.push(Issue::error(start + 10 + i, message));
+        }
+    }
+    if header[12] > 1 {
+        let message = format!("unknown interlace method {}", header[12]);
+        issues.push(Issue::error(start + 12, message));
+    }
+}
+
+/// Check a file against the specification, reporting every deviation
+pub fn validate(data: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if !data.starts_with(&SIGNATURE) {
+        issues.push(Issue::error(0, "signature doesn't match"));
+        return issues;
+    }
+    let mut i = SIGNATURE.len();
+    let mut seen_data = false;
+    let mut end = None;
+    while i < data.len() {
+        if i + 8 > data.len() {
+            issues.push(Issue::error(i, "file ends inside a chunk header"));
+            return issues;
+        }
+        let len = u32_be(&data[i..]) as usize;
+        let kind = &data[i + 4..i + 8];
+        let name = String::from_utf8_lossy(kind);
+        if !kind.iter().all(u8::is_ascii_alphabetic) {
+            issues.push(Issue::error(
+                i + 4,
+                format!("invalid chunk type {:?}", name),
+            ));
+        }
+        if i == SIGNATURE.len() && kind != b"IHDR" {
+            issues.push(Issue::error(i + 4, "the first chunk isn't IHDR"));
+        }
+        let start = i + 8;
+        let crc_at = start.saturating_add(len);
+        if crc_at.saturating_add(4) > data.len() {
+            let message = format!("{} chunk is truncated", name);
+            issues.push(Issue::error(data.len(), message));
+            return issues;
+        }
+        let contents = &data[start..crc_at];
+        match kind {
+            b"IHDR" if len != 13 => {
+                let message = format!("IHDR chunk is {} bytes, instead of 13", len);
+                issues.push(Issue::error(i, message));
+            }
+            b"IHDR" => validate_header(contents, start, &mut issues),
+            b"IDAT" => seen_data = true,
+            _ => {}
+        }
+        let crc = u32_be(&data[crc_at..]);
+        let expected = crc32(&[kind, contents]);
+        if crc != expected {
+            let message = format!(
+                "{} chunk CRC is 0x{:08X}, but should be 0x{:08X}",
+                name, crc, expected
+            );
+            issues.push(Issue::error(crc_at, message));
+        }
+        i = crc_at + 4;
+        if kind == b"IEND" {
+            end = Some(i);
+            break;
+        }
+    }
+    if !seen_data {
+        issues.push(Issue::error(i, "there's no IDAT chunk"));
+    }
+    match end {
+        None => issues.push(Issue::error(data.len(), "there's no IEND chunk")),
+        Some(end) if end < data.len() => {
+            let message = format!("{} bytes after the IEND chunk", data.len() - end);
+            issues.push(Issue::warning(end, message));
+        }
+        Some(_) => {}
+    }
+    issues
+}
+
+/// The name of a color type, and how many samples are in each pixel
+fn color_type_info(color_type: u8) -> Option<(&'static str, u32)> {
+    match color_type {
+        0 => Some(("grayscale", 1)),
+        2 => Some(("rgb", 3)),
+        3 => Some(("indexed", 1)),
+        4 => Some(("grayscale alpha", 2)),
+        6 => Some(("rgba", 4)),
+        _ => None,
+    }
+}
+
+/// Read the keyword and text out of a tEXt, zTXt, or iTXt chunk
+///
+/// The first two are in Latin-1, and the last in UTF-8, with a language
+/// tag and a translated keyword that we skip over.
+fn read_text(kind: &[u8], contents: &[u8]) -> Option<(String, String)> {
+    let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| b as char).collect::<String>();
+    let end = contents.iter().position(|&b| b == 0)?;
+    let keyword = latin1(&contents[..end]);
+    let rest = &contents[end + 1..];
+    let text = match kind {
+        b"tEXt" => latin1(rest),
+        // The compression method comes before the compressed text
+        b"zTXt" => latin1(&zlib::decompress(rest.get(1..)?, MAX_TEXT).ok()?),
+        b"iTXt" => {
+            let compressed = *rest.first()? == 1;
+            let mut rest = rest.get(2..)?;
+            for _ in 0..2 {
+                let end = rest.iter().position(|&b| b == 0)?;
+                rest = &rest[end + 1..];
+            }
+            if compressed {
+                String::from_utf8_lossy(&zlib::decompress(rest, MAX_TEXT).ok()?).into_owned()
+            } else {
+                String::from_utf8_lossy(rest).into_owned()
+            }
+        }
+        _ => return None,
+    };
+    Some((keyword, text))
+}
+
+fn invalid(offset: usize, message: &str) -> MageError {
+    MageError::InvalidFormat {
+        format: "png",
+        message: message.into(),
+        offset: Some(offset),
+    }
+}
+
+/// Describe a file, with the fields of its header and the text in its chunks
+///
+/// Unlike validating, this stops quietly at the first truncated chunk.
+pub fn info(data: &[u8]) -> MageResult<Info> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err(invalid(0, "signature doesn't match"));
+    }
+    let header = match data.get(SIGNATURE.len()..SIGNATURE.len() + 21) {
+        Some(chunk) if chunk[..8] == *b"\0\0\0\x0DIHDR" => &chunk[8..],
+        _ => return Err(invalid(SIGNATURE.len(), "the first chunk isn't IHDR")),
+    };
+    let mut info = Info::new(u32_be(header), u32_be(&header[4..]));
+    let (bit_depth, color_type, compression) = (header[8], header[9], header[10]);
+    if let Some((name, samples)) = color_type_info(color_type) {
+        info.bit_depth = Some(u32::from(bit_depth) * samples);
+        info.color_type = Some(name.into());
+    }
+    info.compression = Some(match compression {
+        0 => "deflate".into(),
+        method => format!("unknown ({})", method),
+    });
+    info.palette_size = Some(0);
+    info.metadata = Some(false);
+    info.field("IHDR.width", u32_be(header));
+    info.field("IHDR.height", u32_be(&header[4..]));
+    info.field("IHDR.bit_depth", bit_depth);
+    info.field("IHDR.color_type", color_type);
+    info.field("IHDR.compression_method", compression);
+    info.field("IHDR.filter_method", header[11]);
+    info.field("IHDR.interlace_method", header[12]);
+    let mut i = SIGNATURE.len();
+    while i + 8 <= data.len() {
+        let len = u32_be(&data[i..]) as usize;
+        let kind = &data[i + 4..i + 8];
+        let start = i + 8;
+        let contents = match data.get(start..start.saturating_add(len)) {
+            Some(contents) => contents,
+            None => break,
+        };
+        match kind {
+            b"PLTE" => info.palette_size = Some((len / 3) as u32),
+            b"pHYs" if len == 9 => {
+                let (x, y, unit) = (u32_be(contents), u32_be(&contents[4..]), contents[8]);
+                info.field("pHYs.x_pixels_per_unit", x);
+                info.field("pHYs.y_pixels_per_unit", y);
+                info.field("pHYs.unit", unit);
+                // Without a unit, these only give the aspect ratio of the pixels
+                if unit == 1 {
+                    info.dpi = Some((dpi_from_meters(x), dpi_from_meters(y)));
+                }
+            }
+            b"tEXt" | b"zTXt" | b"iTXt" => {
+                info.metadata = Some(true);
+                if let Some((keyword, text)) = read_text(kind, contents) {
+                    let name = format!("{}.{}", String::from_utf8_lossy(kind), keyword);
+                    info.field(&name, text);
+                }
+            }
+            b"iCCP" | b"eXIf" => info.metadata = Some(true),
+            b"IEND" => break,
+            _ => {}
+        }
+        i = start + len + 4;
+    }
+    Ok(info)
+}
+
+/// The fields of the image header that decoding needs
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    /// How many bits each pixel takes up in a scanline
+    fn pixel_bits(&self) -> usize {
+        // The color type was checked when reading the header
+        let (_, samples) = color_type_info(self.color_type).unwrap();
+        samples as usize * usize::from(self.bit_depth)
+    }
+
+    /// The width and height of each pass over the image
+    fn passes(&self) -> Vec<(u32, u32, u32, u32, u32, u32)> {
+        let single = [(0, 0, 1, 1)];
+        let passes: &[(u32, u32, u32, u32)] = if self.interlaced { &ADAM7 } else { &single };
+        passes
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let width = self.width.saturating_sub(x0).div_ceil(dx);
+                let height = self.height.saturating_sub(y0).div_ceil(dy);
+                (x0, y0, dx, dy, width, height)
+            })
+            .collect()
+    }
+
+    /// The number of bytes in a scanline, without its filter type
+    fn stride(&self, width: u32) -> Option<usize> {
+        let bits = (width as usize).checked_mul(self.pixel_bits())?;
+        Some(bits.div_ceil(8))
+    }
+}
+
+fn read_header(data: &[u8]) -> MageResult<Header> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err(invalid(0, "signature doesn't match"));
+    }
+    let header = match data.get(SIGNATURE.len()..SIGNATURE.len() + 21) {
+        Some(chunk) if chunk[..8] == *b"\0\0\0\x0DIHDR" => &chunk[8..],
+        _ => return Err(invalid(SIGNATURE.len(), "the first chunk isn't IHDR")),
+    };
+    let start = SIGNATURE.len() + 8;
+    let (width, height) = (u32_be(header), u32_be(&header[4..]));
+    if width == 0 || height == 0 {
+        return Err(invalid(start, "image has no pixels"));
+    }
+    let (bit_depth, color_type) = (header[8], header[9]);
+    if !valid_depth(color_type, bit_depth) {
+        return Err(invalid(start + 8, "invalid bit depth for the color type"));
+    }
+    if header[10] != 0 {
+        return Err(invalid(start + 10, "unknown compression method"));
+    }
+    if header[11] != 0 {
+        return Err(invalid(start + 11, "unknown filter method"));
+    }
+    if header[12] > 1 {
+        return Err(invalid(start + 12, "unknown interlace method"));
+    }
+    Ok(Header {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        interlaced: header[12] == 1,
+    })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Undo the filter on a scanline, given the unfiltered line above it
+///
+/// `bpp` is the number of bytes in a pixel, rounded up to at least 1.
+fn unfilter(filter: u8, line: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), &'static str> {
+    for i in 0..line.len() {
+        let left = if i >= bpp { line[i - bpp] } else { 0 };
+        let up = previous.get(i).copied().unwrap_or(0);
+        let up_left = if i >= bpp {
+            previous.get(i - bpp).copied().unwrap_or(0)
+        } else {
+            0
+        };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err("unknown filter type"),
+        };
+        line[i] = line[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+/// Read the sample at an index in a scanline, with samples of `depth` bits
+fn sample(line: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([line[2 * index], line[2 * index + 1]]),
+        8 => u16::from(line[index]),
+        _ => {
+            let per_byte = 8 / usize::from(depth);
+            let shift = 8 - usize::from(depth) * (index % per_byte + 1);
+            u16::from(line[index / per_byte] >> shift) & ((1 << depth) - 1)
+        }
+    }
+}
+
+/// The chunks that decoding needs, apart from the header
+struct Chunks {
+    palette: Vec<[u8; 3]>,
+    transparency: Vec<u8>,
+    data: Vec<u8>,
+    data_offset: usize,
+}
+
+fn read_chunks(data: &[u8]) -> MageResult<Chunks> {
+    let mut chunks = Chunks {
+        palette: Vec::new(),
+        transparency: Vec::new(),
+        data: Vec::new(),
+        data_offset: data.len(),
+    };
+    let mut i = SIGNATURE.len();
+    loop {
+        if i + 8 > data.len() {
+            return Err(invalid(i, "there's no IEND chunk"));
+        }
+        let len = u32_be(&data[i..]) as usize;
+        let kind = &data[i + 4..i + 8];
+        let start = i + 8;
+        let contents = match data.get(start..start.saturating_add(len)) {
+            Some(contents) => contents,
+            None => return Err(invalid(i, "chunk is truncated")),
+        };
+        match kind {
+            b"PLTE" => {
+                if !len.is_multiple_of(3) || len > 256 * 3 {
+                    return Err(invalid(i, "invalid palette size"));
+                }
+                let colors = contents.chunks_exact(3).map(|c| [c[0], c[1], c[2]]);
+                chunks.palette = colors.collect();
+            }
+            b"tRNS" => chunks.transparency = contents.to_vec(),
+            b"IDAT" => {
+                chunks.data_offset = chunks.data_offset.min(start);
+                chunks.data.extend_from_slice(contents);
+            }
+            b"IEND" => return Ok(chunks),
+            _ => {}
+        }
+        i = start + len + 4;
+    }
+}
+
+/// Parse an image, with any color type, bit depth, or interlacing
+///
+/// 16 bit images keep their precision, and everything else becomes 8 bit RGBA.
+pub fn parse_native(data: &[u8], limits: &Limits) -> MageResult<Decoded> {
+    let header = read_header(data)?;
+    let deep = header.bit_depth == 16;
+    let bytes_per_pixel = if deep { 8 } else { 4 };
+    limits.check_image(
+        u64::from(header.width),
+        u64::from(header.height),
+        bytes_per_pixel,
+    )?;
+    let chunks = read_chunks(data)?;
+    if header.color_type == 3 && chunks.palette.is_empty() {
+        return Err(invalid(SIGNATURE.len(), "indexed image without a palette"));
+    }
+    if chunks.data.is_empty() {
+        return Err(invalid(data.len(), "there's no IDAT chunk"));
+    }
+    let too_large = || invalid(SIGNATURE.len() + 8, "image is too large");
+    let passes = header.passes();
+    let mut raw_len = 0usize;
+    for &(_, _, _, _, width, height) in &passes {
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let stride = header.stride(width).ok_or_else(too_large)?;
+        raw_len = (stride + 1)
+            .checked_mul(height as usize)
+            .and_then(|n| n.checked_add(raw_len))
+            .ok_or_else(too_large)?;
+    }
+    limits.check_alloc(u64::try_from(raw_len).unwrap_or(u64::MAX))?;
+    let mut raw = match zlib::decompress(&chunks.data, raw_len) {
+        Ok(raw) => raw,
+        Err(message) => return Err(invalid(chunks.data_offset, message)),
+    };
+    if raw.len() < raw_len {
+        return Err(invalid(data.len(), "image data is truncated"));
+    }
+    let depth = header.bit_depth;
+    // Palettes are always 8 bit, whatever the depth of the indices
+    let max = if header.color_type == 3 {
+        255
+    } else {
+        (1u32 << depth) - 1
+    };
+    let transparent = |samples: &[u16]| {
+        let key = chunks
+            .transparency
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]));
+        samples.len() == key.len() && key.zip(samples).all(|(k, &s)| k == s)
+    };
+    let scale = |v: u32| (v * 255 / max) as u8;
+    let (width, height) = (header.width, header.height);
+    let mut image = if deep {
+        Decoded::Rgba16(Image::new(width, height))
+    } else {
+        Decoded::Low(Image::new(width, height))
+    };
+    let bpp = (header.pixel_bits() / 8).max(1);
+    let mut start = 0;
+    for &(x0, y0, dx, dy, width, height) in &passes {
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let stride = header.stride(width).unwrap();
+        let mut previous = Vec::new();
+        for row in 0..height {
+            let (filter, line) = raw[start..start + stride + 1].split_first_mut().unwrap();
+            if let Err(message) = unfilter(*filter, line, &previous, bpp) {
+                return Err(invalid(chunks.data_offset, message));
+            }
+            let y = y0 + row * dy;
+            let samples = color_type_info(header.color_type).unwrap().1 as usize;
+            let mut values = [0u16; 4];
+            for column in 0..width as usize {
+                for (s, value) in values.iter_mut().enumerate().take(samples) {
+                    *value = sample(line, column * samples + s, depth);
+                }
+                let pixel = match header.color_type {
+                    0 => {
+                        let gray = u32::from(values[0]);
+                        let alpha = if transparent(&values[..1]) { 0 } else { max };
+                        [gray, gray, gray, alpha]
+                    }
+                    2 => {
+                        let alpha = if transparent(&values[..3]) { 0 } else { max };
+                        let [r, g, b, _] = values;
+                        [u32::from(r), u32::from(g), u32::from(b), alpha]
+                    }
+                    3 => {
+                        let index = usize::from(values[0]);
+                        let [r, g, b] = match chunks.palette.get(index) {
+                            Some(&color) => color,
+                            None => {
+                                return Err(invalid(
+                                    chunks.data_offset,
+                                    "palette index out of range",
+                                ))
+                            }
+                        };
+                        let alpha = chunks.transparency.get(index).copied().unwrap_or(255);
+                        [u32::from(r), u32::from(g), u32::from(b), u32::from(alpha)]
+                    }
+                    4 => {
+                        let [gray, alpha, _, _] = values;
+                        let gray = u32::from(gray);
+                        [gray, gray, gray, u32::from(alpha)]
+                    }
+                    _ => values.map(u32::from),
+                };
+                let x = x0 + column as u32 * dx;
+                let [r, g, b, a] = pixel;
+                match &mut image {
+                    Decoded::Rgba16(image) => {
+                        image.write(x, y, Rgba16::new(r as u16, g as u16, b as u16, a as u16))
+                    }
+                    Decoded::Low(image) => {
+                        image.write(x, y, RGBA::new(scale(r), scale(g), scale(b), scale(a)))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            previous = line.to_vec();
+            start += stride + 1;
+        }
+    }
+    Ok(image)
+}
+
+pub fn parse_image(data: &[u8]) -> MageResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> MageResult<Image> {
+    Ok(parse_native(data, limits)?.into_low(None))
+}
+
+/// Reads and writes PNG files, and dissects and validates existing ones
+pub struct PNGCodec;
+
+impl ImageDecoder for PNGCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_native(data, &options.limits)
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data)
+    }
+}
+
+impl ImageEncoder for PNGCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        options: &EncodeOptions,
+    ) -> MageResult<()> {
+        write_image(&mut writer, &image.into_low(options.exposure))?;
+        Ok(())
+    }
+}
+
+impl Inspector for PNGCodec {
+    fn dissect(&self, data: &[u8]) -> Vec<Region> {
+        dissect(data)
+    }
+
+    fn validate(&self, data: &[u8]) -> Vec<Issue> {
+        validate(data)
+    }
+
+    fn info(&self, data: &[u8]) -> MageResult<Info> {
+        info(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A file with a header, some extra chunks, and compressed scanlines
+    fn file(
+        size: (u32, u32),
+        depth: u8,
+        color: u8,
+        interlace: u8,
+        extra: &[(&[u8; 4], &[u8])],
+        raw: &[u8],
+    ) -> Vec<u8> {
+        let mut data = SIGNATURE.to_vec();
+        let mut header = Vec::new();
+        header.extend_from_slice(&size.0.to_be_bytes());
+        header.extend_from_slice(&size.1.to_be_bytes());
+        header.extend_from_slice(&[depth, color, 0, 0, interlace]);
+        write_chunk(&mut data, b"IHDR", &header).unwrap();
+        for (kind, contents) in extra {
+            write_chunk(&mut data, kind, contents).unwrap();
+        }
+        write_chunk(&mut data, b"IDAT", &zlib::compress(raw)).unwrap();
+        write_chunk(&mut data, b"IEND", &[]).unwrap();
+        data
+    }
+
+    fn gray(image: &Image) -> Vec<u8> {
+        image.as_raw().chunks(4).map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(&[b"IEND"]), 0xAE42_6082);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let image = Image::from_fn(3, 2, |x, y| RGBA::new(x as u8, y as u8, 7, 200));
+        let mut data = Vec::new();
+        write_image(&mut data, &image).unwrap();
+        assert_eq!(parse_image(&data).unwrap().as_raw(), image.as_raw());
+    }
+
+    #[test]
+    fn test_filters() {
+        // Sub, Up, Average and Paeth, on 8 bit grayscale
+        let raw = [1, 10, 5, 5, 2, 1, 1, 1, 3, 0, 0, 0, 4, 1, 1, 1];
+        let image = parse_image(&file((3, 4), 8, 0, 0, &[], &raw)).unwrap();
+        assert_eq!(
+            gray(&image),
+            vec![10, 15, 20, 11, 16, 21, 5, 10, 15, 6, 11, 16]
+        );
+        let bad = [5, 0, 0, 0];
+        assert!(parse_image(&file((3, 1), 8, 0, 0, &[], &bad)).is_err());
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette = [0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF];
+        let extra: [(&[u8; 4], &[u8]); 2] = [(b"PLTE", &palette), (b"tRNS", &[0x80])];
+        // Three 2 bit indices, padded out to a byte
+        let image = parse_image(&file((3, 1), 2, 3, 0, &extra, &[0, 0b0001_1000])).unwrap();
+        assert_eq!(image.read(0, 0), RGBA::new(0xFF, 0, 0, 0x80));
+        assert_eq!(image.read(1, 0), RGBA::new(0, 0xFF, 0, 0xFF));
+        assert_eq!(image.read(2, 0), RGBA::new(0, 0, 0xFF, 0xFF));
+        let out_of_range = file((3, 1), 2, 3, 0, &extra, &[0, 0b0011_0000]);
+        assert!(parse_image(&out_of_range).is_err());
+    }
+
+    #[test]
+    fn test_sixteen_bits() {
+        let extra: [(&[u8; 4], &[u8]); 1] = [(b"tRNS", &[0x12, 0x34])];
+        let data = file((2, 1), 16, 0, 0, &extra, &[0, 0x12, 0x34, 0xFF, 0xFF]);
+        match parse_native(&data, &Limits::default()).unwrap() {
+            Decoded::Rgba16(image) => {
+                assert_eq!(image.read(0, 0), Rgba16::new(0x1234, 0x1234, 0x1234, 0));
+                assert_eq!(
+                    image.read(1, 0),
+                    Rgba16::new(0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF)
+                );
+            }
+            _ => panic!("16 bit images should keep their precision"),
+        }
+    }
+
+    #[test]
+    fn test_interlaced() {
+        // The passes of a 3x3 image, where each pixel is 10 * y + x
+        let raw = [0, 0, 0, 2, 0, 20, 22, 0, 1, 0, 21, 0, 10, 11, 12];
+        let image = parse_image(&file((3, 3), 8, 0, 1, &[], &raw)).unwrap();
+        assert_eq!(gray(&image), vec![0, 1, 2, 10, 11, 12, 20, 21, 22]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut data = Vec::new();
+        write_image(&mut data, &Image::new(2, 2)).unwrap();
+        match parse_image(&data[..data.len() - 20]) {
+            Err(MageError::InvalidFormat { offset, .. }) => assert_eq!(offset, Some(33)),
+            _ => panic!("truncated files should be invalid"),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut data = Vec::new();
+        write_image(&mut data, &Image::new(2, 2)).unwrap();
+        assert!(validate(&data).is_empty());
+        // Break the CRC of the header, and add something after the end
+        data[29] ^= 1;
+        data.extend_from_slice(b"junk");
+        let offsets: Vec<usize> = validate(&data).iter().map(|i| i.offset).collect();
+        assert_eq!(offsets, vec![29, data.len() - 4]);
+    }
+
+    #[test]
+    fn test_info() {
+        let mut data = Vec::new();
+        write_image(&mut data, &Image::new(3, 2)).unwrap();
+        // Put some more chunks in before IEND
+        data.truncate(data.len() - 12);
+        let mut phys = Vec::new();
+        phys.extend_from_slice(&2835u32.to_be_bytes());
+        phys.extend_from_slice(&2835u32.to_be_bytes());
+        phys.push(1);
+        write_chunk(&mut data, b"pHYs", &phys).unwrap();
+        write_chunk(&mut data, b"tEXt", b"Comment\0caf\xE9").unwrap();
+        let mut text = b"Title\0\x01\0en\0\0".to_vec();
+        text.extend_from_slice(&zlib::compress("café".as_bytes()));
+        write_chunk(&mut data, b"iTXt", &text).unwrap();
+        write_chunk(&mut data, b"IEND", &[]).unwrap();
+        let info = info(&data).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.bit_depth, Some(32));
+        assert_eq!(info.color_type.as_deref(), Some("rgba"));
+        assert_eq!(info.dpi, Some((72.0, 72.0)));
+        assert_eq!(info.palette_size, Some(0));
+        assert_eq!(info.metadata, Some(true));
+        let field = |name: &str| {
+            info.fields
+                .iter()
+                .find(|f| f.0 == name)
+                .map(|f| f.1.clone())
+        };
+        assert_eq!(field("tEXt.Comment"), Some("café".into()));
+        assert_eq!(field("iTXt.Title"), Some("café".into()));
+        assert!(super::info(&data[..20]).is_err());
+    }
+}
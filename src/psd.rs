@@ -0,0 +1,903 @@
+use crate::codec::{DecodeOptions, Decoded, ImageDecoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, Luma8, LumaA8, RGBA_BYTES, RGBA};
+use crate::info::Info;
+use crate::limits::{LimitError, Limits};
+use crate::zlib;
+// The structures and parsing in this module are based off of Adobe's
+// "Photoshop File Formats Specification".
+
+fn u16_be(data: &[u8]) -> u16 {
+    ((data[0] as u16) << 8) | (data[1] as u16)
+}
+
+fn u32_be(data: &[u8]) -> u32 {
+    ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | (data[3] as u32)
+}
+
+fn i32_be(data: &[u8]) -> i32 {
+    u32_be(data) as i32
+}
+
+/// Represents the errors we can encounter when reading a PSD file
+#[derive(Debug)]
+pub enum PSDError {
+    /// The format of the file doesn't match the specification
+    ///
+    /// This also holds the offset of the offending bytes in the file.
+    InvalidFormat(String, usize),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This is the case for large PSB documents, and color modes
+    /// other than RGB and grayscale.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type PSDResult<T> = Result<T, PSDError>;
+
+impl From<PSDError> for MageError {
+    fn from(error: PSDError) -> Self {
+        match error {
+            PSDError::InvalidFormat(message, offset) => MageError::InvalidFormat {
+                format: "psd",
+                message,
+                offset: Some(offset),
+            },
+            PSDError::UnsupportedFormat(message) => MageError::unsupported("psd", message),
+            PSDError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for PSDError {
+    fn from(error: LimitError) -> Self {
+        PSDError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(offset: usize, s: S) -> PSDResult<T> {
+    Err(PSDError::InvalidFormat(s.into(), offset))
+}
+
+/// Move the offset of an error in part of a file, starting at `start`
+///
+/// The functions parsing the parts of a file only know about offsets inside
+/// of the slice they're given, so their callers use this to fix them up.
+fn offset_by<T>(start: usize, result: PSDResult<T>) -> PSDResult<T> {
+    result.map_err(|error| match error {
+        PSDError::InvalidFormat(message, offset) => {
+            PSDError::InvalidFormat(message, start + offset)
+        }
+        error => error,
+    })
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> PSDResult<T> {
+    Err(PSDError::UnsupportedFormat(s.into()))
+}
+
+/// The size of the header at the start of each file
+const HEADER_SIZE: usize = 26;
+
+/// The channel id used for transparency in layers
+const ALPHA_CHANNEL: i16 = -1;
+
+/// The color modes we know how to decode
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColorMode {
+    Grayscale,
+    Rgb,
+}
+
+impl ColorMode {
+    /// How many channels make up a color, not counting transparency
+    fn color_channels(self) -> usize {
+        match self {
+            ColorMode::Grayscale => 1,
+            ColorMode::Rgb => 3,
+        }
+    }
+}
+
+/// This contains the data in the header for PSD
+#[derive(Debug)]
+struct Header {
+    channels: usize,
+    width: usize,
+    height: usize,
+    /// How many bytes are in each sample, either 1 or 2
+    bytes_per_sample: usize,
+    color_mode: ColorMode,
+}
+
+fn parse_header(data: &[u8]) -> PSDResult<Header> {
+    if data.len() < HEADER_SIZE {
+        return invalid_format(data.len(), "insufficient header length");
+    }
+    if &data[0..4] != b"8BPS" {
+        return invalid_format(0, "header didn't start with '8BPS'");
+    }
+    match u16_be(&data[4..]) {
+        1 => {}
+        2 => return unsupported_format("large PSB documents are not supported"),
+        _ => return invalid_format(4, "unknown version"),
+    }
+    let bytes_per_sample = match u16_be(&data[22..]) {
+        8 => 1,
+        16 => 2,
+        _ => return unsupported_format("only 8 and 16 bit documents are supported"),
+    };
+    let color_mode = match u16_be(&data[24..]) {
+        1 => ColorMode::Grayscale,
+        3 => ColorMode::Rgb,
+        _ => return unsupported_format("only RGB and grayscale documents are supported"),
+    };
+    let channels = u16_be(&data[12..]) as usize;
+    if channels < color_mode.color_channels() {
+        return invalid_format(12, "too few channels for the color mode");
+    }
+    Ok(Header {
+        channels,
+        width: u32_be(&data[18..]) as usize,
+        height: u32_be(&data[14..]) as usize,
+        bytes_per_sample,
+        color_mode,
+    })
+}
+
+/// Read a section starting with its length, advancing past it
+fn read_section<'a>(data: &'a [u8], i: &mut usize) -> PSDResult<&'a [u8]> {
+    if data.len() < *i + 4 {
+        return invalid_format(data.len(), "insufficient section length");
+    }
+    let length = u32_be(&data[*i..]) as usize;
+    let start = *i + 4;
+    if data.len() - start < length {
+        return invalid_format(*i, "section extends past the end of the file");
+    }
+    *i = start + length;
+    Ok(&data[start..start + length])
+}
+
+/// Undo PackBits compression for a single row
+fn unpack_bits(data: &[u8], out: &mut Vec<u8>, expected: usize) -> PSDResult<()> {
+    let end = out.len() + expected;
+    let mut i = 0;
+    while out.len() < end {
+        if i >= data.len() {
+            return invalid_format(i, "insufficient row data");
+        }
+        let n = data[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let count = n as usize + 1;
+            if i + count > data.len() {
+                return invalid_format(i - 1, "literal run past the end of the row");
+            }
+            out.extend_from_slice(&data[i..i + count]);
+            i += count;
+        } else if n != -128 {
+            if i >= data.len() {
+                return invalid_format(i - 1, "repeat run past the end of the row");
+            }
+            let count = (-(n as isize)) as usize + 1;
+            out.extend(std::iter::repeat_n(data[i], count));
+            i += 1;
+        }
+    }
+    out.truncate(end);
+    Ok(())
+}
+
+/// Decode rows compressed with PackBits, preceded by a table of their sizes
+fn unpack_rows(data: &[u8], rows: usize, row_size: usize) -> PSDResult<Vec<u8>> {
+    if data.len() < 2 * rows {
+        return invalid_format(data.len(), "insufficient row size table");
+    }
+    let mut out = Vec::with_capacity(rows * row_size);
+    let mut i = 2 * rows;
+    for row in 0..rows {
+        let size = u16_be(&data[2 * row..]) as usize;
+        if data.len() - i < size {
+            return invalid_format(2 * row, "row extends past the end of the file");
+        }
+        offset_by(i, unpack_bits(&data[i..i + size], &mut out, row_size))?;
+        i += size;
+    }
+    Ok(out)
+}
+
+/// Undo the delta encoding applied to rows before zip compression
+fn unpredict(data: &mut [u8], row_size: usize, bytes_per_sample: usize) {
+    for row in data.chunks_mut(row_size.max(1)) {
+        if bytes_per_sample == 1 {
+            for i in 1..row.len() {
+                row[i] = row[i].wrapping_add(row[i - 1]);
+            }
+        } else {
+            for i in (2..row.len()).step_by(2) {
+                let value = u16_be(&row[i..]).wrapping_add(u16_be(&row[i - 2..]));
+                row[i] = (value >> 8) as u8;
+                row[i + 1] = value as u8;
+            }
+        }
+    }
+}
+
+/// Decode the data of a single layer channel, starting with its compression
+fn decode_channel(data: &[u8], width: usize, height: usize, bps: usize) -> PSDResult<Vec<u8>> {
+    if data.len() < 2 {
+        return invalid_format(data.len(), "insufficient channel data");
+    }
+    let row_size = width * bps;
+    let expected = row_size * height;
+    let compression = u16_be(data);
+    let data = &data[2..];
+    let out = match compression {
+        0 => {
+            if data.len() < expected {
+                return invalid_format(2 + data.len(), "insufficient channel data");
+            }
+            data[..expected].to_vec()
+        }
+        1 => offset_by(2, unpack_rows(data, height, row_size))?,
+        2 | 3 => {
+            let mut out = match zlib::decompress(data, expected) {
+                Ok(out) => out,
+                Err(e) => return invalid_format(2, format!("bad zip data: {}", e)),
+            };
+            if out.len() < expected {
+                return invalid_format(2, "channel decompresses to the wrong size");
+            }
+            if compression == 3 {
+                unpredict(&mut out, row_size, bps);
+            }
+            out
+        }
+        _ => return invalid_format(0, "unknown compression"),
+    };
+    Ok(out)
+}
+
+/// Build an image out of planes of samples, keeping the top byte of each
+fn assemble(
+    color_mode: ColorMode,
+    width: usize,
+    height: usize,
+    bps: usize,
+    colors: &[Option<&[u8]>],
+    alpha: Option<&[u8]>,
+) -> Image {
+    let mut image = Image::new(width as u32, height as u32);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * bps;
+            let sample = |plane: Option<&[u8]>, default: u8| plane.map_or(default, |p| p[i]);
+            let a = sample(alpha, 0xFF);
+            let color = match color_mode {
+                ColorMode::Grayscale => {
+                    let v = sample(colors[0], 0);
+                    RGBA::new(v, v, v, a)
+                }
+                ColorMode::Rgb => RGBA::new(
+                    sample(colors[0], 0),
+                    sample(colors[1], 0),
+                    sample(colors[2], 0),
+                    a,
+                ),
+            };
+            image.write(x as u32, y as u32, color);
+        }
+    }
+    image
+}
+
+/// A single layer of a document
+pub struct Layer {
+    /// The name given to the layer
+    pub name: String,
+    /// The position of the left edge of the layer in the document
+    pub x: i32,
+    /// The position of the top edge of the layer in the document
+    pub y: i32,
+    /// The pixels of the layer, covering only its bounds
+    pub image: Image,
+}
+
+/// The parts of a layer record we need to decode its pixels
+struct LayerRecord {
+    name: String,
+    top: i32,
+    left: i32,
+    width: usize,
+    height: usize,
+    /// The id and data length of each channel
+    channels: Vec<(i16, usize)>,
+}
+
+/// Read the name of a layer from its additional information
+///
+/// Names are stored as MacRoman, but newer files also include
+/// a unicode version of the name, which we prefer.
+fn parse_layer_name(extra: &[u8]) -> PSDResult<String> {
+    let mut i = 0;
+    read_section(extra, &mut i)?;
+    read_section(extra, &mut i)?;
+    let length = match extra.get(i) {
+        Some(&length) => length as usize,
+        None => return invalid_format(i, "missing layer name"),
+    };
+    if extra.len() < i + 1 + length {
+        return invalid_format(i, "layer name extends past the end of the record");
+    }
+    let mut name = String::from_utf8_lossy(&extra[i + 1..i + 1 + length]).into_owned();
+    // The name is padded to a multiple of 4 bytes, including the length
+    i += (length + 1).div_ceil(4) * 4;
+    while i + 12 <= extra.len() {
+        let key = &extra[i + 4..i + 8];
+        let size = u32_be(&extra[i + 8..]) as usize;
+        let start = i + 12;
+        if extra.len() - start < size {
+            break;
+        }
+        if key == b"luni" && size >= 4 {
+            let count = u32_be(&extra[start..]) as usize;
+            let units: Vec<u16> = extra[start + 4..start + size]
+                .chunks_exact(2)
+                .take(count)
+                .map(u16_be)
+                .collect();
+            name = String::from_utf16_lossy(&units);
+        }
+        i = start + size;
+    }
+    Ok(name)
+}
+
+fn parse_layer_record(data: &[u8], i: &mut usize) -> PSDResult<LayerRecord> {
+    if data.len() < *i + 18 {
+        return invalid_format(data.len(), "insufficient layer record length");
+    }
+    let top = i32_be(&data[*i..]);
+    let left = i32_be(&data[*i + 4..]);
+    let bottom = i32_be(&data[*i + 8..]);
+    let right = i32_be(&data[*i + 12..]);
+    if bottom < top || right < left {
+        return invalid_format(*i, "negative layer dimensions");
+    }
+    let channel_count = u16_be(&data[*i + 16..]) as usize;
+    *i += 18;
+    if data.len() < *i + 6 * channel_count + 16 {
+        return invalid_format(data.len(), "insufficient layer record length");
+    }
+    let mut channels = Vec::with_capacity(channel_count);
+    for _ in 0..channel_count {
+        let id = u16_be(&data[*i..]) as i16;
+        let length = u32_be(&data[*i + 2..]) as usize;
+        channels.push((id, length));
+        *i += 6;
+    }
+    if &data[*i..*i + 4] != b"8BIM" {
+        return invalid_format(*i, "missing blend mode signature");
+    }
+    // Skip the blend mode, opacity, clipping, and flags
+    *i += 12;
+    let extra = read_section(data, i)?;
+    let name = offset_by(*i - extra.len(), parse_layer_name(extra))?;
+    Ok(LayerRecord {
+        name,
+        top,
+        left,
+        width: (i64::from(right) - i64::from(left)) as usize,
+        height: (i64::from(bottom) - i64::from(top)) as usize,
+        channels,
+    })
+}
+
+/// Read the layer and mask information section of a file
+fn parse_layer_section(header: &Header, section: &[u8], limits: &Limits) -> PSDResult<Vec<Layer>> {
+    if section.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut i = 0;
+    let info = read_section(section, &mut i)?;
+    if info.len() < 2 {
+        return Ok(Vec::new());
+    }
+    // Everything from here on is in the layer info, after its length
+    let info_start = 4;
+    // A negative count means the first alpha channel is for the composite
+    let count = (u16_be(info) as i16).unsigned_abs() as usize;
+    limits.check_frames(count as u64)?;
+    let mut i = 2;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        let record = offset_by(info_start, parse_layer_record(info, &mut i))?;
+        let (width, height) = (record.width as u64, record.height as u64);
+        limits.check_image(width, height, RGBA_BYTES as u64)?;
+        records.push(record);
+    }
+    let bps = header.bytes_per_sample;
+    let mut layers = Vec::with_capacity(count);
+    for record in records {
+        let mut colors: Vec<Option<Vec<u8>>> = vec![None; header.color_mode.color_channels()];
+        let mut alpha = None;
+        for &(id, length) in &record.channels {
+            if info.len() - i < length {
+                let message = "channel extends past the end of the layer section";
+                return invalid_format(info_start + i, message);
+            }
+            let start = info_start + i;
+            let data = &info[i..i + length];
+            i += length;
+            // Masks have their own bounds, and don't contribute to the pixels
+            if id < ALPHA_CHANNEL {
+                continue;
+            }
+            limits.check_alloc((record.width * record.height * bps) as u64)?;
+            let plane = offset_by(
+                start,
+                decode_channel(data, record.width, record.height, bps),
+            )?;
+            if id == ALPHA_CHANNEL {
+                alpha = Some(plane);
+            } else if let Some(color) = colors.get_mut(id as usize) {
+                *color = Some(plane);
+            }
+        }
+        let colors: Vec<Option<&[u8]>> = colors.iter().map(|c| c.as_deref()).collect();
+        let image = assemble(
+            header.color_mode,
+            record.width,
+            record.height,
+            bps,
+            &colors,
+            alpha.as_deref(),
+        );
+        layers.push(Layer {
+            name: record.name,
+            x: record.left,
+            y: record.top,
+            image,
+        });
+    }
+    Ok(layers)
+}
+
+/// The parts of a file we decode, along with where they start in it
+struct Sections<'a> {
+    header: Header,
+    layers: &'a [u8],
+    layers_start: usize,
+    /// The merged image data, which runs to the end of the file
+    composite: &'a [u8],
+    composite_start: usize,
+}
+
+/// Split a file into its header, the layer section, and the composite data
+fn parse_sections<'a>(data: &'a [u8], limits: &Limits) -> PSDResult<Sections<'a>> {
+    let header = parse_header(data)?;
+    let (width, height) = (header.width as u64, header.height as u64);
+    limits.check_image(width, height, RGBA_BYTES as u64)?;
+    let mut i = HEADER_SIZE;
+    // The color mode data and image resources aren't needed for RGB or grayscale
+    read_section(data, &mut i)?;
+    let resources = read_section(data, &mut i)?;
+    limits.check_metadata(resources.len() as u64)?;
+    let layers_start = i + 4;
+    let layers = read_section(data, &mut i)?;
+    Ok(Sections {
+        header,
+        layers,
+        layers_start,
+        composite: &data[i..],
+        composite_start: i,
+    })
+}
+
+/// Decode the merged composite of all the layers in a file
+pub fn parse_image(data: &[u8]) -> PSDResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> PSDResult<Image> {
+    parse_native(data, limits).map(|decoded| decoded.into_low(None))
+}
+
+/// Parse an image, keeping grayscale images as grayscale
+pub fn parse_native(data: &[u8], limits: &Limits) -> PSDResult<Decoded> {
+    let Sections {
+        header,
+        layers,
+        composite: data,
+        composite_start: start,
+        ..
+    } = parse_sections(data, limits)?;
+    if data.len() < 2 {
+        return invalid_format(start + data.len(), "insufficient image data");
+    }
+    let bps = header.bytes_per_sample;
+    let plane_size = header.width * header.height * bps;
+    let expected = plane_size * header.channels;
+    limits.check_alloc(expected as u64)?;
+    let planes = match u16_be(data) {
+        0 => {
+            if data.len() - 2 < expected {
+                return invalid_format(start + data.len(), "insufficient image data");
+            }
+            data[2..2 + expected].to_vec()
+        }
+        1 => {
+            let rows = header.height * header.channels;
+            let row_size = header.width * bps;
+            offset_by(start + 2, unpack_rows(&data[2..], rows, row_size))?
+        }
+        _ => return unsupported_format("unsupported composite compression"),
+    };
+    let plane = |c: usize| Some(&planes[c * plane_size..(c + 1) * plane_size]);
+    let color_channels = header.color_mode.color_channels();
+    let colors: Vec<Option<&[u8]>> = (0..color_channels).map(plane).collect();
+    let alpha = if header.channels > color_channels && has_merged_alpha(layers) {
+        plane(color_channels)
+    } else {
+        None
+    };
+    let (width, height) = (header.width as u32, header.height as u32);
+    if let (ColorMode::Grayscale, Some(gray)) = (header.color_mode, colors[0]) {
+        // We keep the top byte of each sample, like `assemble` does
+        let sample =
+            |plane: &[u8], x: u32, y: u32| plane[(y as usize * header.width + x as usize) * bps];
+        let decoded = match alpha {
+            None => {
+                let mut image = Image::new(width, height);
+                for y in 0..height {
+                    for x in 0..width {
+                        image.write(x, y, Luma8::new(sample(gray, x, y)));
+                    }
+                }
+                Decoded::Gray(image)
+            }
+            Some(alpha) => {
+                let mut image = Image::new(width, height);
+                for y in 0..height {
+                    for x in 0..width {
+                        let a = sample(alpha, x, y);
+                        image.write(x, y, LumaA8::new(unmatte(sample(gray, x, y), a), a));
+                    }
+                }
+                Decoded::GrayAlpha(image)
+            }
+        };
+        return Ok(decoded);
+    }
+    let mut image = assemble(
+        header.color_mode,
+        header.width,
+        header.height,
+        bps,
+        &colors,
+        alpha,
+    );
+    // Transparent composites are stored blended against white
+    if alpha.is_some() {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let pixel = image.read(x, y);
+                image.write(x, y, remove_matte(pixel));
+            }
+        }
+    }
+    Ok(Decoded::Low(image))
+}
+
+/// Whether the first extra channel of the composite holds its transparency
+///
+/// This is signalled by the layer count being negative.
+fn has_merged_alpha(section: &[u8]) -> bool {
+    section.len() >= 6 && u32_be(section) >= 2 && (u16_be(&section[4..]) as i16) < 0
+}
+
+/// Undo the blending of a single channel against white, given its alpha
+fn unmatte(c: u8, a: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    let a = u32::from(a);
+    let c = u32::from(c) + a;
+    (c.saturating_sub(0xFF) * 0xFF / a).min(0xFF) as u8
+}
+
+/// Undo the blending of a color against a white background
+fn remove_matte(pixel: RGBA) -> RGBA {
+    if pixel.a == 0 {
+        return RGBA::new(0, 0, 0, 0);
+    }
+    RGBA::new(
+        unmatte(pixel.r, pixel.a),
+        unmatte(pixel.g, pixel.a),
+        unmatte(pixel.b, pixel.a),
+        pixel.a,
+    )
+}
+
+/// The names of the color modes, in the order the header numbers them
+const COLOR_MODE_NAMES: [&str; 10] = [
+    "bitmap",
+    "grayscale",
+    "indexed",
+    "rgb",
+    "cmyk",
+    "",
+    "",
+    "multichannel",
+    "duotone",
+    "lab",
+];
+
+/// The image resources holding color profiles, and other metadata
+const METADATA_RESOURCES: [u16; 5] = [0x0404, 0x040F, 0x0422, 0x0423, 0x0424];
+
+/// The image resource holding the resolution of the document
+const RESOLUTION_RESOURCE: u16 = 0x03ED;
+
+/// Split the image resources section into the id and data of each resource
+fn parse_resources(section: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut resources = Vec::new();
+    let mut i = 0;
+    while section.len() >= i + 7 && &section[i..i + 4] == b"8BIM" {
+        let id = u16_be(&section[i + 4..]);
+        // The name is a Pascal string, padded to an even length
+        let size_at = i + 6 + (section[i + 6] as usize + 2) / 2 * 2;
+        if section.len() < size_at + 4 {
+            break;
+        }
+        let size = u32_be(&section[size_at..]) as usize;
+        let start = size_at + 4;
+        match section.get(start..start.saturating_add(size)) {
+            Some(data) => resources.push((id, data)),
+            None => break,
+        }
+        i = start + size + size % 2;
+    }
+    resources
+}
+
+/// Describe a file, with the fields of its header and the resolution it was saved at
+///
+/// Unlike decoding, this works for every color mode.
+pub fn info(data: &[u8]) -> PSDResult<Info> {
+    if data.len() < HEADER_SIZE {
+        return invalid_format(data.len(), "insufficient header length");
+    }
+    if &data[0..4] != b"8BPS" {
+        return invalid_format(0, "header didn't start with '8BPS'");
+    }
+    let version = u16_be(&data[4..]);
+    match version {
+        1 => {}
+        2 => return unsupported_format("large PSB documents are not supported"),
+        _ => return invalid_format(4, "unknown version"),
+    }
+    let channels = u16_be(&data[12..]);
+    let depth = u16_be(&data[22..]);
+    let mode = u16_be(&data[24..]);
+    let mut info = Info::new(u32_be(&data[18..]), u32_be(&data[14..]));
+    info.bit_depth = Some(u32::from(depth) * u32::from(channels));
+    info.color_type = Some(match COLOR_MODE_NAMES.get(mode as usize) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => format!("unknown ({})", mode),
+    });
+    info.field("Header.version", version);
+    info.field("Header.channels", channels);
+    info.field("Header.height", u32_be(&data[14..]));
+    info.field("Header.width", u32_be(&data[18..]));
+    info.field("Header.depth", depth);
+    info.field("Header.color_mode", mode);
+    let mut i = HEADER_SIZE;
+    let color_data = read_section(data, &mut i)?;
+    // Only indexed documents keep a palette here, with 256 colors
+    info.palette_size = Some(if mode == 2 {
+        color_data.len() as u32 / 3
+    } else {
+        0
+    });
+    let resources = parse_resources(read_section(data, &mut i)?);
+    info.metadata = Some(
+        resources
+            .iter()
+            .any(|(id, _)| METADATA_RESOURCES.contains(id)),
+    );
+    if let Some((_, resolution)) = resources.iter().find(|(id, _)| *id == RESOLUTION_RESOURCE) {
+        if resolution.len() >= 16 {
+            // Both resolutions are in pixels per inch, as 16.16 fixed point numbers
+            let (x, y) = (u32_be(resolution), u32_be(&resolution[8..]));
+            info.field("ResolutionInfo.h_res", x);
+            info.field("ResolutionInfo.v_res", y);
+            info.dpi = Some((f64::from(x) / 65536.0, f64::from(y) / 65536.0));
+        }
+    }
+    read_section(data, &mut i)?;
+    if let Some(compression) = data.get(i..i + 2).map(u16_be) {
+        info.compression = Some(match compression {
+            0 => "none".into(),
+            1 => "rle".into(),
+            2 => "zip".into(),
+            3 => "zip with prediction".into(),
+            _ => format!("unknown ({})", compression),
+        });
+        info.field("ImageData.compression", compression);
+    }
+    Ok(info)
+}
+
+/// Decode each layer in a file, from the bottom up
+pub fn parse_layers(data: &[u8]) -> PSDResult<Vec<Layer>> {
+    parse_layers_with(data, &Limits::default())
+}
+
+/// Decode each layer in a file, refusing any that go over the limits
+pub fn parse_layers_with(data: &[u8], limits: &Limits) -> PSDResult<Vec<Layer>> {
+    let Sections {
+        header,
+        layers,
+        layers_start,
+        ..
+    } = parse_sections(data, limits)?;
+    offset_by(layers_start, parse_layer_section(&header, layers, limits))
+}
+
+/// Decodes the merged composite of Photoshop documents, leaving out the layers
+pub struct PSDCodec;
+
+impl ImageDecoder for PSDCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_native(data, &options.limits).map_err(MageError::from)
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push_u16(out: &mut Vec<u8>, v: u16) {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn push_u32(out: &mut Vec<u8>, v: u32) {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+
+    #[test]
+    fn test_composite_and_layers() {
+        // A 2x1 RGB document, with a 1x1 layer named "dot" at (1, 0)
+        let mut data = b"8BPS".to_vec();
+        push_u16(&mut data, 1);
+        data.extend_from_slice(&[0; 6]);
+        push_u16(&mut data, 3);
+        push_u32(&mut data, 1);
+        push_u32(&mut data, 2);
+        push_u16(&mut data, 8);
+        push_u16(&mut data, 3);
+        push_u32(&mut data, 0);
+        push_u32(&mut data, 0);
+
+        let mut info = Vec::new();
+        push_u16(&mut info, 1);
+        for &v in &[0, 1, 1, 2] {
+            push_u32(&mut info, v);
+        }
+        push_u16(&mut info, 3);
+        for id in 0..3 {
+            push_u16(&mut info, id);
+            push_u32(&mut info, 3);
+        }
+        info.extend_from_slice(b"8BIMnorm");
+        info.extend_from_slice(&[0xFF, 0, 0, 0]);
+        push_u32(&mut info, 12);
+        push_u32(&mut info, 0);
+        push_u32(&mut info, 0);
+        info.extend_from_slice(&[3, b'd', b'o', b't']);
+        // Each channel is stored raw
+        for &v in &[0x10, 0x20, 0x30] {
+            info.extend_from_slice(&[0, 0, v]);
+        }
+        let mut section = Vec::new();
+        push_u32(&mut section, info.len() as u32);
+        section.extend_from_slice(&info);
+        push_u32(&mut data, section.len() as u32);
+        data.extend_from_slice(&section);
+
+        // The composite is PackBits compressed, with one row per channel
+        push_u16(&mut data, 1);
+        for _ in 0..3 {
+            push_u16(&mut data, 2);
+        }
+        data.extend_from_slice(&[0xFF, 0x80, 0xFF, 0x40, 0xFF, 0x20]);
+
+        let image = parse_image(&data).unwrap();
+        assert_eq!(image.width, 2);
+        assert_eq!(image.read(1, 0), RGBA::new(0x80, 0x40, 0x20, 0xFF));
+        let layers = parse_layers(&data).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].name, "dot");
+        assert_eq!((layers[0].x, layers[0].y), (1, 0));
+        assert_eq!(
+            layers[0].image.read(0, 0),
+            RGBA::new(0x10, 0x20, 0x30, 0xFF)
+        );
+        // Errors deep inside the layers still point at the right byte
+        let channel = data.len() - 14 - 3;
+        data[channel + 1] = 5;
+        match parse_layers(&data) {
+            Err(PSDError::InvalidFormat(_, offset)) => assert_eq!(offset, channel),
+            _ => panic!("unknown compression decoded"),
+        }
+    }
+
+    #[test]
+    fn test_info() {
+        // A 1x1 CMYK document at 300 DPI, with an empty color profile
+        let mut data = b"8BPS".to_vec();
+        push_u16(&mut data, 1);
+        data.extend_from_slice(&[0; 6]);
+        push_u16(&mut data, 4);
+        push_u32(&mut data, 1);
+        push_u32(&mut data, 1);
+        push_u16(&mut data, 8);
+        push_u16(&mut data, 4);
+        push_u32(&mut data, 0);
+        let mut resources = Vec::new();
+        resources.extend_from_slice(b"8BIM\x03\xED\0\0");
+        push_u32(&mut resources, 16);
+        for _ in 0..2 {
+            push_u32(&mut resources, 300 << 16);
+            push_u32(&mut resources, 0x0001_0001);
+        }
+        resources.extend_from_slice(b"8BIM\x04\x0F\x01a");
+        push_u32(&mut resources, 0);
+        push_u32(&mut data, resources.len() as u32);
+        data.extend_from_slice(&resources);
+        push_u32(&mut data, 0);
+        push_u16(&mut data, 1);
+        assert!(parse_image(&data).is_err());
+        let info = info(&data).unwrap();
+        assert_eq!((info.width, info.height), (1, 1));
+        assert_eq!(info.bit_depth, Some(32));
+        assert_eq!(info.color_type.as_deref(), Some("cmyk"));
+        assert_eq!(info.compression.as_deref(), Some("rle"));
+        assert_eq!(info.dpi, Some((300.0, 300.0)));
+        assert_eq!(info.palette_size, Some(0));
+        assert_eq!(info.metadata, Some(true));
+    }
+
+    #[test]
+    fn test_gray() {
+        // A raw 2x1 grayscale document, without any layers
+        let mut data = b"8BPS".to_vec();
+        push_u16(&mut data, 1);
+        data.extend_from_slice(&[0; 6]);
+        push_u16(&mut data, 1);
+        push_u32(&mut data, 1);
+        push_u32(&mut data, 2);
+        push_u16(&mut data, 8);
+        push_u16(&mut data, 1);
+        for _ in 0..3 {
+            push_u32(&mut data, 0);
+        }
+        push_u16(&mut data, 0);
+        data.extend_from_slice(&[0x00, 0x90]);
+        match parse_native(&data, &Limits::default()).unwrap() {
+            Decoded::Gray(image) => assert_eq!(image.read(1, 0), Luma8::new(0x90)),
+            _ => panic!("grayscale document didn't stay grayscale"),
+        }
+        let image = parse_image(&data).unwrap();
+        assert_eq!(image.read(1, 0), RGBA::new(0x90, 0x90, 0x90, 0xFF));
+    }
+}
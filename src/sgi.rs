@@ -0,0 +1,393 @@
+use crate::codec::{DecodeOptions, Decoded, ImageDecoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, Luma8, LumaA8, Pixel, Rgb8, Rgba16, RGBA};
+use crate::info::Info;
+use crate::limits::{LimitError, Limits};
+// The structures and parsing in this module are based off of Paul Haeberli's
+// "The SGI Image File Format" specification, version 1.00.
+
+fn u16_be(data: &[u8]) -> u16 {
+    ((data[0] as u16) << 8) | (data[1] as u16)
+}
+
+fn u32_be(data: &[u8]) -> u32 {
+    ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | (data[3] as u32)
+}
+
+/// Represents the errors we can encounter when reading an SGI file
+#[derive(Debug)]
+pub enum SGIError {
+    /// The format of the file doesn't match the specification
+    ///
+    /// This also holds the offset of the offending bytes in the file.
+    InvalidFormat(String, usize),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This is the case for the obsolete color map modes.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type SGIResult<T> = Result<T, SGIError>;
+
+impl From<SGIError> for MageError {
+    fn from(error: SGIError) -> Self {
+        match error {
+            SGIError::InvalidFormat(message, offset) => MageError::InvalidFormat {
+                format: "sgi",
+                message,
+                offset: Some(offset),
+            },
+            SGIError::UnsupportedFormat(message) => MageError::unsupported("sgi", message),
+            SGIError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for SGIError {
+    fn from(error: LimitError) -> Self {
+        SGIError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(offset: usize, s: S) -> SGIResult<T> {
+    Err(SGIError::InvalidFormat(s.into(), offset))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> SGIResult<T> {
+    Err(SGIError::UnsupportedFormat(s.into()))
+}
+
+/// The size of the header at the start of each file
+const HEADER_SIZE: usize = 512;
+
+/// This contains the data in the header for SGI
+#[derive(Debug)]
+struct Header {
+    /// Whether or not the scanlines are run length encoded
+    is_rle: bool,
+    /// How many bytes are used for each channel, either 1 or 2
+    bytes_per_channel: usize,
+    width: usize,
+    height: usize,
+    /// How many channels there are, from grayscale up to RGBA
+    channels: usize,
+}
+
+fn parse_header(data: &[u8]) -> SGIResult<Header> {
+    if data.len() < HEADER_SIZE {
+        return invalid_format(data.len(), "insufficient header length");
+    }
+    if u16_be(data) != 474 {
+        return invalid_format(0, "wrong magic number");
+    }
+    let is_rle = match data[2] {
+        0 => false,
+        1 => true,
+        _ => return invalid_format(2, "unknown storage format"),
+    };
+    let bytes_per_channel = data[3] as usize;
+    if bytes_per_channel != 1 && bytes_per_channel != 2 {
+        return invalid_format(3, "bytes per channel not 1 or 2");
+    }
+    let dimension = u16_be(&data[4..]);
+    let width = u16_be(&data[6..]) as usize;
+    // Lower dimensional images leave out these sizes
+    let height = if dimension >= 2 {
+        u16_be(&data[8..]) as usize
+    } else {
+        1
+    };
+    let channels = if dimension >= 3 {
+        u16_be(&data[10..]) as usize
+    } else {
+        1
+    };
+    if !(1..=4).contains(&channels) {
+        return unsupported_format("unsupported channel count");
+    }
+    if u32_be(&data[104..]) != 0 {
+        return unsupported_format("color map images are not supported");
+    }
+    Ok(Header {
+        is_rle,
+        bytes_per_channel,
+        width,
+        height,
+        channels,
+    })
+}
+
+/// Decode one run length encoded scanline of a single channel
+///
+/// The scanline starts at `start` in the file, which errors are relative to.
+fn decode_rle_row(data: &[u8], start: usize, bpc: usize, out: &mut [u8]) -> SGIResult<()> {
+    let read = |i: usize| -> SGIResult<u16> {
+        if i + bpc > data.len() {
+            return invalid_format(start + i, "run extends past the end of the file");
+        }
+        Ok(if bpc == 1 {
+            data[i] as u16
+        } else {
+            u16_be(&data[i..])
+        })
+    };
+    let mut i = 0;
+    let mut x = 0;
+    loop {
+        let control = read(i)?;
+        i += bpc;
+        let count = (control & 0x7F) as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        if x + count * bpc > out.len() {
+            let offset = start + i - bpc;
+            return invalid_format(offset, "run extends past the end of the scanline");
+        }
+        if control & 0x80 != 0 {
+            for _ in 0..count {
+                let value = read(i)?;
+                i += bpc;
+                write_value(out, &mut x, value, bpc);
+            }
+        } else {
+            let value = read(i)?;
+            i += bpc;
+            for _ in 0..count {
+                write_value(out, &mut x, value, bpc);
+            }
+        }
+    }
+}
+
+fn write_value(out: &mut [u8], x: &mut usize, value: u16, bpc: usize) {
+    if bpc == 1 {
+        out[*x] = value as u8;
+    } else {
+        out[*x] = (value >> 8) as u8;
+        out[*x + 1] = value as u8;
+    }
+    *x += bpc;
+}
+
+/// Describe a file, with every field in its header
+pub fn info(data: &[u8]) -> SGIResult<Info> {
+    let header = parse_header(data)?;
+    let mut info = Info::new(header.width as u32, header.height as u32);
+    info.bit_depth = Some((8 * header.bytes_per_channel * header.channels) as u32);
+    let color_type = ["grayscale", "grayscale alpha", "rgb", "rgba"][header.channels - 1];
+    info.color_type = Some(color_type.into());
+    info.compression = Some(if header.is_rle { "rle" } else { "none" }.into());
+    info.palette_size = Some(0);
+    // The name is the only metadata the header has room for
+    let name = &data[24..104];
+    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+    info.metadata = Some(!name.is_empty());
+    info.field("Header.magic", u16_be(data));
+    info.field("Header.storage", data[2]);
+    info.field("Header.bpc", data[3]);
+    let names = ["dimension", "xsize", "ysize", "zsize"];
+    for (i, field) in names.iter().enumerate() {
+        info.field(&format!("Header.{}", field), u16_be(&data[4 + 2 * i..]));
+    }
+    info.field("Header.pixmin", u32_be(&data[12..]));
+    info.field("Header.pixmax", u32_be(&data[16..]));
+    info.field(
+        "Header.imagename",
+        String::from_utf8_lossy(name).into_owned(),
+    );
+    info.field("Header.colormap", u32_be(&data[104..]));
+    Ok(info)
+}
+
+pub fn parse_image(data: &[u8]) -> SGIResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> SGIResult<Image> {
+    let decoded = parse_native(data, limits)?;
+    decoded.check_low(limits)?;
+    Ok(decoded.into_low(None))
+}
+
+/// Gather the channels of each pixel from their planes
+///
+/// The planes hold their rows from the bottom up, so this also flips them.
+fn gather<P: Pixel, F: Fn(&[u16]) -> P>(header: &Header, planes: &[u8], pixel: F) -> Image<P> {
+    let bpc = header.bytes_per_channel;
+    let row_size = header.width * bpc;
+    let plane_size = row_size * header.height;
+    let mut image = Image::new(header.width as u32, header.height as u32);
+    let mut channels = [0; 4];
+    for y in 0..header.height {
+        for x in 0..header.width {
+            for (c, value) in channels[..header.channels].iter_mut().enumerate() {
+                let i = c * plane_size + y * row_size + x * bpc;
+                *value = if bpc == 1 {
+                    u16::from(planes[i])
+                } else {
+                    u16_be(&planes[i..])
+                };
+            }
+            let color = pixel(&channels[..header.channels]);
+            image.write(x as u32, (header.height - 1 - y) as u32, color);
+        }
+    }
+    image
+}
+
+/// Parse an image, keeping the channels and precision of the file
+pub fn parse_native(data: &[u8], limits: &Limits) -> SGIResult<Decoded> {
+    let header = parse_header(data)?;
+    let bpc = header.bytes_per_channel;
+    // Images with 16 bit channels always become RGBA
+    let bytes_per_pixel = if bpc == 1 { header.channels } else { 4 * bpc };
+    let (width, height) = (header.width as u64, header.height as u64);
+    limits.check_image(width, height, bytes_per_pixel as u64)?;
+    let row_size = header.width * bpc;
+    let plane_size = row_size * header.height;
+    limits.check_alloc((plane_size * header.channels) as u64)?;
+    // Each channel is stored as a separate plane, from the bottom row up
+    let mut planes = vec![0; plane_size * header.channels];
+    if header.is_rle {
+        let rows = header.height * header.channels;
+        if data.len() < HEADER_SIZE + 8 * rows {
+            return invalid_format(data.len(), "insufficient offset table length");
+        }
+        for row in 0..rows {
+            let start = u32_be(&data[HEADER_SIZE + 4 * row..]) as usize;
+            let length = u32_be(&data[HEADER_SIZE + 4 * (rows + row)..]) as usize;
+            if start > data.len() || data.len() - start < length {
+                let offset = HEADER_SIZE + 4 * row;
+                return invalid_format(offset, "scanline extends past the end of the file");
+            }
+            let out = &mut planes[row * row_size..(row + 1) * row_size];
+            decode_rle_row(&data[start..start + length], start, bpc, out)?;
+        }
+    } else {
+        let end = HEADER_SIZE + planes.len();
+        if data.len() < end {
+            return invalid_format(data.len(), "insufficient pixel data");
+        }
+        planes.copy_from_slice(&data[HEADER_SIZE..end]);
+    }
+
+    let byte = |c: u16| c as u8;
+    let decoded = match (bpc, header.channels) {
+        (1, 1) => Decoded::Gray(gather(&header, &planes, |c| Luma8::new(byte(c[0])))),
+        (1, 2) => Decoded::GrayAlpha(gather(&header, &planes, |c| {
+            LumaA8::new(byte(c[0]), byte(c[1]))
+        })),
+        (1, 3) => Decoded::Rgb(gather(&header, &planes, |c| {
+            Rgb8::new(byte(c[0]), byte(c[1]), byte(c[2]))
+        })),
+        (1, _) => Decoded::Low(gather(&header, &planes, |c| {
+            RGBA::new(byte(c[0]), byte(c[1]), byte(c[2]), byte(c[3]))
+        })),
+        (_, channels) => Decoded::Rgba16(gather(&header, &planes, |c| match channels {
+            1 => Rgba16::new(c[0], c[0], c[0], 0xFFFF),
+            2 => Rgba16::new(c[0], c[0], c[0], c[1]),
+            3 => Rgba16::new(c[0], c[1], c[2], 0xFFFF),
+            _ => Rgba16::new(c[0], c[1], c[2], c[3]),
+        })),
+    };
+    Ok(decoded)
+}
+
+/// Decodes SGI images, keeping their channels and precision
+pub struct SGICodec;
+
+impl ImageDecoder for SGICodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_native(data, &options.limits).map_err(MageError::from)
+    }
+
+    fn info(&self, data: &[u8], _: &DecodeOptions) -> MageResult<Info> {
+        info(data).map_err(MageError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// An uncompressed file holding a single row of grayscale pixels
+    fn gray_file(bpc: u8, row: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[0..2].copy_from_slice(&474u16.to_be_bytes());
+        data[3] = bpc;
+        data[5] = 2;
+        data[7] = (row.len() / bpc as usize) as u8;
+        data[9] = 1;
+        data.extend_from_slice(row);
+        data
+    }
+
+    #[test]
+    fn test_native() {
+        let data = gray_file(1, &[0x10, 0x80]);
+        match parse_native(&data, &Limits::default()).unwrap() {
+            Decoded::Gray(image) => assert_eq!(image.as_raw(), &[0x10, 0x80]),
+            _ => panic!("grayscale file wasn't decoded as grayscale"),
+        }
+        let image = parse_image(&data).unwrap();
+        assert_eq!(image.read(1, 0), RGBA::new(0x80, 0x80, 0x80, 0xFF));
+        // Enough room for the grayscale pixels, but not for RGBA
+        let limits = Limits {
+            max_alloc: 2,
+            ..Limits::default()
+        };
+        assert!(parse_native(&data, &limits).is_ok());
+        assert!(matches!(
+            parse_image_with(&data, &limits),
+            Err(SGIError::LimitsExceeded(_))
+        ));
+        // The low byte of 16 bit channels survives
+        let data = gray_file(2, &[0x12, 0x34]);
+        match parse_native(&data, &Limits::default()).unwrap() {
+            Decoded::Rgba16(image) => {
+                assert_eq!(
+                    image.read(0, 0),
+                    Rgba16::new(0x1234, 0x1234, 0x1234, 0xFFFF)
+                )
+            }
+            _ => panic!("16 bit file wasn't decoded as 16 bit"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let data = gray_file(1, &[0x10, 0x80]);
+        match parse_image(&data[..HEADER_SIZE + 1]) {
+            Err(SGIError::InvalidFormat(_, offset)) => assert_eq!(offset, HEADER_SIZE + 1),
+            _ => panic!("truncated file decoded"),
+        }
+        // A single run length encoded row, with a run of 3 pixels in a row of 2
+        let mut data = gray_file(1, &[]);
+        data[2] = 1;
+        data.extend_from_slice(&(HEADER_SIZE as u32 + 8).to_be_bytes());
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&[0x03, 0x10]);
+        data[7] = 2;
+        match parse_image(&data) {
+            Err(SGIError::InvalidFormat(_, offset)) => assert_eq!(offset, HEADER_SIZE + 8),
+            _ => panic!("overlong run decoded"),
+        }
+    }
+
+    #[test]
+    fn test_info() {
+        let mut data = gray_file(2, &[0x12, 0x34]);
+        data[24..29].copy_from_slice(b"photo");
+        let info = info(&data).unwrap();
+        assert_eq!((info.width, info.height), (1, 1));
+        assert_eq!(info.bit_depth, Some(16));
+        assert_eq!(info.color_type.as_deref(), Some("grayscale"));
+        assert_eq!(info.compression.as_deref(), Some("none"));
+        assert_eq!(info.metadata, Some(true));
+        assert_eq!(info.fields[9], ("Header.imagename".into(), "photo".into()));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::codec::{DecodeOptions, Decoded, ImageDecoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, RGBA_BYTES, RGBA};
+use crate::limits::{LimitError, Limits};
+// The structures and parsing in this module are based off of the
+// rasterfile(5) manual page from SunOS.
+
+fn u32_be(data: &[u8]) -> u32 {
+    ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | (data[3] as u32)
+}
+
+/// Represents the errors we can encounter when reading a Sun Raster file
+#[derive(Debug)]
+pub enum SunRasterError {
+    /// The format of the file doesn't match the specification
+    ///
+    /// This also holds the offset of the offending bytes in the file.
+    InvalidFormat(String, usize),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This is the case for the TIFF and IFF conversion types.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type SunRasterResult<T> = Result<T, SunRasterError>;
+
+impl From<SunRasterError> for MageError {
+    fn from(error: SunRasterError) -> Self {
+        match error {
+            SunRasterError::InvalidFormat(message, offset) => MageError::InvalidFormat {
+                format: "ras",
+                message,
+                offset: Some(offset),
+            },
+            SunRasterError::UnsupportedFormat(message) => MageError::unsupported("ras", message),
+            SunRasterError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for SunRasterError {
+    fn from(error: LimitError) -> Self {
+        SunRasterError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(offset: usize, s: S) -> SunRasterResult<T> {
+    Err(SunRasterError::InvalidFormat(s.into(), offset))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> SunRasterResult<T> {
+    Err(SunRasterError::UnsupportedFormat(s.into()))
+}
+
+/// The size of the header at the start of each file
+const HEADER_SIZE: usize = 32;
+
+/// The byte introducing a run in byte encoded images
+const ESCAPE: u8 = 0x80;
+
+/// The ways the pixel data in a file can be stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RasterType {
+    /// The original format, identical to `Standard`
+    Old,
+    /// Uncompressed, with pixels in BGR order
+    Standard,
+    /// Run length encoded, with pixels in BGR order
+    ByteEncoded,
+    /// Uncompressed, with pixels in RGB order
+    RGBFormat,
+}
+
+/// This contains the data in the header for Sun Raster
+#[derive(Debug)]
+struct Header {
+    width: usize,
+    height: usize,
+    /// How many bits are in each pixel
+    depth: u32,
+    raster_type: RasterType,
+    /// How many bytes are dedicated to the color map
+    map_length: usize,
+}
+
+fn parse_header(data: &[u8]) -> SunRasterResult<Header> {
+    if data.len() < HEADER_SIZE {
+        return invalid_format(data.len(), "insufficient header length");
+    }
+    if u32_be(data) != 0x59A6_6A95 {
+        return invalid_format(0, "wrong magic number");
+    }
+    let raster_type = match u32_be(&data[20..]) {
+        0 => RasterType::Old,
+        1 => RasterType::Standard,
+        2 => RasterType::ByteEncoded,
+        3 => RasterType::RGBFormat,
+        _ => return unsupported_format("unsupported raster type"),
+    };
+    let map_type = u32_be(&data[24..]);
+    let map_length = u32_be(&data[28..]) as usize;
+    if map_type == 2 {
+        return unsupported_format("raw color maps are not supported");
+    }
+    if map_type > 2 || (map_type == 0 && map_length != 0) {
+        return invalid_format(24, "invalid color map type");
+    }
+    Ok(Header {
+        width: u32_be(&data[4..]) as usize,
+        height: u32_be(&data[8..]) as usize,
+        depth: u32_be(&data[12..]),
+        raster_type,
+        map_length,
+    })
+}
+
+/// Undo the run length encoding of byte encoded images
+///
+/// The pixel data starts at `start` in the file, which errors are relative to.
+fn decode_runs(data: &[u8], start: usize, expected: usize) -> SunRasterResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected);
+    let mut i = 0;
+    while out.len() < expected {
+        if i >= data.len() {
+            return invalid_format(start + i, "insufficient pixel data");
+        }
+        let byte = data[i];
+        i += 1;
+        if byte != ESCAPE {
+            out.push(byte);
+            continue;
+        }
+        match data.get(i) {
+            // An escape followed by 0 is just the escape byte itself
+            Some(0) => {
+                out.push(ESCAPE);
+                i += 1;
+            }
+            Some(&count) if i + 1 < data.len() => {
+                out.extend(std::iter::repeat_n(data[i + 1], count as usize + 1));
+                i += 2;
+            }
+            _ => return invalid_format(start + i - 1, "truncated run"),
+        }
+    }
+    out.truncate(expected);
+    Ok(out)
+}
+
+pub fn parse_image(data: &[u8]) -> SunRasterResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> SunRasterResult<Image> {
+    let header = parse_header(data)?;
+    let (width, height) = (header.width as u64, header.height as u64);
+    limits.check_image(width, height, RGBA_BYTES as u64)?;
+    if data.len() < HEADER_SIZE + header.map_length {
+        return invalid_format(data.len(), "insufficient color map length");
+    }
+    // The map holds all the red values, then green, then blue
+    let map = &data[HEADER_SIZE..HEADER_SIZE + header.map_length];
+    let map_colors = map.len() / 3;
+    let bits_per_line = header.width * header.depth as usize;
+    // Each scanline is padded to 16 bits
+    let line_size = bits_per_line.div_ceil(16) * 2;
+    let start = HEADER_SIZE + header.map_length;
+    let pixels = &data[start..];
+    let expected = line_size * header.height;
+    limits.check_alloc(expected as u64)?;
+    let raw = if header.raster_type == RasterType::ByteEncoded {
+        decode_runs(pixels, start, expected)?
+    } else if pixels.len() < expected {
+        return invalid_format(data.len(), "insufficient pixel data");
+    } else {
+        pixels[..expected].to_vec()
+    };
+    let is_rgb = header.raster_type == RasterType::RGBFormat;
+    let mapped = |index: usize| {
+        if map_colors == 0 {
+            let v = index as u8;
+            return RGBA::new(v, v, v, 0xFF);
+        }
+        let index = index.min(map_colors - 1);
+        let r = map[index];
+        let g = map[map_colors + index];
+        let b = map[2 * map_colors + index];
+        RGBA::new(r, g, b, 0xFF)
+    };
+
+    let mut image = Image::new(header.width as u32, header.height as u32);
+    for y in 0..header.height {
+        let line = &raw[y * line_size..(y + 1) * line_size];
+        for x in 0..header.width {
+            let color = match header.depth {
+                1 => {
+                    let bit = (line[x / 8] >> (7 - x % 8)) & 1;
+                    if map_colors >= 2 {
+                        mapped(bit as usize)
+                    } else if bit == 1 {
+                        RGBA::new(0, 0, 0, 0xFF)
+                    } else {
+                        RGBA::new(0xFF, 0xFF, 0xFF, 0xFF)
+                    }
+                }
+                8 => mapped(line[x] as usize),
+                24 | 32 => {
+                    let bytes = header.depth as usize / 8;
+                    // 32 bit pixels start with a padding byte
+                    let p = &line[x * bytes + bytes - 3..];
+                    if is_rgb {
+                        RGBA::new(p[0], p[1], p[2], 0xFF)
+                    } else {
+                        RGBA::new(p[2], p[1], p[0], 0xFF)
+                    }
+                }
+                _ => return unsupported_format("unsupported pixel depth"),
+            };
+            image.write(x as u32, y as u32, color);
+        }
+    }
+    Ok(image)
+}
+
+/// Decodes Sun Raster images, whether raw or byte encoded
+pub struct SunRasterCodec;
+
+impl ImageDecoder for SunRasterCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::Low)
+            .map_err(MageError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build a file out of its header fields, color map, and pixel data
+    fn raster(
+        width: u32,
+        height: u32,
+        depth: u32,
+        kind: u32,
+        map: &[u8],
+        pixels: &[u8],
+    ) -> Vec<u8> {
+        let map_type = if map.is_empty() { 0 } else { 1 };
+        let fields = [
+            0x59A6_6A95,
+            width,
+            height,
+            depth,
+            pixels.len() as u32,
+            kind,
+            map_type,
+            map.len() as u32,
+        ];
+        let mut data: Vec<u8> = fields.iter().flat_map(|f| f.to_be_bytes()).collect();
+        data.extend_from_slice(map);
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    #[test]
+    fn test_standard() {
+        // Two rows of 3 BGR pixels, each padded by a byte to 16 bits
+        let pixels = [
+            0, 0, 0xFF, 0, 0xFF, 0, 0xFF, 0, 0, 0, //
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 0,
+        ];
+        let image = parse_image(&raster(3, 2, 24, 1, &[], &pixels)).unwrap();
+        assert_eq!(image.read(0, 0), RGBA::new(0xFF, 0, 0, 0xFF));
+        assert_eq!(image.read(2, 0), RGBA::new(0, 0, 0xFF, 0xFF));
+        assert_eq!(image.read(1, 1), RGBA::new(6, 5, 4, 0xFF));
+        let rgb = parse_image(&raster(3, 2, 24, 3, &[], &pixels)).unwrap();
+        assert_eq!(rgb.read(1, 1), RGBA::new(4, 5, 6, 0xFF));
+        let truncated = raster(3, 2, 24, 1, &[], &pixels[..15]);
+        match parse_image(&truncated) {
+            Err(SunRasterError::InvalidFormat(_, offset)) => assert_eq!(offset, 32 + 15),
+            _ => panic!("truncated file decoded"),
+        }
+    }
+
+    #[test]
+    fn test_byte_encoded() {
+        // Red, green, and blue in the map, with a run of 3 blues and an escaped 0x80
+        let map = [0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF];
+        let pixels = [0, ESCAPE, 2, 2, ESCAPE, 0, 1];
+        let image = parse_image(&raster(6, 1, 8, 2, &map, &pixels)).unwrap();
+        let row: Vec<RGBA> = image.into_iter().collect();
+        let (red, blue) = (RGBA::new(0xFF, 0, 0, 0xFF), RGBA::new(0, 0, 0xFF, 0xFF));
+        // Indices past the end of the map get its last color
+        assert_eq!(
+            row,
+            vec![red, blue, blue, blue, blue, RGBA::new(0, 0xFF, 0, 0xFF)]
+        );
+        // The escape at the end has a count, but no color
+        let truncated = raster(6, 1, 8, 2, &map, &pixels[..3]);
+        match parse_image(&truncated) {
+            Err(SunRasterError::InvalidFormat(_, offset)) => assert_eq!(offset, 32 + 9 + 1),
+            _ => panic!("truncated run decoded"),
+        }
+    }
+}
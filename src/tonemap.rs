@@ -0,0 +1,87 @@
+use crate::image::{FloatImage, FloatRGBA, Image, Pixel, RGBA};
+// Tone mapping squeezes the unbounded range of a `FloatImage` into the
+// 8 bits per component of an `Image`, so that it can be displayed. We also
+// go the other way, so that ordinary images can be saved in those formats.
+
+/// The gamma most displays expect
+pub const DEFAULT_GAMMA: f32 = 2.2;
+
+/// Convert a linear component into 8 bits, after gamma correction
+fn encode(value: f32, gamma: f32) -> u8 {
+    // Casting NaN to an integer gives 0
+    let clamped = value.clamp(0.0, 1.0);
+    (clamped.powf(1.0 / gamma) * 255.0 + 0.5) as u8
+}
+
+/// Convert a component from 0.0 to 1.0 back into a linear value, undoing gamma
+fn decode(value: f32, gamma: f32) -> f32 {
+    value.powf(gamma)
+}
+
+fn encode_alpha(alpha: f32) -> u8 {
+    (alpha.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+fn map_pixels<F: Fn(FloatRGBA) -> RGBA>(image: &FloatImage, f: F) -> Image {
+    let mut out = Image::new(image.width, image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            out.write(x, y, f(image.read(x, y)));
+        }
+    }
+    out
+}
+
+/// Tone map an image using Reinhard's global operator
+///
+/// Each pixel is scaled by `1 / (1 + L)`, where `L` is its luminance, which
+/// brings bright areas into range while leaving dark areas mostly intact.
+pub fn reinhard(image: &FloatImage) -> Image {
+    map_pixels(image, |p| {
+        let luminance = 0.2126 * p.r + 0.7152 * p.g + 0.0722 * p.b;
+        let scale = 1.0 / (1.0 + luminance.max(0.0));
+        RGBA::new(
+            encode(p.r * scale, DEFAULT_GAMMA),
+            encode(p.g * scale, DEFAULT_GAMMA),
+            encode(p.b * scale, DEFAULT_GAMMA),
+            encode_alpha(p.a),
+        )
+    })
+}
+
+/// Tone map an image by adjusting its exposure, and then applying gamma
+///
+/// The exposure is in stops, so each step doubles the brightness. Anything
+/// that ends up brighter than 1.0 is clipped.
+pub fn exposure(image: &FloatImage, stops: f32, gamma: f32) -> Image {
+    let scale = 2f32.powf(stops);
+    map_pixels(image, |p| {
+        RGBA::new(
+            encode(p.r * scale, gamma),
+            encode(p.g * scale, gamma),
+            encode(p.b * scale, gamma),
+            encode_alpha(p.a),
+        )
+    })
+}
+
+/// Turn an ordinary image into a linear floating point one
+///
+/// This is the inverse of `exposure` with 0 stops, so nothing brighter than
+/// 1.0 comes out. Any kind of pixel works, like 16 bit or grayscale ones.
+pub fn linearize<P: Pixel>(image: &Image<P>, gamma: f32) -> FloatImage {
+    let mut out = FloatImage::new(image.width, image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let p = image.read(x, y).to_float();
+            let pixel = FloatRGBA::new(
+                decode(p.r, gamma),
+                decode(p.g, gamma),
+                decode(p.b, gamma),
+                p.a,
+            );
+            out.write(x, y, pixel);
+        }
+    }
+    out
+}
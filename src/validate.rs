@@ -0,0 +1,54 @@
+use std::fmt;
+// Validating a file means checking it against the specification of its
+// format. Unlike decoding, which stops at the first problem, we report every
+// deviation we can find, so that broken files can be understood and fixed.
+
+/// How bad a deviation from the specification is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Readers can usually cope with this, but shouldn't have to
+    Warning,
+    /// The file is broken, and readers can't be expected to make sense of it
+    Error,
+}
+
+/// A single deviation from the specification of a format
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// The offset of the offending bytes in the file
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn error<S: Into<String>>(offset: usize, message: S) -> Self {
+        Issue {
+            severity: Severity::Error,
+            offset,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning<S: Into<String>>(offset: usize, message: S) -> Self {
+        Issue {
+            severity: Severity::Warning,
+            offset,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}: {} (at byte {})",
+            severity, self.message, self.offset
+        )
+    }
+}
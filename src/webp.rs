@@ -0,0 +1,994 @@
+use crate::codec::{DecodeOptions, Decoded, ImageDecoder};
+use crate::error::{MageError, MageResult};
+use crate::huffman::{BitReader, Huffman};
+use crate::image::{Image, RGBA_BYTES, RGBA};
+use crate::limits::{LimitError, Limits};
+// The structures and decoding in this module follow the WebP container
+// specification, and the lossless bitstream specification:
+// https://developers.google.com/speed/webp/docs/riff_container
+// https://developers.google.com/speed/webp/docs/webp_lossless_bitstream_specification
+
+fn u32_le(data: &[u8]) -> u32 {
+    (data[0] as u32) | ((data[1] as u32) << 8) | ((data[2] as u32) << 16) | ((data[3] as u32) << 24)
+}
+
+/// Represents the errors we can encounter when reading a WebP file
+#[derive(Debug)]
+pub enum WebPError {
+    /// The format of the file doesn't match the specification
+    InvalidFormat(String),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This is the case for lossy and animated images for now.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type WebPResult<T> = Result<T, WebPError>;
+
+impl From<WebPError> for MageError {
+    fn from(error: WebPError) -> Self {
+        match error {
+            WebPError::InvalidFormat(message) => MageError::invalid("webp", message),
+            WebPError::UnsupportedFormat(message) => MageError::unsupported("webp", message),
+            WebPError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for WebPError {
+    fn from(error: LimitError) -> Self {
+        WebPError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(s: S) -> WebPResult<T> {
+    Err(WebPError::InvalidFormat(s.into()))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> WebPResult<T> {
+    Err(WebPError::UnsupportedFormat(s.into()))
+}
+
+/// A single chunk inside of the RIFF container
+struct Chunk<'a> {
+    /// The four character code identifying this chunk
+    fourcc: &'a [u8],
+    data: &'a [u8],
+}
+
+/// Split the contents of the RIFF container into chunks
+fn parse_chunks(data: &[u8]) -> WebPResult<Vec<Chunk<'_>>> {
+    if data.len() < 12 {
+        return invalid_format("insufficient RIFF header length");
+    }
+    if &data[0..4] != b"RIFF" {
+        return invalid_format("header didn't start with 'RIFF'");
+    }
+    if &data[8..12] != b"WEBP" {
+        return invalid_format("RIFF form type wasn't 'WEBP'");
+    }
+    let riff_size = u32_le(&data[4..]) as usize;
+    if riff_size < 4 || data.len() < 8 + riff_size {
+        return invalid_format("insufficient RIFF data");
+    }
+    let mut chunks = Vec::new();
+    let mut i = 12;
+    let end = 8 + riff_size;
+    while i + 8 <= end {
+        let fourcc = &data[i..i + 4];
+        let size = u32_le(&data[i + 4..]) as usize;
+        let start = i + 8;
+        if end - start < size {
+            return invalid_format("chunk extends past the end of the file");
+        }
+        chunks.push(Chunk {
+            fourcc,
+            data: &data[start..start + size],
+        });
+        // Chunks are padded to an even number of bytes
+        i = start + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+/// Find the lossless bitstream inside the container
+fn find_vp8l<'a>(chunks: &[Chunk<'a>]) -> WebPResult<&'a [u8]> {
+    let first = match chunks.first() {
+        Some(chunk) => chunk,
+        None => return invalid_format("no chunks in file"),
+    };
+    match first.fourcc {
+        b"VP8L" => return Ok(first.data),
+        b"VP8 " => return unsupported_format("lossy images are not supported"),
+        b"VP8X" => {}
+        _ => return invalid_format("unknown first chunk"),
+    }
+    if first.data.len() < 10 {
+        return invalid_format("insufficient VP8X chunk length");
+    }
+    // The animation flag
+    if first.data[0] & 0x02 != 0 {
+        return unsupported_format("animated images are not supported");
+    }
+    for chunk in &chunks[1..] {
+        match chunk.fourcc {
+            b"VP8L" => return Ok(chunk.data),
+            b"VP8 " => return unsupported_format("lossy images are not supported"),
+            _ => {}
+        }
+    }
+    invalid_format("no image data chunk")
+}
+
+fn read_bits(reader: &mut BitReader, n: u32) -> WebPResult<u32> {
+    match reader.read_bits(n) {
+        Some(bits) => Ok(bits),
+        None => invalid_format("unexpected end of bitstream"),
+    }
+}
+
+/// A prefix code, as used for every alphabet in the bitstream
+enum PrefixCode {
+    /// Only one symbol is used, so reading it takes no bits at all
+    Single(u16),
+    Tree(Huffman),
+}
+
+impl PrefixCode {
+    fn new(lengths: &[u8]) -> WebPResult<PrefixCode> {
+        let mut used = lengths.iter().enumerate().filter(|(_, &len)| len != 0);
+        let first = match used.next() {
+            Some((symbol, _)) => symbol,
+            None => return invalid_format("prefix code with no symbols"),
+        };
+        if used.next().is_none() {
+            return Ok(PrefixCode::Single(first as u16));
+        }
+        match Huffman::new(lengths) {
+            Some(huffman) => Ok(PrefixCode::Tree(huffman)),
+            None => invalid_format("over-subscribed prefix code"),
+        }
+    }
+
+    fn read(&self, reader: &mut BitReader) -> WebPResult<u16> {
+        match self {
+            PrefixCode::Single(symbol) => Ok(*symbol),
+            PrefixCode::Tree(huffman) => match huffman.decode(reader) {
+                Some(symbol) => Ok(symbol),
+                None => invalid_format("invalid prefix code"),
+            },
+        }
+    }
+}
+
+/// The order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+fn read_prefix_code(reader: &mut BitReader, alphabet_size: usize) -> WebPResult<PrefixCode> {
+    let mut lengths = vec![0u8; alphabet_size];
+    if read_bits(reader, 1)? == 1 {
+        // A simple code, with one or two symbols
+        let symbol_count = read_bits(reader, 1)? + 1;
+        let first_bits = if read_bits(reader, 1)? == 1 { 8 } else { 1 };
+        let first = read_bits(reader, first_bits)? as usize;
+        if first >= alphabet_size {
+            return invalid_format("simple code symbol out of range");
+        }
+        lengths[first] = 1;
+        if symbol_count == 2 {
+            let second = read_bits(reader, 8)? as usize;
+            if second >= alphabet_size {
+                return invalid_format("simple code symbol out of range");
+            }
+            lengths[second] = 1;
+        }
+        return PrefixCode::new(&lengths);
+    }
+    let mut code_length_lengths = [0u8; 19];
+    let count = read_bits(reader, 4)? as usize + 4;
+    for &i in &CODE_LENGTH_ORDER[..count] {
+        code_length_lengths[i] = read_bits(reader, 3)? as u8;
+    }
+    let code_length_code = PrefixCode::new(&code_length_lengths)?;
+    let mut max_symbol = if read_bits(reader, 1)? == 1 {
+        let length_bits = 2 + 2 * read_bits(reader, 3)?;
+        let max_symbol = 2 + read_bits(reader, length_bits)? as usize;
+        if max_symbol > alphabet_size {
+            return invalid_format("max symbol larger than alphabet");
+        }
+        max_symbol
+    } else {
+        alphabet_size
+    };
+    let mut previous = 8;
+    let mut symbol = 0;
+    while symbol < alphabet_size && max_symbol > 0 {
+        max_symbol -= 1;
+        let code = code_length_code.read(reader)?;
+        if code < 16 {
+            lengths[symbol] = code as u8;
+            symbol += 1;
+            if code != 0 {
+                previous = code as u8;
+            }
+            continue;
+        }
+        let (value, repeat) = match code {
+            16 => (previous, 3 + read_bits(reader, 2)?),
+            17 => (0, 3 + read_bits(reader, 3)?),
+            _ => (0, 11 + read_bits(reader, 7)?),
+        };
+        let repeat = repeat as usize;
+        if symbol + repeat > alphabet_size {
+            return invalid_format("code lengths repeat past the alphabet");
+        }
+        for length in &mut lengths[symbol..symbol + repeat] {
+            *length = value;
+        }
+        symbol += repeat;
+    }
+    PrefixCode::new(&lengths)
+}
+
+/// The five prefix codes used to decode a group of pixels
+struct PrefixGroup {
+    /// Green, along with backward reference lengths and color cache indices
+    green: PrefixCode,
+    red: PrefixCode,
+    blue: PrefixCode,
+    alpha: PrefixCode,
+    distance: PrefixCode,
+}
+
+fn read_prefix_group(reader: &mut BitReader, cache_size: usize) -> WebPResult<PrefixGroup> {
+    Ok(PrefixGroup {
+        green: read_prefix_code(reader, 256 + 24 + cache_size)?,
+        red: read_prefix_code(reader, 256)?,
+        blue: read_prefix_code(reader, 256)?,
+        alpha: read_prefix_code(reader, 256)?,
+        distance: read_prefix_code(reader, 40)?,
+    })
+}
+
+/// Convert a length or distance prefix into the value it represents
+fn prefix_value(reader: &mut BitReader, prefix: u32) -> WebPResult<usize> {
+    if prefix < 4 {
+        return Ok(prefix as usize + 1);
+    }
+    let extra_bits = (prefix - 2) >> 1;
+    let offset = (2 + (prefix & 1)) << extra_bits;
+    Ok((offset + read_bits(reader, extra_bits)?) as usize + 1)
+}
+
+/// The (x, y) offsets that the smallest distance codes refer to
+#[rustfmt::skip]
+const DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1), (1, 0), (1, 1), (-1, 1), (0, 2), (2, 0), (1, 2), (-1, 2),
+    (2, 1), (-2, 1), (2, 2), (-2, 2), (0, 3), (3, 0), (1, 3), (-1, 3),
+    (3, 1), (-3, 1), (2, 3), (-2, 3), (3, 2), (-3, 2), (0, 4), (4, 0),
+    (1, 4), (-1, 4), (4, 1), (-4, 1), (3, 3), (-3, 3), (2, 4), (-2, 4),
+    (4, 2), (-4, 2), (0, 5), (3, 4), (-3, 4), (4, 3), (-4, 3), (5, 0),
+    (1, 5), (-1, 5), (5, 1), (-5, 1), (2, 5), (-2, 5), (5, 2), (-5, 2),
+    (4, 4), (-4, 4), (3, 5), (-3, 5), (5, 3), (-5, 3), (0, 6), (6, 0),
+    (1, 6), (-1, 6), (6, 1), (-6, 1), (2, 6), (-2, 6), (6, 2), (-6, 2),
+    (4, 5), (-4, 5), (5, 4), (-5, 4), (3, 6), (-3, 6), (6, 3), (-6, 3),
+    (0, 7), (7, 0), (1, 7), (-1, 7), (5, 5), (-5, 5), (7, 1), (-7, 1),
+    (4, 6), (-4, 6), (6, 4), (-6, 4), (2, 7), (-2, 7), (7, 2), (-7, 2),
+    (3, 7), (-3, 7), (7, 3), (-7, 3), (5, 6), (-5, 6), (6, 5), (-6, 5),
+    (8, 0), (4, 7), (-4, 7), (7, 4), (-7, 4), (8, 1), (8, 2), (6, 6),
+    (-6, 6), (8, 3), (5, 7), (-5, 7), (7, 5), (-7, 5), (8, 4), (6, 7),
+    (-6, 7), (7, 6), (-7, 6), (8, 5), (7, 7), (-7, 7), (8, 6), (8, 7),
+];
+
+fn map_distance(code: usize, xsize: usize) -> usize {
+    if code > 120 {
+        return code - 120;
+    }
+    let (dx, dy) = DISTANCE_MAP[code - 1];
+    let distance = i64::from(dx) + i64::from(dy) * xsize as i64;
+    if distance < 1 {
+        1
+    } else {
+        distance as usize
+    }
+}
+
+/// A cache of recently used colors, indexed by a hash of the color
+struct ColorCache {
+    bits: u32,
+    colors: Vec<u32>,
+}
+
+impl ColorCache {
+    fn new(bits: u32) -> Self {
+        ColorCache {
+            bits,
+            colors: vec![0; 1 << bits],
+        }
+    }
+
+    fn insert(&mut self, argb: u32) {
+        let index = 0x1e35_a7bd_u32.wrapping_mul(argb) >> (32 - self.bits);
+        self.colors[index as usize] = argb;
+    }
+}
+
+fn ceil_div(a: usize, bits: u32) -> usize {
+    (a + (1 << bits) - 1) >> bits
+}
+
+/// Decode an image of ARGB pixels, made up of prefix codes and pixel data
+///
+/// The main image can use several groups of prefix codes, chosen by an
+/// entropy image, but the images holding transform data only use one.
+fn decode_entropy_coded(
+    reader: &mut BitReader,
+    xsize: usize,
+    ysize: usize,
+    is_main: bool,
+) -> WebPResult<Vec<u32>> {
+    let mut cache = if read_bits(reader, 1)? == 1 {
+        let bits = read_bits(reader, 4)?;
+        if !(1..=11).contains(&bits) {
+            return invalid_format("invalid color cache size");
+        }
+        Some(ColorCache::new(bits))
+    } else {
+        None
+    };
+    let cache_size = cache.as_ref().map_or(0, |c| c.colors.len());
+    let mut entropy = None;
+    if is_main && read_bits(reader, 1)? == 1 {
+        let bits = read_bits(reader, 3)? + 2;
+        let width = ceil_div(xsize, bits);
+        let image = decode_entropy_coded(reader, width, ceil_div(ysize, bits), false)?;
+        entropy = Some((bits, width, image));
+    }
+    let group_count = match &entropy {
+        Some((_, _, image)) => image.iter().map(|p| (p >> 8) & 0xFFFF).max().unwrap_or(0) + 1,
+        None => 1,
+    };
+    let mut groups = Vec::with_capacity(group_count as usize);
+    for _ in 0..group_count {
+        groups.push(read_prefix_group(reader, cache_size)?);
+    }
+
+    let total = xsize * ysize;
+    let mut pixels = Vec::with_capacity(total);
+    while pixels.len() < total {
+        let group = match &entropy {
+            Some((bits, width, image)) => {
+                let x = pixels.len() % xsize;
+                let y = pixels.len() / xsize;
+                let meta = image[(y >> bits) * width + (x >> bits)];
+                &groups[((meta >> 8) & 0xFFFF) as usize]
+            }
+            None => &groups[0],
+        };
+        let symbol = group.green.read(reader)? as u32;
+        if symbol < 256 {
+            let red = group.red.read(reader)? as u32;
+            let blue = group.blue.read(reader)? as u32;
+            let alpha = group.alpha.read(reader)? as u32;
+            let argb = (alpha << 24) | (red << 16) | (symbol << 8) | blue;
+            pixels.push(argb);
+            if let Some(cache) = &mut cache {
+                cache.insert(argb);
+            }
+        } else if symbol < 256 + 24 {
+            let length = prefix_value(reader, symbol - 256)?;
+            let distance_prefix = group.distance.read(reader)? as u32;
+            let distance = map_distance(prefix_value(reader, distance_prefix)?, xsize);
+            if distance > pixels.len() {
+                return invalid_format("backward reference before the start of the image");
+            }
+            if pixels.len() + length > total {
+                return invalid_format("backward reference past the end of the image");
+            }
+            for _ in 0..length {
+                let argb = pixels[pixels.len() - distance];
+                pixels.push(argb);
+                if let Some(cache) = &mut cache {
+                    cache.insert(argb);
+                }
+            }
+        } else {
+            let cache = match &mut cache {
+                Some(cache) => cache,
+                None => return invalid_format("color cache symbol without a cache"),
+            };
+            let argb = cache.colors[(symbol - 256 - 24) as usize];
+            pixels.push(argb);
+            cache.insert(argb);
+        }
+    }
+    Ok(pixels)
+}
+
+/// The transforms an encoder can apply to the image before compressing it
+enum Transform {
+    Predictor {
+        bits: u32,
+        xsize: usize,
+        data: Vec<u32>,
+    },
+    Color {
+        bits: u32,
+        xsize: usize,
+        data: Vec<u32>,
+    },
+    SubtractGreen,
+    ColorIndexing {
+        /// How many pixels are packed into each byte, as a power of 2
+        bits: u32,
+        /// The width of the image before the pixels were packed
+        xsize: usize,
+        table: Vec<u32>,
+    },
+}
+
+/// Read the transform, returning the width of the image after it
+fn read_transform(
+    reader: &mut BitReader,
+    xsize: usize,
+    ysize: usize,
+) -> WebPResult<(Transform, usize)> {
+    match read_bits(reader, 2)? {
+        kind @ 0 | kind @ 1 => {
+            let bits = read_bits(reader, 3)? + 2;
+            let data =
+                decode_entropy_coded(reader, ceil_div(xsize, bits), ceil_div(ysize, bits), false)?;
+            let transform = if kind == 0 {
+                Transform::Predictor { bits, xsize, data }
+            } else {
+                Transform::Color { bits, xsize, data }
+            };
+            Ok((transform, xsize))
+        }
+        2 => Ok((Transform::SubtractGreen, xsize)),
+        _ => {
+            let size = read_bits(reader, 8)? as usize + 1;
+            let mut table = decode_entropy_coded(reader, size, 1, false)?;
+            // Each color is stored as the difference from the last
+            for i in 1..table.len() {
+                table[i] = add_pixels(table[i], table[i - 1]);
+            }
+            let bits = match size {
+                0..=2 => 3,
+                3..=4 => 2,
+                5..=16 => 1,
+                _ => 0,
+            };
+            let transform = Transform::ColorIndexing { bits, xsize, table };
+            Ok((transform, ceil_div(xsize, bits)))
+        }
+    }
+}
+
+/// Add two pixels together, channel by channel, modulo 256
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xFF00_FF00).wrapping_add(b & 0xFF00_FF00) & 0xFF00_FF00;
+    let red_blue = (a & 0x00FF_00FF).wrapping_add(b & 0x00FF_00FF) & 0x00FF_00FF;
+    alpha_green | red_blue
+}
+
+fn channels(argb: u32) -> [i32; 4] {
+    [
+        (argb >> 24) as i32,
+        ((argb >> 16) & 0xFF) as i32,
+        ((argb >> 8) & 0xFF) as i32,
+        (argb & 0xFF) as i32,
+    ]
+}
+
+fn from_channels(c: [i32; 4]) -> u32 {
+    ((c[0] as u32) << 24) | ((c[1] as u32) << 16) | ((c[2] as u32) << 8) | (c[3] as u32)
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    from_channels([
+        (a[0] + b[0]) / 2,
+        (a[1] + b[1]) / 2,
+        (a[2] + b[2]) / 2,
+        (a[3] + b[3]) / 2,
+    ])
+}
+
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+    let mut left_distance = 0;
+    let mut top_distance = 0;
+    for i in 0..4 {
+        let estimate = l[i] + t[i] - tl[i];
+        left_distance += (estimate - l[i]).abs();
+        top_distance += (estimate - t[i]).abs();
+    }
+    if left_distance < top_distance {
+        left
+    } else {
+        top
+    }
+}
+
+fn clamp_add_subtract_full(a: u32, b: u32, c: u32) -> u32 {
+    let (a, b, c) = (channels(a), channels(b), channels(c));
+    let mut out = [0; 4];
+    for i in 0..4 {
+        out[i] = (a[i] + b[i] - c[i]).clamp(0, 255);
+    }
+    from_channels(out)
+}
+
+fn clamp_add_subtract_half(a: u32, b: u32) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    let mut out = [0; 4];
+    for i in 0..4 {
+        out[i] = (a[i] + (a[i] - b[i]) / 2).clamp(0, 255);
+    }
+    from_channels(out)
+}
+
+fn predict(mode: u32, pixels: &[u32], i: usize, xsize: usize) -> u32 {
+    let left = pixels[i - 1];
+    let top = pixels[i - xsize];
+    let top_left = pixels[i - xsize - 1];
+    // For the rightmost column, this is the first pixel of the current row
+    let top_right = pixels[i - xsize + 1];
+    match mode {
+        0 => 0xFF00_0000,
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average2(average2(left, top_right), top),
+        6 => average2(left, top_left),
+        7 => average2(left, top),
+        8 => average2(top_left, top),
+        9 => average2(top, top_right),
+        10 => average2(average2(left, top_left), average2(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => clamp_add_subtract_full(left, top, top_left),
+        13 => clamp_add_subtract_half(average2(left, top), top_left),
+        // The remaining modes are reserved, and act like the first
+        _ => 0xFF00_0000,
+    }
+}
+
+fn color_transform_delta(t: u8, c: u8) -> u8 {
+    ((i32::from(t as i8) * i32::from(c as i8)) >> 5) as u8
+}
+
+impl Transform {
+    /// Undo this transform, returning the pixels as they were before it
+    fn invert(&self, mut pixels: Vec<u32>, ysize: usize) -> Vec<u32> {
+        match self {
+            Transform::Predictor { bits, xsize, data } => {
+                let xsize = *xsize;
+                let width = ceil_div(xsize, *bits);
+                for y in 0..ysize {
+                    for x in 0..xsize {
+                        let i = y * xsize + x;
+                        let prediction = match (x, y) {
+                            (0, 0) => 0xFF00_0000,
+                            (_, 0) => pixels[i - 1],
+                            (0, _) => pixels[i - xsize],
+                            _ => {
+                                let block = data[(y >> bits) * width + (x >> bits)];
+                                predict((block >> 8) & 0xF, &pixels, i, xsize)
+                            }
+                        };
+                        pixels[i] = add_pixels(pixels[i], prediction);
+                    }
+                }
+                pixels
+            }
+            Transform::Color { bits, xsize, data } => {
+                let width = ceil_div(*xsize, *bits);
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let (x, y) = (i % xsize, i / xsize);
+                    let element = data[(y >> bits) * width + (x >> bits)];
+                    let red_to_blue = (element >> 16) as u8;
+                    let green_to_blue = (element >> 8) as u8;
+                    let green_to_red = element as u8;
+                    let green = (*pixel >> 8) as u8;
+                    let mut red = (*pixel >> 16) as u8;
+                    let mut blue = *pixel as u8;
+                    red = red.wrapping_add(color_transform_delta(green_to_red, green));
+                    blue = blue.wrapping_add(color_transform_delta(green_to_blue, green));
+                    blue = blue.wrapping_add(color_transform_delta(red_to_blue, red));
+                    *pixel = (*pixel & 0xFF00_FF00) | (u32::from(red) << 16) | u32::from(blue);
+                }
+                pixels
+            }
+            Transform::SubtractGreen => {
+                for pixel in &mut pixels {
+                    let green = (*pixel >> 8) & 0xFF;
+                    *pixel = add_pixels(*pixel, (green << 16) | green);
+                }
+                pixels
+            }
+            Transform::ColorIndexing { bits, xsize, table } => {
+                let packed_width = ceil_div(*xsize, *bits);
+                let bits_per_pixel = 8 >> bits;
+                let mask = (1 << bits_per_pixel) - 1;
+                let mut out = Vec::with_capacity(xsize * ysize);
+                for y in 0..ysize {
+                    for x in 0..*xsize {
+                        let packed = (pixels[y * packed_width + (x >> bits)] >> 8) & 0xFF;
+                        let shift = (x & ((1 << bits) - 1)) as u32 * bits_per_pixel;
+                        let index = ((packed >> shift) & mask) as usize;
+                        // Out of range indices are transparent black
+                        out.push(table.get(index).cloned().unwrap_or(0));
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Decode the lossless bitstream held in a VP8L chunk
+fn decode_vp8l(data: &[u8], limits: &Limits) -> WebPResult<Image> {
+    if data.len() < 5 {
+        return invalid_format("insufficient VP8L header length");
+    }
+    if data[0] != 0x2F {
+        return invalid_format("VP8L signature wasn't 0x2F");
+    }
+    let mut reader = BitReader::new(&data[1..]);
+    let width = read_bits(&mut reader, 14)? as usize + 1;
+    let height = read_bits(&mut reader, 14)? as usize + 1;
+    limits.check_image(width as u64, height as u64, RGBA_BYTES as u64)?;
+    let _alpha_is_used = read_bits(&mut reader, 1)?;
+    if read_bits(&mut reader, 3)? != 0 {
+        return unsupported_format("unknown VP8L version");
+    }
+
+    let mut transforms = Vec::new();
+    let mut xsize = width;
+    while read_bits(&mut reader, 1)? == 1 {
+        let (transform, next_xsize) = read_transform(&mut reader, xsize, height)?;
+        let repeated = transforms
+            .iter()
+            .any(|t| std::mem::discriminant(t) == std::mem::discriminant(&transform));
+        if repeated {
+            return invalid_format("transform used more than once");
+        }
+        transforms.push(transform);
+        xsize = next_xsize;
+    }
+    let mut pixels = decode_entropy_coded(&mut reader, xsize, height, true)?;
+    for transform in transforms.iter().rev() {
+        pixels = transform.invert(pixels, height);
+    }
+
+    let mut image = Image::new(width as u32, height as u32);
+    for (i, &argb) in pixels.iter().enumerate() {
+        let color = RGBA::new(
+            (argb >> 16) as u8,
+            (argb >> 8) as u8,
+            argb as u8,
+            (argb >> 24) as u8,
+        );
+        image.write((i % width) as u32, (i / width) as u32, color);
+    }
+    Ok(image)
+}
+
+pub fn parse_image(data: &[u8]) -> WebPResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> WebPResult<Image> {
+    let chunks = parse_chunks(data)?;
+    // We skip over color profiles and metadata, but they still count
+    let metadata: usize = chunks
+        .iter()
+        .filter(|c| matches!(c.fourcc, b"ICCP" | b"EXIF" | b"XMP "))
+        .map(|c| c.data.len())
+        .sum();
+    limits.check_metadata(metadata as u64)?;
+    let frames = chunks.iter().filter(|c| c.fourcc == b"ANMF").count();
+    limits.check_frames(frames as u64)?;
+    let bitstream = find_vp8l(&chunks)?;
+    decode_vp8l(bitstream, limits)
+}
+
+/// Decodes still, lossless WebP images, skipping over their metadata
+pub struct WebPCodec;
+
+impl ImageDecoder for WebPCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::Low)
+            .map_err(MageError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writes bits in the order `BitReader` reads them, lowest first
+    struct Bits {
+        data: Vec<u8>,
+        count: usize,
+    }
+
+    impl Bits {
+        /// Start a bitstream with the header of a VP8L chunk
+        fn vp8l(width: u32, height: u32) -> Bits {
+            let mut bits = Bits {
+                data: Vec::new(),
+                count: 0,
+            };
+            bits.put(0x2F, 8);
+            bits.put(width - 1, 14);
+            bits.put(height - 1, 14);
+            // No alpha hint, and version 0
+            bits.put(0, 4);
+            bits
+        }
+
+        fn put(&mut self, value: u32, n: u32) {
+            for i in 0..n {
+                if self.count.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.data.last_mut().unwrap() |= bit << (self.count % 8);
+                self.count += 1;
+            }
+        }
+
+        /// Write a simple prefix code, with one or two symbols below 256
+        fn simple(&mut self, symbols: &[u32]) {
+            self.put(1, 1);
+            self.put(symbols.len() as u32 - 1, 1);
+            self.put(1, 1);
+            for &symbol in symbols {
+                self.put(symbol, 8);
+            }
+        }
+
+        /// Write a prefix code where two symbols of any alphabet take 1 bit
+        ///
+        /// The code length code gives lengths 0 and 1 a single bit each, so
+        /// that each symbol's length is written as one bit.
+        fn pair(&mut self, alphabet: usize, a: usize, b: usize) {
+            self.put(0, 1);
+            // Lengths for 17, 18, 0 and 1
+            self.put(0, 4);
+            for &length in &[0, 0, 1, 1] {
+                self.put(length, 3);
+            }
+            // Every symbol of the alphabet has a length
+            self.put(0, 1);
+            for symbol in 0..alphabet {
+                self.put((symbol == a || symbol == b) as u32, 1);
+            }
+        }
+
+        /// Put the bitstream in a RIFF container
+        fn file(&self) -> Vec<u8> {
+            container(&self.data)
+        }
+    }
+
+    fn container(bitstream: &[u8]) -> Vec<u8> {
+        let padding = bitstream.len() % 2;
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(12 + bitstream.len() as u32 + padding as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBPVP8L");
+        data.extend_from_slice(&(bitstream.len() as u32).to_le_bytes());
+        data.extend_from_slice(bitstream);
+        data.resize(data.len() + padding, 0);
+        data
+    }
+
+    fn argb(image: &Image) -> Vec<u32> {
+        image
+            .into_iter()
+            .map(|p| u32::from_be_bytes([p.a, p.r, p.g, p.b]))
+            .collect()
+    }
+
+    fn is_invalid<T>(result: WebPResult<T>) -> bool {
+        matches!(result, Err(WebPError::InvalidFormat(_)))
+    }
+
+    #[test]
+    fn test_backward_reference() {
+        let mut bits = Bits::vp8l(3, 2);
+        // No transforms, color cache, or entropy image
+        bits.put(0, 3);
+        // Green has a literal, and the prefix for a length of 3
+        bits.pair(280, 0x20, 256 + 2);
+        bits.simple(&[0x10, 0x40]);
+        bits.simple(&[0x30]);
+        bits.simple(&[0xFF]);
+        // Distance code 1 is the pixel right above
+        bits.simple(&[0]);
+        for &red in &[0, 1, 0] {
+            bits.put(0, 1);
+            bits.put(red, 1);
+        }
+        bits.put(1, 1);
+        let image = parse_image(&bits.file()).unwrap();
+        let row = [0xFF10_2030, 0xFF40_2030, 0xFF10_2030];
+        assert_eq!(argb(&image), [row, row].concat());
+        // Without the byte holding the pixels
+        let truncated = &bits.data[..bits.data.len() - 1];
+        assert!(is_invalid(parse_image(&container(truncated))));
+        // A reference to before the first pixel
+        let mut bits = Bits::vp8l(3, 2);
+        bits.put(0, 3);
+        bits.pair(280, 0x20, 256 + 2);
+        for _ in 0..4 {
+            bits.simple(&[0]);
+        }
+        bits.put(0, 1);
+        bits.put(1, 1);
+        assert!(is_invalid(parse_image(&bits.file())));
+    }
+
+    #[test]
+    fn test_color_cache() {
+        let mut bits = Bits::vp8l(3, 1);
+        // No transforms, and a cache of 2 colors
+        bits.put(0, 1);
+        bits.put(1, 1);
+        bits.put(1, 4);
+        bits.put(0, 1);
+        // Green has a literal, and the first entry of the cache
+        bits.pair(256 + 24 + 2, 0x20, 256 + 24);
+        bits.simple(&[0x10, 0x40]);
+        bits.simple(&[0x30]);
+        bits.simple(&[0xFF]);
+        bits.simple(&[0]);
+        // 0xFF102030 hashes to the first entry, and 0xFF402030 to the second
+        bits.put(0, 2);
+        bits.put(0b10, 2);
+        bits.put(1, 1);
+        let image = parse_image(&bits.file()).unwrap();
+        assert_eq!(argb(&image), [0xFF10_2030, 0xFF40_2030, 0xFF10_2030]);
+        // A cache needs between 1 and 11 bits
+        let mut bits = Bits::vp8l(3, 1);
+        bits.put(0, 1);
+        bits.put(1, 1);
+        bits.put(0, 4);
+        assert!(is_invalid(parse_image(&bits.file())));
+    }
+
+    #[test]
+    fn test_predictor() {
+        let mut bits = Bits::vp8l(3, 2);
+        // A predictor transform, with one 4x4 block using the average
+        // of the left and top pixels
+        bits.put(1, 1);
+        bits.put(0, 2);
+        bits.put(0, 3);
+        bits.put(0, 1);
+        bits.simple(&[7]);
+        for _ in 0..4 {
+            bits.simple(&[0]);
+        }
+        bits.put(0, 1);
+        // Every residual adds 0x10 to the color channels
+        bits.put(0, 2);
+        bits.simple(&[0x10]);
+        bits.simple(&[0x10]);
+        bits.simple(&[0x10]);
+        bits.simple(&[0]);
+        bits.simple(&[0]);
+        let image = parse_image(&bits.file()).unwrap();
+        let expected = [
+            0xFF10_1010,
+            0xFF20_2020,
+            0xFF30_3030,
+            0xFF20_2020,
+            0xFF30_3030,
+            0xFF40_4040,
+        ];
+        assert_eq!(argb(&image), expected);
+    }
+
+    #[test]
+    fn test_color_transform() {
+        let mut bits = Bits::vp8l(1, 1);
+        bits.put(1, 1);
+        bits.put(1, 2);
+        bits.put(0, 3);
+        // Red to blue, green to blue and green to red multipliers
+        bits.put(0, 1);
+        bits.simple(&[0x10]);
+        bits.simple(&[0x08]);
+        bits.simple(&[0x20]);
+        bits.simple(&[0]);
+        bits.simple(&[0]);
+        bits.put(0, 1);
+        bits.put(0, 2);
+        bits.simple(&[0x40]);
+        bits.simple(&[0]);
+        bits.simple(&[0]);
+        bits.simple(&[0xFF]);
+        bits.simple(&[0]);
+        let image = parse_image(&bits.file()).unwrap();
+        // Red gets 0x20 * 0x40 >> 5, and blue 0x10 * 0x40 >> 5 + 0x08 * 0x40 >> 5
+        assert_eq!(argb(&image), [0xFF40_4030]);
+    }
+
+    #[test]
+    fn test_color_indexing() {
+        let mut bits = Bits::vp8l(4, 1);
+        // A table of two colors, so each index takes a single bit
+        bits.put(1, 1);
+        bits.put(3, 2);
+        bits.put(1, 8);
+        bits.put(0, 1);
+        bits.simple(&[0x10, 0x20]);
+        bits.simple(&[0x10]);
+        bits.simple(&[0x10, 0x30]);
+        bits.simple(&[0x00, 0xFF]);
+        bits.simple(&[0]);
+        // The second color is stored as the difference from the first
+        bits.put(0b111, 3);
+        bits.put(0b000, 3);
+        bits.put(0, 1);
+        // The indices 0, 1, 1, 0, packed into the green channel
+        bits.put(0, 2);
+        bits.simple(&[0b0110]);
+        bits.simple(&[0]);
+        bits.simple(&[0]);
+        bits.simple(&[0]);
+        bits.simple(&[0]);
+        let image = parse_image(&bits.file()).unwrap();
+        let (first, second) = (0xFF10_2030, 0xFF20_3040);
+        assert_eq!(argb(&image), [first, second, second, first]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut bits = Bits::vp8l(3, 2);
+        bits.put(0, 3);
+        bits.pair(280, 0x20, 256 + 2);
+        let data = bits.data.clone();
+        // Cut off partway through the prefix codes
+        assert!(is_invalid(parse_image(&container(
+            &data[..data.len() - 20]
+        ))));
+        let mut broken = data;
+        broken[0] = 0x2E;
+        assert!(is_invalid(parse_image(&container(&broken))));
+        // The same transform twice
+        let mut bits = Bits::vp8l(1, 1);
+        bits.put(0b101, 3);
+        bits.put(0b101, 3);
+        assert!(is_invalid(parse_image(&bits.file())));
+        assert!(is_invalid(parse_image(b"RIFF\x04\0\0\0WEBP")));
+    }
+
+    #[test]
+    fn test_subtract_green() {
+        // A 2x2 image of one color, stored with the subtract green transform
+        // and a single symbol code for each channel.
+        let data = [
+            0x52, 0x49, 0x46, 0x46, 0x1A, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50,
+            0x38, 0x4C, 0x0E, 0x00, 0x00, 0x00, 0x2F, 0x01, 0x40, 0x00, 0x00, 0x45, 0x41, 0x0A,
+            0x5F, 0x88, 0xFE, 0x17, 0x00, 0x00,
+        ];
+        let image = parse_image(&data).unwrap();
+        assert_eq!(image.width, 2);
+        assert_eq!(image.height, 2);
+        let color = RGBA::new(0x10, 0x20, 0x30, 0xFF);
+        for pixel in &image {
+            assert_eq!(pixel, color);
+        }
+    }
+}
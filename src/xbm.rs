@@ -0,0 +1,263 @@
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, ImageView, Luma8};
+use crate::limits::{LimitError, Limits};
+use std::io;
+// XBM files are C source code, with a few #defines for the dimensions,
+// followed by an array holding the bits of the image. Each row starts on a
+// new byte, and the leftmost pixel of each byte is its lowest bit.
+
+/// Represents the errors we can encounter when reading an XBM file
+#[derive(Debug)]
+pub enum XBMError {
+    /// The format of the file doesn't match the specification
+    InvalidFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type XBMResult<T> = Result<T, XBMError>;
+
+impl From<XBMError> for MageError {
+    fn from(error: XBMError) -> Self {
+        match error {
+            XBMError::InvalidFormat(message) => MageError::invalid("xbm", message),
+            XBMError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for XBMError {
+    fn from(error: LimitError) -> Self {
+        XBMError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(s: S) -> XBMResult<T> {
+    Err(XBMError::InvalidFormat(s.into()))
+}
+
+/// The color we use for set bits
+const FOREGROUND: Luma8 = Luma8 { luma: 0 };
+
+/// The color we use for unset bits
+const BACKGROUND: Luma8 = Luma8 { luma: 0xFF };
+
+/// Parse a C integer literal, in either hexadecimal or decimal
+fn parse_number(s: &str) -> XBMResult<u32> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    match parsed {
+        Ok(n) => Ok(n),
+        Err(_) => invalid_format(format!("bad number '{}'", s)),
+    }
+}
+
+/// Find the value of a define whose name ends with a given suffix
+fn find_define(text: &str, suffix: &str) -> XBMResult<Option<u32>> {
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some("#define") {
+            continue;
+        }
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if name.ends_with(suffix) => {
+                return parse_number(value).map(Some);
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+pub fn parse_image(data: &[u8]) -> XBMResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> XBMResult<Image> {
+    let decoded = parse_native(data, limits)?;
+    decoded.check_low(limits)?;
+    Ok(decoded.into_low(None))
+}
+
+/// Parse an image into grayscale, which is all that a bitmap needs
+pub fn parse_native(data: &[u8], limits: &Limits) -> XBMResult<Decoded> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return invalid_format("file isn't valid text"),
+    };
+    let width = find_define(text, "_width")?;
+    let height = find_define(text, "_height")?;
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return invalid_format("missing width or height"),
+    };
+    limits.check_image(u64::from(width), u64::from(height), 1)?;
+    let start = match text.find('{') {
+        Some(i) => i + 1,
+        None => return invalid_format("missing bits array"),
+    };
+    let end = match text[start..].find('}') {
+        Some(i) => start + i,
+        None => return invalid_format("unterminated bits array"),
+    };
+    // The older X10 format stores the bits as 16 bit shorts
+    let unit_bits = if text[..start].contains("short") {
+        16
+    } else {
+        8
+    };
+    let mut values = Vec::new();
+    for item in text[start..end].split(',') {
+        // A trailing comma leaves an empty item
+        if !item.trim().is_empty() {
+            values.push(parse_number(item)?);
+        }
+    }
+    let units_per_row = (width as usize).div_ceil(unit_bits);
+    if values.len() < units_per_row * height as usize {
+        return invalid_format("insufficient bits");
+    }
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let unit = values[y as usize * units_per_row + x as usize / unit_bits];
+            let set = (unit >> (x as usize % unit_bits)) & 1 == 1;
+            image.write(x, y, if set { FOREGROUND } else { BACKGROUND });
+        }
+    }
+    Ok(Decoded::Gray(image))
+}
+
+/// Write an image as an XBM file, using `name` as the prefix for identifiers
+///
+/// Dark, opaque pixels become set bits, and everything else is left unset.
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a>>>(
+    writer: &mut W,
+    image: I,
+    name: &str,
+) -> io::Result<()> {
+    let image = image.into();
+    writeln!(writer, "#define {}_width {}", name, image.width)?;
+    writeln!(writer, "#define {}_height {}", name, image.height)?;
+    writeln!(writer, "static unsigned char {}_bits[] = {{", name)?;
+    let mut bytes = Vec::new();
+    for y in 0..image.height {
+        for x_start in (0..image.width).step_by(8) {
+            let mut byte = 0u8;
+            for x in x_start..image.width.min(x_start + 8) {
+                let p = image.read(x, y);
+                let luma =
+                    (u32::from(p.r) * 299 + u32::from(p.g) * 587 + u32::from(p.b) * 114) / 1000;
+                if p.a >= 0x80 && luma < 0x80 {
+                    byte |= 1 << (x - x_start);
+                }
+            }
+            bytes.push(format!("0x{:02x}", byte));
+        }
+    }
+    for (i, line) in bytes.chunks(12).enumerate() {
+        let separator = if (i + 1) * 12 < bytes.len() { "," } else { "" };
+        writeln!(writer, "   {}{}", line.join(", "), separator)?;
+    }
+    writeln!(writer, "}};")
+}
+
+/// Reads and writes X bitmaps, which are monochrome images written as C
+pub struct XBMCodec;
+
+impl ImageDecoder for XBMCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_native(data, &options.limits).map_err(MageError::from)
+    }
+}
+
+impl ImageEncoder for XBMCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        options: &EncodeOptions,
+    ) -> MageResult<()> {
+        write_image(
+            &mut writer,
+            &image.into_low(options.exposure),
+            &options.name,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::RGBA;
+
+    const SET: RGBA = RGBA {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0xFF,
+    };
+    const UNSET: RGBA = RGBA {
+        r: 0xFF,
+        g: 0xFF,
+        b: 0xFF,
+        a: 0xFF,
+    };
+
+    #[test]
+    fn test_parse() {
+        let data = b"#define arrow_width 10
+#define arrow_height 2
+static unsigned char arrow_bits[] = {
+   0x01, 0x02, 0x00, 0x0a, };
+";
+        let image = parse_image(data).unwrap();
+        assert_eq!((image.width, image.height), (10, 2));
+        assert_eq!(image.read(0, 0), SET);
+        assert_eq!(image.read(1, 0), UNSET);
+        assert_eq!(image.read(9, 0), SET);
+        assert_eq!(image.read(0, 1), UNSET);
+        assert_eq!(image.read(9, 1), SET);
+        let x10 = b"#define old_width 3
+#define old_height 1
+static short old_bits[] = { 0x0005 };
+";
+        match parse_native(x10, &Limits::default()).unwrap() {
+            Decoded::Gray(image) => assert_eq!(image.read(2, 0), FOREGROUND),
+            _ => panic!("bitmap wasn't decoded as grayscale"),
+        }
+        // Enough room for the grayscale pixels, but not for RGBA
+        let limits = Limits {
+            max_alloc: 3,
+            ..Limits::default()
+        };
+        assert!(parse_native(x10, &limits).is_ok());
+        assert!(matches!(
+            parse_image_with(x10, &limits),
+            Err(XBMError::LimitsExceeded(_))
+        ));
+        assert!(parse_image(&data[..80]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = Image::new(13, 3);
+        for y in 0..3 {
+            for x in 0..13 {
+                let dark = (x + y) % 3 == 0;
+                image.write(x, y, if dark { SET } else { UNSET });
+            }
+        }
+        let mut data = Vec::new();
+        write_image(&mut data, &image, "test").unwrap();
+        assert!(data.starts_with(b"#define test_width 13\n"));
+        let read = parse_image(&data).unwrap();
+        assert!(read.into_iter().eq(&image));
+    }
+}
@@ -0,0 +1,459 @@
+use crate::codec::{DecodeOptions, Decoded, EncodeOptions, ImageDecoder, ImageEncoder};
+use crate::error::{MageError, MageResult};
+use crate::image::{Image, ImageView, RGBA_BYTES, RGBA};
+use crate::limits::{LimitError, Limits};
+use std::collections::HashMap;
+use std::io;
+// XPM files are C source code, holding an array of strings. The first string
+// has the dimensions, followed by one string per color, and one per row.
+// This follows the XPM3 format, described in the XPM Manual:
+// https://www.x.org/docs/XPM/xpm.pdf
+
+/// Represents the errors we can encounter when reading an XPM file
+#[derive(Debug)]
+pub enum XPMError {
+    /// The format of the file doesn't match the specification
+    InvalidFormat(String),
+    /// The format of the file is valid, but we don't support it
+    ///
+    /// This happens with colors we don't know the name of, or that are
+    /// given in HSV, instead of RGB.
+    UnsupportedFormat(String),
+    /// The file is larger than the limits we've been given
+    LimitsExceeded(LimitError),
+}
+
+pub type XPMResult<T> = Result<T, XPMError>;
+
+impl From<XPMError> for MageError {
+    fn from(error: XPMError) -> Self {
+        match error {
+            XPMError::InvalidFormat(message) => MageError::invalid("xpm", message),
+            XPMError::UnsupportedFormat(message) => MageError::unsupported("xpm", message),
+            XPMError::LimitsExceeded(e) => MageError::from(e),
+        }
+    }
+}
+
+impl From<LimitError> for XPMError {
+    fn from(error: LimitError) -> Self {
+        XPMError::LimitsExceeded(error)
+    }
+}
+
+fn invalid_format<T, S: Into<String>>(s: S) -> XPMResult<T> {
+    Err(XPMError::InvalidFormat(s.into()))
+}
+
+fn unsupported_format<T, S: Into<String>>(s: S) -> XPMResult<T> {
+    Err(XPMError::UnsupportedFormat(s.into()))
+}
+
+/// The named colors of the X11 color database that we know about
+///
+/// Names are stored in lower case, without spaces. The numbered shades
+/// of gray are handled separately.
+#[rustfmt::skip]
+const NAMED_COLORS: [(&str, [u8; 3]); 147] = [
+    ("aliceblue", [240, 248, 255]), ("antiquewhite", [250, 235, 215]), ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]), ("azure", [240, 255, 255]), ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]), ("black", [0, 0, 0]), ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]), ("blueviolet", [138, 43, 226]), ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]), ("cadetblue", [95, 158, 160]), ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]), ("coral", [255, 127, 80]), ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]), ("crimson", [220, 20, 60]), ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]), ("darkcyan", [0, 139, 139]), ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]), ("darkgreen", [0, 100, 0]), ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]), ("darkmagenta", [139, 0, 139]), ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]), ("darkorchid", [153, 50, 204]), ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]), ("darkseagreen", [143, 188, 143]), ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]), ("darkslategrey", [47, 79, 79]), ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]), ("deeppink", [255, 20, 147]), ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]), ("dimgrey", [105, 105, 105]), ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]), ("floralwhite", [255, 250, 240]), ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]), ("gainsboro", [220, 220, 220]), ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]), ("goldenrod", [218, 165, 32]), ("gray", [190, 190, 190]),
+    ("green", [0, 255, 0]), ("greenyellow", [173, 255, 47]), ("grey", [190, 190, 190]),
+    ("honeydew", [240, 255, 240]), ("hotpink", [255, 105, 180]), ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]), ("ivory", [255, 255, 240]), ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]), ("lavenderblush", [255, 240, 245]), ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]), ("lightblue", [173, 216, 230]), ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]), ("lightgoldenrodyellow", [250, 250, 210]), ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]), ("lightgrey", [211, 211, 211]), ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]), ("lightseagreen", [32, 178, 170]), ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]), ("lightslategrey", [119, 136, 153]), ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]), ("lime", [0, 255, 0]), ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]), ("magenta", [255, 0, 255]), ("maroon", [176, 48, 96]),
+    ("mediumaquamarine", [102, 205, 170]), ("mediumblue", [0, 0, 205]), ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]), ("mediumseagreen", [60, 179, 113]), ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]), ("mediumturquoise", [72, 209, 204]), ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]), ("mintcream", [245, 255, 250]), ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]), ("navajowhite", [255, 222, 173]), ("navy", [0, 0, 128]),
+    ("navyblue", [0, 0, 128]), ("oldlace", [253, 245, 230]), ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]), ("orange", [255, 165, 0]), ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]), ("palegoldenrod", [238, 232, 170]), ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]), ("palevioletred", [219, 112, 147]), ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]), ("peru", [205, 133, 63]), ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]), ("powderblue", [176, 224, 230]), ("purple", [160, 32, 240]),
+    ("red", [255, 0, 0]), ("rosybrown", [188, 143, 143]), ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]), ("salmon", [250, 128, 114]), ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]), ("seashell", [255, 245, 238]), ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]), ("skyblue", [135, 206, 235]), ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]), ("slategrey", [112, 128, 144]), ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]), ("steelblue", [70, 130, 180]), ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]), ("thistle", [216, 191, 216]), ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]), ("violet", [238, 130, 238]), ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]), ("whitesmoke", [245, 245, 245]), ("yellow", [255, 255, 0]),
+];
+
+/// Look up a color by name, ignoring case and spaces
+fn named_color(name: &str) -> Option<RGBA> {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    // gray0 through gray100 go from black to white
+    let shade = name
+        .strip_prefix("gray")
+        .or_else(|| name.strip_prefix("grey"))
+        .and_then(|n| n.parse::<u32>().ok());
+    if let Some(shade) = shade {
+        if shade <= 100 {
+            let v = ((shade * 255 + 50) / 100) as u8;
+            return Some(RGBA::new(v, v, v, 0xFF));
+        }
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, [r, g, b])| RGBA::new(*r, *g, *b, 0xFF))
+}
+
+/// Parse a color given in hexadecimal, like `#FF0000`
+///
+/// Each component can have between 1 and 4 digits, and we keep the most
+/// significant 8 bits of each.
+fn hex_color(hex: &str) -> Option<RGBA> {
+    if !hex.is_ascii() || hex.is_empty() || !hex.len().is_multiple_of(3) || hex.len() > 12 {
+        return None;
+    }
+    let digits = hex.len() / 3;
+    let mut components = [0u8; 3];
+    for (i, component) in components.iter_mut().enumerate() {
+        let value = u32::from_str_radix(&hex[i * digits..(i + 1) * digits], 16).ok()?;
+        let bits = 4 * digits as u32;
+        *component = if bits >= 8 {
+            (value >> (bits - 8)) as u8
+        } else {
+            // A single digit is repeated, so that F becomes FF
+            (value * 0x11) as u8
+        };
+    }
+    Some(RGBA::new(components[0], components[1], components[2], 0xFF))
+}
+
+fn parse_color(value: &str) -> XPMResult<RGBA> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(RGBA::new(0, 0, 0, 0));
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        return match hex_color(hex) {
+            Some(color) => Ok(color),
+            None => invalid_format(format!("bad hex color '{}'", value)),
+        };
+    }
+    if value.starts_with('%') {
+        return unsupported_format("HSV colors are not supported");
+    }
+    match named_color(value) {
+        Some(color) => Ok(color),
+        None => unsupported_format(format!("unknown color '{}'", value)),
+    }
+}
+
+/// Pull out every string literal in the file, skipping comments
+fn string_literals(text: &str) -> XPMResult<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => last = c,
+                        None => return invalid_format("unterminated comment"),
+                    }
+                }
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => s.push(c),
+                            None => return invalid_format("unterminated string"),
+                        },
+                        Some(c) => s.push(c),
+                        None => return invalid_format("unterminated string"),
+                    }
+                }
+                strings.push(s);
+            }
+            _ => {}
+        }
+    }
+    Ok(strings)
+}
+
+/// Parse the part of a color definition after the pixel characters
+///
+/// This is a list of pairs like `c red` or `m black`, where the color itself
+/// can contain spaces. We prefer the color visual, falling back to grayscale
+/// and then monochrome.
+fn parse_color_definition(definition: &str) -> XPMResult<RGBA> {
+    let mut values: Vec<(&str, String)> = Vec::new();
+    for token in definition.split_whitespace() {
+        match token {
+            "c" | "m" | "g" | "g4" | "s" => values.push((token, String::new())),
+            _ => match values.last_mut() {
+                Some((_, value)) => {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(token);
+                }
+                None => return invalid_format("color value without a key"),
+            },
+        }
+    }
+    for key in &["c", "g", "g4", "m"] {
+        if let Some((_, value)) = values.iter().find(|(k, _)| k == key) {
+            return parse_color(value);
+        }
+    }
+    unsupported_format("color only has a symbolic name")
+}
+
+pub fn parse_image(data: &[u8]) -> XPMResult<Image> {
+    parse_image_with(data, &Limits::default())
+}
+
+/// Parse an image, refusing any that go over the limits
+pub fn parse_image_with(data: &[u8], limits: &Limits) -> XPMResult<Image> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return invalid_format("file isn't valid text"),
+    };
+    if !text.trim_start().starts_with("/* XPM */") {
+        return invalid_format("file didn't start with '/* XPM */'");
+    }
+    let strings = string_literals(text)?;
+    let values: Vec<usize> = match strings.first() {
+        Some(s) => s
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect(),
+        None => return invalid_format("missing values"),
+    };
+    if values.len() < 4 {
+        return invalid_format("malformed values");
+    }
+    let (width, height, color_count, cpp) = (values[0], values[1], values[2], values[3]);
+    if cpp == 0 {
+        return invalid_format("zero characters per pixel");
+    }
+    limits.check_image(width as u64, height as u64, RGBA_BYTES as u64)?;
+    let row_chars = match width.checked_mul(cpp) {
+        Some(n) => n,
+        None => return invalid_format("row length overflows"),
+    };
+    if color_count >= strings.len() || height > strings.len() - 1 - color_count {
+        return invalid_format("insufficient strings");
+    }
+    let mut colors = HashMap::new();
+    for definition in &strings[1..=color_count] {
+        let key: String = definition.chars().take(cpp).collect();
+        let rest: String = definition.chars().skip(cpp).collect();
+        if key.chars().count() != cpp {
+            return invalid_format("color definition too short");
+        }
+        colors.insert(key, parse_color_definition(&rest)?);
+    }
+    let mut image = Image::new(width as u32, height as u32);
+    for (y, row) in strings[1 + color_count..1 + color_count + height]
+        .iter()
+        .enumerate()
+    {
+        let chars: Vec<char> = row.chars().collect();
+        if chars.len() < row_chars {
+            return invalid_format("row too short");
+        }
+        for x in 0..width {
+            let key: String = chars[x * cpp..(x + 1) * cpp].iter().collect();
+            match colors.get(&key) {
+                Some(&color) => image.write(x as u32, y as u32, color),
+                None => return invalid_format(format!("unknown pixel '{}'", key)),
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// The characters we use to name colors, which don't need escaping
+fn pixel_chars() -> Vec<char> {
+    (b' '..=b'~')
+        .filter(|&c| c != b'"' && c != b'\\')
+        .map(char::from)
+        .collect()
+}
+
+/// Write an image as an XPM file, using `name` for the array
+///
+/// Pixels that are more than half transparent become `None`.
+pub fn write_image<'a, W: io::Write, I: Into<ImageView<'a>>>(
+    writer: &mut W,
+    image: I,
+    name: &str,
+) -> io::Result<()> {
+    let image = image.into();
+    let key = |p: RGBA| {
+        if p.a < 0x80 {
+            None
+        } else {
+            Some((p.r, p.g, p.b))
+        }
+    };
+    let mut palette = Vec::new();
+    let mut indices = HashMap::new();
+    for pixel in image {
+        let k = key(pixel);
+        indices.entry(k).or_insert_with(|| {
+            palette.push(k);
+            palette.len() - 1
+        });
+    }
+    let alphabet = pixel_chars();
+    let mut cpp = 1;
+    while alphabet.len().pow(cpp as u32) < palette.len() {
+        cpp += 1;
+    }
+    let code = |mut index: usize| {
+        let mut s = String::new();
+        for _ in 0..cpp {
+            s.push(alphabet[index % alphabet.len()]);
+            index /= alphabet.len();
+        }
+        s
+    };
+    writeln!(writer, "/* XPM */")?;
+    writeln!(writer, "static char *{}[] = {{", name)?;
+    writeln!(
+        writer,
+        "\"{} {} {} {}\",",
+        image.width,
+        image.height,
+        palette.len(),
+        cpp
+    )?;
+    for (i, color) in palette.iter().enumerate() {
+        match color {
+            Some((r, g, b)) => writeln!(writer, "\"{} c #{:02X}{:02X}{:02X}\",", code(i), r, g, b)?,
+            None => writeln!(writer, "\"{} c None\",", code(i))?,
+        }
+    }
+    for y in 0..image.height {
+        let mut row = String::new();
+        for x in 0..image.width {
+            row.push_str(&code(indices[&key(image.read(x, y))]));
+        }
+        let separator = if y + 1 < image.height { "," } else { "" };
+        writeln!(writer, "\"{}\"{}", row, separator)?;
+    }
+    writeln!(writer, "}};")
+}
+
+/// Reads and writes X pixmaps, with palettes of named or hex colors
+pub struct XPMCodec;
+
+impl ImageDecoder for XPMCodec {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> MageResult<Decoded> {
+        parse_image_with(data, &options.limits)
+            .map(Decoded::Low)
+            .map_err(MageError::from)
+    }
+}
+
+impl ImageEncoder for XPMCodec {
+    fn encode(
+        &self,
+        mut writer: &mut dyn io::Write,
+        image: Decoded,
+        options: &EncodeOptions,
+    ) -> MageResult<()> {
+        write_image(
+            &mut writer,
+            &image.into_low(options.exposure),
+            &options.name,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let data = br#"/* XPM */
+static char * icon[] = {
+/* width height colors cpp */
+"3 2 3 1",
+"  c None",
+". c light blue",
+"X c #F00",
+" .X",
+"X. "};
+"#;
+        let image = parse_image(data).unwrap();
+        assert_eq!(image.read(0, 0).a, 0);
+        assert_eq!(image.read(1, 0), RGBA::new(173, 216, 230, 0xFF));
+        assert_eq!(image.read(2, 0), RGBA::new(0xFF, 0, 0, 0xFF));
+        assert_eq!(image.read(0, 1), RGBA::new(0xFF, 0, 0, 0xFF));
+    }
+
+    #[test]
+    fn test_oversized_header() {
+        for header in &["1 1 18446744073709551615 1", "1 18446744073709551615 1 1"] {
+            let data = format!("/* XPM */\n\"{}\",\n\". c #000\",\n\".\"", header);
+            match parse_image(data.as_bytes()) {
+                Err(XPMError::InvalidFormat(_)) => {}
+                Err(XPMError::LimitsExceeded(_)) => {}
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = Image::new(12, 10);
+        for y in 0..10 {
+            for x in 0..12 {
+                image.write(x, y, RGBA::new((x * 20) as u8, (y * 20) as u8, 7, 0xFF));
+            }
+        }
+        let mut data = Vec::new();
+        write_image(&mut data, &image, "test").unwrap();
+        let read = parse_image(&data).unwrap();
+        for y in 0..10 {
+            for x in 0..12 {
+                assert_eq!(read.read(x, y), image.read(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use crate::huffman::{BitReader, Huffman};
+// This implements the zlib container (RFC 1950) around DEFLATE (RFC 1951).
+// Decompression handles every kind of block, but compression only produces
+// blocks using the fixed prefix codes, which keeps the encoder small.
+
+/// The base length for each length symbol, starting at 257
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// How many extra bits follow each length symbol
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The base distance for each distance symbol
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// How many extra bits follow each distance symbol
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub type ZlibResult<T> = Result<T, &'static str>;
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 is the most bytes we can sum before b might overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+fn bits(reader: &mut BitReader, n: u32) -> ZlibResult<u32> {
+    reader.read_bits(n).ok_or("unexpected end of data")
+}
+
+fn symbol(reader: &mut BitReader, code: &Huffman) -> ZlibResult<u16> {
+    code.decode(reader).ok_or("invalid prefix code")
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    // These are fixed, so they can't be over-subscribed
+    let literal = Huffman::new(&lengths).unwrap();
+    let distance = Huffman::new(&[5; 30]).unwrap();
+    (literal, distance)
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> ZlibResult<(Huffman, Huffman)> {
+    let literal_count = bits(reader, 5)? as usize + 257;
+    let distance_count = bits(reader, 5)? as usize + 1;
+    let code_length_count = bits(reader, 4)? as usize + 4;
+    let mut code_length_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[i] = bits(reader, 3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_length_lengths).ok_or("invalid code length code")?;
+    let total = literal_count + distance_count;
+    let mut lengths = vec![0u8; total];
+    let mut i = 0;
+    while i < total {
+        let code = symbol(reader, &code_length_code)?;
+        if code < 16 {
+            lengths[i] = code as u8;
+            i += 1;
+            continue;
+        }
+        let (value, repeat) = match code {
+            16 if i > 0 => (lengths[i - 1], 3 + bits(reader, 2)?),
+            16 => return Err("repeated code length with no previous length"),
+            17 => (0, 3 + bits(reader, 3)?),
+            _ => (0, 11 + bits(reader, 7)?),
+        };
+        let repeat = repeat as usize;
+        if i + repeat > total {
+            return Err("code lengths repeat past the end");
+        }
+        for length in &mut lengths[i..i + repeat] {
+            *length = value;
+        }
+        i += repeat;
+    }
+    let literal = Huffman::new(&lengths[..literal_count]).ok_or("invalid literal code")?;
+    let distance = Huffman::new(&lengths[literal_count..]).ok_or("invalid distance code")?;
+    Ok((literal, distance))
+}
+
+/// The error for data that inflates to more than the caller expects
+const TOO_LONG: &str = "data decompresses to more than the expected size";
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    max_out: usize,
+    literal: &Huffman,
+    distance: &Huffman,
+) -> ZlibResult<()> {
+    loop {
+        let sym = symbol(reader, literal)? as usize;
+        if sym < 256 {
+            if out.len() >= max_out {
+                return Err(TOO_LONG);
+            }
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(());
+        }
+        let sym = sym - 257;
+        if sym >= LENGTH_BASE.len() {
+            return Err("invalid length symbol");
+        }
+        let length = LENGTH_BASE[sym] as usize + bits(reader, LENGTH_EXTRA[sym].into())? as usize;
+        let sym = symbol(reader, distance)? as usize;
+        if sym >= DISTANCE_BASE.len() {
+            return Err("invalid distance symbol");
+        }
+        let dist = DISTANCE_BASE[sym] as usize + bits(reader, DISTANCE_EXTRA[sym].into())? as usize;
+        if dist > out.len() {
+            return Err("distance before the start of the data");
+        }
+        if length > max_out - out.len() {
+            return Err(TOO_LONG);
+        }
+        let start = out.len() - dist;
+        // The copy can overlap with what it produces, so we go byte by byte
+        for i in 0..length {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+}
+
+/// Decompress data held in a zlib container
+///
+/// This fails as soon as the output grows past `max_out` bytes, so that a
+/// small amount of data can't inflate into more memory than we can spare.
+pub fn decompress(data: &[u8], max_out: usize) -> ZlibResult<Vec<u8>> {
+    if data.len() < 6 {
+        return Err("insufficient zlib data");
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0F != 8 {
+        return Err("compression method isn't deflate");
+    }
+    if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return Err("bad zlib header check");
+    }
+    if flg & 0x20 != 0 {
+        return Err("preset dictionaries are not supported");
+    }
+    let mut reader = BitReader::new(&data[2..]);
+    let mut out = Vec::new();
+    loop {
+        let last = bits(&mut reader, 1)? == 1;
+        match bits(&mut reader, 2)? {
+            0 => {
+                reader.align_to_byte();
+                let len = bits(&mut reader, 16)?;
+                let nlen = bits(&mut reader, 16)?;
+                if len != !nlen & 0xFFFF {
+                    return Err("stored block length mismatch");
+                }
+                if len as usize > max_out - out.len() {
+                    return Err(TOO_LONG);
+                }
+                for _ in 0..len {
+                    out.push(bits(&mut reader, 8)? as u8);
+                }
+            }
+            1 => {
+                let (literal, distance) = fixed_codes();
+                inflate_block(&mut reader, &mut out, max_out, &literal, &distance)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, max_out, &literal, &distance)?;
+            }
+            _ => return Err("invalid block type"),
+        }
+        if last {
+            break;
+        }
+    }
+    reader.align_to_byte();
+    let mut checksum = 0;
+    for _ in 0..4 {
+        checksum = checksum << 8 | bits(&mut reader, 8)?;
+    }
+    if checksum != adler32(&out) {
+        return Err("checksum mismatch");
+    }
+    Ok(out)
+}
+
+/// Writes bits into bytes, least significant bit first
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, n: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a prefix code, which goes in starting from its highest bit
+    fn write_code(&mut self, code: u32, n: u32) {
+        let reversed = code.reverse_bits() >> (32 - n);
+        self.write_bits(reversed, n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, dist: usize) {
+    let sym = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= length)
+        .unwrap();
+    write_literal(writer, 257 + sym as u32);
+    let extra = (length - LENGTH_BASE[sym] as usize) as u32;
+    writer.write_bits(extra, LENGTH_EXTRA[sym].into());
+    let sym = DISTANCE_BASE
+        .iter()
+        .rposition(|&b| b as usize <= dist)
+        .unwrap();
+    writer.write_code(sym as u32, 5);
+    let extra = (dist - DISTANCE_BASE[sym] as usize) as u32;
+    writer.write_bits(extra, DISTANCE_EXTRA[sym].into());
+}
+
+const WINDOW_SIZE: usize = 1 << 15;
+const HASH_BITS: u32 = 15;
+const MAX_MATCH: usize = 258;
+const MIN_MATCH: usize = 3;
+/// How many earlier positions we try before settling for the best match
+const MAX_CHAIN: usize = 64;
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from(data[0]) << 16 | u32::from(data[1]) << 8 | u32::from(data[2]);
+    (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Remember position `i`, so that later data can refer back to it
+fn insert(data: &[u8], i: usize, head: &mut [usize], previous: &mut [usize]) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash(&data[i..]);
+        previous[i % WINDOW_SIZE] = head[h];
+        head[h] = i;
+    }
+}
+
+/// Compress data into a zlib container
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // A single final block, using the fixed codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+    // The most recent position with each hash, and the one before each position
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_length = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(&data[i..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_dist = i - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = previous[candidate % WINDOW_SIZE];
+                // Entries get overwritten as the window slides along
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_dist);
+            for j in i..i + best_length {
+                insert(data, j, &mut head, &mut previous);
+            }
+            i += best_length;
+        } else {
+            write_literal(&mut writer, u32::from(data[i]));
+            insert(data, i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+    write_literal(&mut writer, 256);
+    let mut out = vec![0x78, 0x01];
+    out.extend(writer.finish());
+    out.extend(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut data = Vec::new();
+        for i in 0..5000u32 {
+            data.push((i % 7) as u8);
+            data.push((i * i % 251) as u8);
+        }
+        let compressed = compress(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        assert_eq!(decompress(&compressed, data.len() - 1), Err(TOO_LONG));
+    }
+
+    #[test]
+    fn test_stored_block() {
+        // "abc", stored without compression
+        let data = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xFC, 0xFF, 0x61, 0x62, 0x63, 0x02, 0x4D, 0x01, 0x27,
+        ];
+        assert_eq!(decompress(&data, 3).unwrap(), b"abc");
+        assert_eq!(decompress(&data, 2), Err(TOO_LONG));
+    }
+}